@@ -0,0 +1,14 @@
+use std::{env, path::PathBuf};
+use underrail_fix::bench;
+
+fn main() {
+	match env::var("UNDERRAIL_BENCH_DIR") {
+		Ok(path) => {
+			match bench::run(PathBuf::from(path)) {
+				Ok(report) => print!("{}", report),
+				Err(e) => println!("failed to open the archive: {}", e)
+			}
+		}
+		Err(_) => println!("UNDERRAIL_BENCH_DIR not set, skipping read benchmark")
+	}
+}
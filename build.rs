@@ -1,5 +1,115 @@
+use std::{
+	env,
+	process::Command,
+	time::{SystemTime, UNIX_EPOCH}
+};
+
+const SYSTEM32: &str = r"C:\Windows\System32";
+
+const VERSION_EXPORTS: &[&str] = &[
+	"GetFileVersionInfoA",
+	"GetFileVersionInfoByHandle",
+	"GetFileVersionInfoExA",
+	"GetFileVersionInfoExW",
+	"GetFileVersionInfoSizeA",
+	"GetFileVersionInfoSizeExA",
+	"GetFileVersionInfoSizeExW",
+	"GetFileVersionInfoSizeW",
+	"GetFileVersionInfoW",
+	"VerFindFileA",
+	"VerFindFileW",
+	"VerInstallFileA",
+	"VerInstallFileW",
+	"VerLanguageNameA",
+	"VerLanguageNameW",
+	"VerQueryValueA",
+	"VerQueryValueW"
+];
+
+const WINMM_EXPORTS: &[&str] = &[
+	"PlaySoundA",
+	"PlaySoundW",
+	"joyGetDevCapsW",
+	"joyGetNumDevs",
+	"joyGetPos",
+	"joyGetPosEx",
+	"mciGetErrorStringW",
+	"mciSendCommandW",
+	"mciSendStringW",
+	"midiOutClose",
+	"midiOutGetNumDevs",
+	"midiOutOpen",
+	"midiOutReset",
+	"midiOutShortMsg",
+	"mixerClose",
+	"mixerGetControlDetailsW",
+	"mixerGetDevCapsW",
+	"mixerGetLineControlsW",
+	"mixerGetLineInfoW",
+	"mixerGetNumDevs",
+	"mixerOpen",
+	"mixerSetControlDetails",
+	"mmioAscend",
+	"mmioClose",
+	"mmioDescend",
+	"mmioOpenW",
+	"mmioRead",
+	"mmioSeek",
+	"sndPlaySoundW",
+	"timeBeginPeriod",
+	"timeEndPeriod",
+	"timeGetDevCaps",
+	"timeGetTime",
+	"timeKillEvent",
+	"timeSetEvent",
+	"waveInAddBuffer",
+	"waveInClose",
+	"waveInGetDevCapsW",
+	"waveInGetNumDevs",
+	"waveInOpen",
+	"waveInPrepareHeader",
+	"waveInReset",
+	"waveInStart",
+	"waveInStop",
+	"waveInUnprepareHeader",
+	"waveOutClose",
+	"waveOutGetDevCapsW",
+	"waveOutGetNumDevs",
+	"waveOutGetPosition",
+	"waveOutGetVolume",
+	"waveOutOpen",
+	"waveOutPause",
+	"waveOutPrepareHeader",
+	"waveOutReset",
+	"waveOutRestart",
+	"waveOutSetVolume",
+	"waveOutUnprepareHeader",
+	"waveOutWrite"
+];
+
 fn main() {
-	build();
+	println!("cargo:rerun-if-changed=Detours/src");
+	println!("cargo:rerun-if-env-changed=DETOURS_LIB_DIR");
+
+	build_info();
+
+	// the DLL and its hooks are Windows only, elsewhere just the packer is built
+	if env::var("CARGO_CFG_TARGET_OS").map_or(true, |os| os != "windows") {
+		return;
+	}
+
+	if env::var_os("CARGO_FEATURE_PROXY").is_some() {
+		proxy();
+	}
+
+	if env::var_os("CARGO_FEATURE_MINHOOK").is_some() {
+		return;
+	}
+
+	match env::var("DETOURS_LIB_DIR") {
+		Ok(dir) => println!("cargo:rustc-link-search=native={}", dir),
+		Err(_) => build()
+	}
 }
 
 fn build() {
@@ -22,3 +132,49 @@ fn build() {
 		.file("Detours/src/creatwth.cpp")
 		.compile("detours");
 }
+
+fn build_info() {
+	let hash = Command::new("git")
+		.args(&["rev-parse", "--short", "HEAD"])
+		.output()
+		.ok()
+		.filter(|o| o.status.success())
+		.map_or_else(
+			|| "unknown".to_owned(),
+			|o| String::from_utf8_lossy(&o.stdout).trim().to_owned()
+		);
+
+	let days = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap()
+		.as_secs() as i64
+		/ 86400;
+
+	// civil date from days since 1970-01-01
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+	println!("cargo:rustc-env=UNDERRAIL_FIX_GIT_HASH={}", hash);
+	println!(
+		"cargo:rustc-env=UNDERRAIL_FIX_BUILD_DATE={:04}-{:02}-{:02}",
+		year, month, day
+	);
+}
+
+fn proxy() {
+	for (dll, exports) in &[("version", VERSION_EXPORTS), ("winmm", WINMM_EXPORTS)] {
+		for export in *exports {
+			println!(
+				"cargo:rustc-cdylib-link-arg=/EXPORT:{}={}\\{}.{}",
+				export, SYSTEM32, dll, export
+			);
+		}
+	}
+}
@@ -0,0 +1,169 @@
+use crate::vfs::{self, Entry, Vfs};
+use std::{
+	fmt::{self, Display, Formatter},
+	fs::File,
+	io::{self, Read, Seek, SeekFrom},
+	path::{Path, PathBuf},
+	time::{Duration, Instant}
+};
+
+const SEQUENTIAL_LIMIT: u64 = 256 << 20;
+const RANDOM_READS: usize = 20000;
+const RANDOM_READ_LEN: usize = 4096;
+
+pub struct Report {
+	files: usize,
+	sequential: (Throughput, Throughput),
+	random: (Throughput, Throughput)
+}
+
+struct Throughput {
+	bytes: u64,
+	elapsed: Duration
+}
+
+struct Loose;
+
+struct Rng(u64);
+
+impl Rng {
+	fn next(&mut self, n: u64) -> u64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		self.0 % n
+	}
+}
+
+trait Source {
+	fn open(&self, path: &Path) -> Box<dyn ReadSeek>;
+}
+
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+impl Source for Vfs {
+	fn open(&self, path: &Path) -> Box<dyn ReadSeek> {
+		Box::new(self.read(path).unwrap().expect("file missing from VFS"))
+	}
+}
+
+impl Source for Loose {
+	fn open(&self, path: &Path) -> Box<dyn ReadSeek> {
+		Box::new(File::open(&path).expect(&format!("failed to open file: {}", path.display())))
+	}
+}
+
+impl Throughput {
+	fn mib_per_sec(&self) -> f64 {
+		self.bytes as f64 / 1024.0 / 1024.0 / self.elapsed.as_secs_f64()
+	}
+}
+
+impl Display for Throughput {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{:9.1} MiB/s ({} bytes in {:.3}s)",
+			self.mib_per_sec(),
+			self.bytes,
+			self.elapsed.as_secs_f64()
+		)
+	}
+}
+
+impl Display for Report {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		let row = |f: &mut Formatter, name, (vfs, loose): &(Throughput, Throughput)| {
+			writeln!(f, "{} vfs:   {}", name, vfs)?;
+			writeln!(f, "{} loose: {}", name, loose)?;
+			writeln!(
+				f,
+				"{} ratio: {:9.2}x",
+				name,
+				vfs.mib_per_sec() / loose.mib_per_sec()
+			)
+		};
+
+		writeln!(f, "files: {}", self.files)?;
+		row(f, "sequential", &self.sequential)?;
+		row(f, "random    ", &self.random)
+	}
+}
+
+pub fn run(path: PathBuf) -> io::Result<Report> {
+	let data = path.join(vfs::data_dir());
+	let vfs = Vfs::open(path)?;
+
+	let files: Vec<_> = vfs
+		.find_recursive(&data.join("**"))
+		.expect("data path outside VFS")
+		.into_iter()
+		.filter_map(|(path, entry)| {
+			match *entry {
+				Entry::File { len, .. } if len > 0 => Some((data.join(path), len)),
+				_ => None
+			}
+		})
+		.collect();
+
+	assert!(!files.is_empty(), "no files in VFS");
+
+	let sequential = |source: &dyn Source| {
+		let mut buf = vec![0; 1 << 16];
+		let mut bytes = 0;
+		let instant = Instant::now();
+
+		for (path, _) in &files {
+			let mut file = source.open(path);
+
+			loop {
+				let read = file.read(&mut buf).expect("failed to read file");
+
+				if read == 0 {
+					break;
+				}
+
+				bytes += read as u64;
+			}
+
+			if bytes >= SEQUENTIAL_LIMIT {
+				break;
+			}
+		}
+
+		Throughput {
+			bytes,
+			elapsed: instant.elapsed()
+		}
+	};
+
+	let random = |source: &dyn Source| {
+		let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+		let mut buf = [0; RANDOM_READ_LEN];
+		let mut bytes = 0;
+		let instant = Instant::now();
+
+		for _ in 0..RANDOM_READS {
+			let (path, len) = &files[rng.next(files.len() as u64) as usize];
+			let mut file = source.open(path);
+
+			file.seek(SeekFrom::Start(rng.next(*len)))
+				.expect("failed to seek file");
+
+			bytes += file.read(&mut buf).expect("failed to read file") as u64;
+		}
+
+		Throughput {
+			bytes,
+			elapsed: instant.elapsed()
+		}
+	};
+
+	Ok(Report {
+		files: files.len(),
+		sequential: (sequential(&vfs), sequential(&Loose)),
+		random: (random(&vfs), random(&Loose))
+	})
+}
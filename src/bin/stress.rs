@@ -0,0 +1,11 @@
+// hammers the Fixer handle tables from several threads, run with --features stress
+#[cfg(windows)]
+fn main() {
+	underrail_fix::stress::run();
+}
+
+#[cfg(not(windows))]
+fn main() {
+	eprintln!("the stress test drives the hooks, which only exist on Windows");
+	std::process::exit(1);
+}
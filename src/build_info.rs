@@ -0,0 +1,19 @@
+pub fn build_info() -> String {
+	let features: Vec<_> = [
+		("minhook", cfg!(feature = "minhook")),
+		("no-console", cfg!(feature = "no-console")),
+		("proxy", cfg!(feature = "proxy"))
+	]
+	.iter()
+	.filter(|(_, enabled)| *enabled)
+	.map(|(name, _)| *name)
+	.collect();
+
+	format!(
+		"underrail-fix {} ({} {}) features: [{}]",
+		env!("CARGO_PKG_VERSION"),
+		env!("UNDERRAIL_FIX_GIT_HASH"),
+		env!("UNDERRAIL_FIX_BUILD_DATE"),
+		features.join(", ")
+	)
+}
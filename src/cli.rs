@@ -0,0 +1,1015 @@
+use crate::vfs::{self, Entry, Vfs};
+#[cfg(windows)]
+use crate::{
+	game, inject,
+	stats::Stats,
+	version::{self, Version}
+};
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use serde_json::{json, Value};
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	env,
+	fs::{self, File},
+	io::{self, Read, Write},
+	path::{Path, PathBuf, MAIN_SEPARATOR},
+	time::{SystemTime, UNIX_EPOCH}
+};
+#[cfg(windows)]
+use std::{ffi::OsString, io::BufRead};
+
+#[derive(Parser)]
+#[clap(
+	version,
+	about = "Packs Underrail's data into VFS archives and launches the game with underrail_fix.dll"
+)]
+pub struct Cli {
+	/// Game executable to launch (overrides UNDERRAIL_EXE)
+	#[clap(long, global = true)]
+	pub exe: Option<PathBuf>,
+	/// Profile from underrail-fix.toml selecting the archive and overlay set (overrides
+	/// UNDERRAIL_FIX_PROFILE)
+	#[clap(long, global = true)]
+	pub profile: Option<String>,
+	/// Install from the [installs] tables of underrail-fix.toml (overrides UNDERRAIL_FIX_INSTALL)
+	#[clap(long, global = true)]
+	pub install: Option<String>,
+	/// Log debug messages, twice for trace, in both the launcher and the game (overrides
+	/// UNDERRAIL_FIX_LOG)
+	#[clap(short, long, global = true, parse(from_occurrences))]
+	pub verbose: u8,
+	/// Only log warnings and errors, in both the launcher and the game (overrides
+	/// UNDERRAIL_FIX_LOG)
+	#[clap(short, long, global = true, conflicts_with = "verbose")]
+	pub quiet: bool,
+	/// Print machine-readable JSON from list, verify, info, stats, diff and doctor instead of text
+	#[clap(long, global = true)]
+	pub json: bool,
+	#[clap(subcommand)]
+	pub command: Option<Command>
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+	/// Pack the data directories into VFS archives if they changed
+	Pack {
+		/// Threads reading files while packing (overrides UNDERRAIL_FIX_PACK_THREADS)
+		#[clap(long)]
+		threads: Option<usize>,
+		/// Write buffer size in bytes (overrides UNDERRAIL_FIX_PACK_BUFFER_SIZE)
+		#[clap(long)]
+		buffer_size: Option<usize>,
+		/// Rebuild the archives even if the data directories look unchanged
+		#[clap(long)]
+		force: bool
+	},
+	/// Keep the archives up to date as the data directories change
+	#[cfg(windows)]
+	Watch,
+	/// Compare the archives against the loose files
+	Verify,
+	/// List archive entries matching a glob relative to each archived directory
+	List {
+		#[clap(default_value = "**")]
+		pattern: String
+	},
+	/// Write a single archived file to stdout, e.g. Data/rules/items.xml
+	Cat {
+		/// Path relative to the game directory
+		entry: PathBuf,
+		/// File to write instead of stdout
+		#[clap(long, short)]
+		output: Option<PathBuf>
+	},
+	/// Add or replace a single archived file without repacking, until the next repack
+	Put {
+		/// File to store
+		source: PathBuf,
+		/// Path relative to the game directory, e.g. Data/rules/items.xml
+		entry: PathBuf
+	},
+	/// Write every archive entry with its location, checksum and timestamps as CSV or JSON
+	DumpIndex {
+		/// File to write instead of stdout
+		#[clap(long, short)]
+		output: Option<PathBuf>,
+		#[clap(long, default_value = "csv", possible_values = &["csv", "json"])]
+		format: String,
+		/// Skip reading every file for its CRC-32
+		#[clap(long)]
+		no_hash: bool
+	},
+	/// Extract archive entries matching a glob into a directory
+	Extract {
+		dest: PathBuf,
+		#[clap(default_value = "**")]
+		pattern: String
+	},
+	/// Show archive sizes and entry counts
+	Info,
+	/// Show the file counters of a game running with the fix
+	#[cfg(windows)]
+	Stats {
+		/// Process id of the game
+		#[clap(long)]
+		pid: u32
+	},
+	/// Compare the archives against another game directory's, e.g. a backup or another version
+	Diff {
+		/// Game directory to compare against
+		other: PathBuf
+	},
+	/// Pack, then launch the game with the fix injected (the default)
+	#[cfg(windows)]
+	#[clap(trailing_var_arg = true)]
+	Run {
+		/// Skip the archive freshness check and launch straight away
+		#[clap(long)]
+		no_pack: bool,
+		/// Compare the archives against the loose files before launching
+		#[clap(long)]
+		verify_on_launch: bool,
+		/// Priority class to start the game with
+		#[clap(long, possible_values = crate::priority::PRIORITIES)]
+		priority: Option<String>,
+		/// Cores the game may run on, as a list like 0-7,16 or a hex mask like 0xff
+		#[clap(long)]
+		affinity: Option<String>,
+		/// Whether the game shares the launcher's console, gets a hidden one or none at all
+		#[clap(long, possible_values = crate::inject::CONSOLE_MODES)]
+		console: Option<String>,
+		/// Keep the launcher's console open after the game exits
+		#[clap(long)]
+		pause: bool,
+		/// Offer to kill the game if it touches no files for this many seconds while starting
+		#[clap(long)]
+		watchdog: Option<u64>,
+		/// Arguments passed through to the game
+		#[clap(allow_hyphen_values = true)]
+		args: Vec<OsString>
+	},
+	/// Inject into an already running game
+	#[cfg(windows)]
+	Attach {
+		#[clap(long)]
+		pid: u32
+	},
+	/// Replace the game exe with the launcher so every launch goes through the fix
+	#[cfg(windows)]
+	Install,
+	/// Restore the original game exe and remove the installed launcher and DLL
+	#[cfg(windows)]
+	Uninstall,
+	/// Create a Desktop shortcut that launches the game through the fix
+	#[cfg(windows)]
+	Shortcut {
+		/// Also add a Start Menu entry
+		#[clap(long)]
+		start_menu: bool
+	},
+	/// Print the Steam launch options that route the game through the fix
+	#[cfg(windows)]
+	SteamOptions {
+		/// Copy the launch options to the clipboard
+		#[clap(long)]
+		clipboard: bool
+	},
+	/// Record the game's VFS reads or replay them headlessly
+	#[cfg(windows)]
+	Trace {
+		#[clap(subcommand)]
+		command: TraceCommand
+	},
+	/// Manage translation packs layered over the game data
+	Lang {
+		#[clap(subcommand)]
+		command: LangCommand
+	},
+	/// Delete the VFS archives, logs, crash reports and temp files, returning to a vanilla install
+	Clean {
+		/// Only print what would be removed
+		#[clap(long)]
+		dry_run: bool
+	},
+	/// Check the setup for common problems and print how to fix them
+	#[cfg(windows)]
+	Doctor,
+	/// Pack natively, install the proxy DLL and print the Steam launch options for Proton
+	#[cfg(not(windows))]
+	Proton {
+		/// System DLL the proxy build stands in for
+		#[clap(long, default_value = "version", possible_values = &["version", "winmm"])]
+		proxy: String
+	},
+	/// Check GitHub for a newer release
+	#[cfg(feature = "update")]
+	CheckUpdate,
+	/// Compare VFS and loose file read throughput
+	#[cfg(windows)]
+	Bench {
+		/// Launch the game with and without the fix and time how long it takes to wait for input
+		#[clap(long)]
+		launch: bool,
+		/// Number of launches per configuration
+		#[clap(long, default_value = "3")]
+		runs: u32
+	}
+}
+
+#[cfg(windows)]
+#[derive(Subcommand)]
+pub enum TraceCommand {
+	/// Launch the game and record every read served from the archives
+	Record {
+		/// Where to write the recording (defaults to the game directory)
+		#[clap(long)]
+		output: Option<PathBuf>
+	},
+	/// Re-run a recording against the archives and loose files and compare
+	Replay {
+		/// Recording to replay (defaults to the one in the game directory)
+		input: Option<PathBuf>
+	}
+}
+
+#[derive(Subcommand)]
+pub enum LangCommand {
+	/// Pack a directory laid out like Data into a translation pack
+	Install {
+		source: PathBuf,
+		/// Pack name, which also sets its priority (defaults to the directory name)
+		#[clap(long)]
+		name: Option<String>
+	},
+	/// Remove an installed translation pack
+	Remove { name: String },
+	/// List installed packs and which one supplies each conflicting file
+	List
+}
+
+// the injected DLL inherits the environment, so this sets its level too
+pub fn log_level(verbose: u8, quiet: bool) -> Option<&'static str> {
+	match (verbose, quiet) {
+		(_, true) => Some("warn"),
+		(0, false) => None,
+		(1, false) => Some("debug"),
+		_ => Some("trace")
+	}
+}
+
+pub fn pack(path: &Path) -> anyhow::Result<()> {
+	for (i, dir) in vfs::dirs().into_iter().enumerate() {
+		if i == 0 || path.join(&dir).is_dir() {
+			Vfs::create_dir(path.into(), &dir)
+				.with_context(|| format!("failed to pack {}", dir.display()))?;
+		} else {
+			tracing::warn!("{} not found, skipping", dir.display());
+		}
+	}
+
+	Ok(())
+}
+
+pub fn verify(path: &Path, json: bool) -> anyhow::Result<bool> {
+	let mut problems = Vec::new();
+
+	for vfs in archives(path)? {
+		problems.extend(
+			vfs.verify()
+				.with_context(|| format!("failed to verify {}", vfs.path().display()))?
+		);
+	}
+
+	if json {
+		println!(
+			"{}",
+			json!({
+				"ok": problems.is_empty(),
+				"problems": problems
+					.iter()
+					.map(|(path, problem)| json!({ "path": path, "problem": problem }))
+					.collect::<Vec<_>>()
+			})
+		);
+	} else {
+		for (path, problem) in &problems {
+			println!("{}: {}", path.display(), problem);
+		}
+	}
+
+	Ok(problems.is_empty())
+}
+
+// returns false if the user declined to repack a mismatched archive
+#[cfg(windows)]
+pub fn verify_or_repack(path: &Path) -> anyhow::Result<bool> {
+	if verify(path, false)? {
+		return Ok(true);
+	}
+
+	if !confirm("The archives don't match the game files. Repack now?") {
+		return Ok(false);
+	}
+
+	clean_archives(path);
+	pack(path)?;
+	Ok(true)
+}
+
+// a patch can change files without touching the data directory itself, which create_dir goes by
+#[cfg(windows)]
+pub fn update_if_outdated(path: &Path, exe: &Path) -> anyhow::Result<()> {
+	let installed = match version::read(exe) {
+		Some(version) => version,
+		None => return Ok(())
+	};
+
+	let outdated: Vec<_> = vfs::dirs()
+		.into_iter()
+		.map(|dir| (path.join(vfs::archive_name(&dir)), dir))
+		.filter(|(archive, _)| archive.is_file())
+		.filter_map(|(archive, dir)| {
+			let packed = Version(vfs::game_version(&archive)?);
+			Some((archive, dir, packed)).filter(|_| packed.0 < installed.0)
+		})
+		.collect();
+
+	if outdated.is_empty() {
+		return Ok(());
+	}
+
+	for (archive, _, packed) in &outdated {
+		tracing::warn!(
+			"{} was packed against game version {}, but {} is installed",
+			archive.display(),
+			packed,
+			installed
+		);
+	}
+
+	if !confirm("The game was updated since the archives were packed. Update them now?") {
+		return Ok(());
+	}
+
+	for (archive, dir, _) in outdated {
+		let changed = Vfs::update_dir(path.into(), &dir)
+			.with_context(|| format!("failed to update {}", archive.display()))?;
+		tracing::info!("updated {} entries in {}", changed, archive.display());
+	}
+
+	Ok(())
+}
+
+#[cfg(windows)]
+pub fn confirm(question: &str) -> bool {
+	#[cfg(feature = "gui")]
+	if crate::gui::active() {
+		return crate::gui::confirm(question);
+	}
+
+	print!("{} [y/N] ", question);
+	io::stdout().flush().ok();
+
+	let mut answer = String::new();
+	io::stdin().lock().read_line(&mut answer).ok();
+	answer.trim().eq_ignore_ascii_case("y")
+}
+
+pub fn list(path: &Path, pattern: &str, json: bool) -> anyhow::Result<()> {
+	let mut entries = Vec::new();
+
+	for vfs in archives(path)? {
+		for (suffix, entry) in matching(&vfs, pattern) {
+			if json {
+				entries.push(match *entry {
+					Entry::Directory => {
+						json!({ "path": vfs.path().join(suffix), "type": "directory" })
+					}
+					Entry::File { len, .. } => {
+						json!({ "path": vfs.path().join(suffix), "type": "file", "len": len })
+					}
+				});
+
+				continue;
+			}
+
+			match *entry {
+				Entry::Directory => {
+					println!("{}{}", vfs.path().join(suffix).display(), MAIN_SEPARATOR)
+				}
+				Entry::File { len, .. } => {
+					println!("{} {}", vfs.path().join(suffix).display(), len)
+				}
+			}
+		}
+	}
+
+	if json {
+		println!("{}", json!(entries));
+	}
+
+	Ok(())
+}
+
+pub fn extract(path: &Path, pattern: &str, dest: &Path) -> anyhow::Result<()> {
+	for vfs in archives(path)? {
+		for (suffix, entry) in matching(&vfs, pattern) {
+			let source = vfs.path().join(suffix);
+			let target = dest.join(source.strip_prefix(path).unwrap());
+
+			match *entry {
+				Entry::Directory => {
+					fs::create_dir_all(&target)
+						.expect(&format!("failed to create dir: {}", target.display()));
+				}
+				Entry::File { .. } => {
+					if let Some(parent) = target.parent() {
+						fs::create_dir_all(parent)
+							.expect(&format!("failed to create dir: {}", parent.display()));
+					}
+
+					let mut reader = vfs.read(&source).flatten().unwrap();
+
+					io::copy(
+						&mut reader,
+						&mut File::create(&target)
+							.expect(&format!("failed to create file: {}", target.display()))
+					)
+					.expect(&format!("failed to extract: {}", source.display()));
+				}
+			}
+		}
+	}
+
+	Ok(())
+}
+
+pub fn cat(path: &Path, entry: &Path, output: Option<&Path>) -> anyhow::Result<()> {
+	let full = path.join(entry);
+
+	let mut reader = archives(path)?
+		.iter()
+		.find_map(|vfs| vfs.read(&full))
+		.unwrap_or_else(|| panic!("{} is not in any archive", entry.display()))
+		.unwrap_or_else(|| panic!("{} is a directory or not archived", entry.display()));
+
+	let result = match output {
+		Some(output) => File::create(output).and_then(|mut file| io::copy(&mut reader, &mut file)),
+		None => io::copy(&mut reader, &mut io::stdout().lock())
+	};
+
+	result.with_context(|| format!("failed to write {}", entry.display()))?;
+	Ok(())
+}
+
+pub fn put(path: &Path, source: &Path, entry: &Path) -> anyhow::Result<()> {
+	let full = path.join(entry);
+
+	let mut holder = None;
+
+	for dir in vfs::dirs() {
+		if path.join(vfs::archive_name(&dir)).is_file()
+			&& Vfs::open_dir(path.into(), &dir)
+				.with_context(|| format!("failed to open {}", vfs::archive_name(&dir)))?
+				.inside(&full)
+		{
+			holder = Some(dir);
+			break;
+		}
+	}
+
+	let dir = holder.with_context(|| format!("no archive holds {}", entry.display()))?;
+
+	Vfs::put(path.into(), &dir, &full, source)
+		.with_context(|| format!("failed to store {}", entry.display()))?;
+	tracing::info!("stored {} as {}", source.display(), entry.display());
+	Ok(())
+}
+
+// modified is the loose file's, which is what update_dir compares against the archive's own
+pub fn dump_index(
+	path: &Path,
+	output: Option<&Path>,
+	json: bool,
+	hash: bool
+) -> anyhow::Result<()> {
+	let mut rows = Vec::new();
+	let mut buf = vec![0; 1 << 20];
+
+	for vfs in archives(path)? {
+		let archive = path.join(vfs::archive_name(vfs.path().strip_prefix(path).unwrap()));
+		let packed = archive
+			.metadata()
+			.ok()
+			.and_then(|m| unix_time(m.modified()));
+
+		for (suffix, entry) in vfs.entries() {
+			let loose = vfs.source().join(suffix);
+			let modified = loose.metadata().ok().and_then(|m| unix_time(m.modified()));
+
+			let (kind, offset, len, crc32) = match *entry {
+				Entry::Directory => ("directory", None, None, None),
+				Entry::File { offset, len } => {
+					let crc32 = if hash {
+						Some(crc32(&vfs, suffix, &mut buf))
+					} else {
+						None
+					};
+
+					("file", Some(offset), Some(len), crc32)
+				}
+			};
+
+			rows.push(json!({
+				"archive": archive,
+				"path": suffix,
+				"type": kind,
+				"offset": offset,
+				"len": len,
+				"crc32": crc32,
+				"modified": modified,
+				"packed": packed
+			}));
+		}
+	}
+
+	let mut out: Box<dyn Write> = match output {
+		Some(output) => {
+			Box::new(io::BufWriter::new(
+				File::create(output).expect(&format!("failed to create {}", output.display()))
+			))
+		}
+		None => Box::new(io::stdout())
+	};
+
+	let columns = [
+		"archive", "path", "type", "offset", "len", "crc32", "modified", "packed"
+	];
+
+	let result = if json {
+		serde_json::to_writer_pretty(&mut out, &rows)
+			.map_err(io::Error::from)
+			.and_then(|()| writeln!(out))
+	} else {
+		writeln!(out, "{}", columns.join(",")).and_then(|()| {
+			rows.iter().try_for_each(|row| {
+				let fields: Vec<_> = columns
+					.iter()
+					.map(|&column| {
+						match &row[column] {
+							Value::Null => String::new(),
+							Value::String(s) => csv_field(s),
+							value => value.to_string()
+						}
+					})
+					.collect();
+
+				writeln!(out, "{}", fields.join(","))
+			})
+		})
+	};
+
+	result
+		.and_then(|()| out.flush())
+		.context("failed to write the index")
+}
+
+fn crc32(vfs: &Vfs, suffix: &Path, buf: &mut [u8]) -> String {
+	let mut reader = vfs.read(&vfs.path().join(suffix)).flatten().unwrap();
+	let mut hasher = crc32fast::Hasher::new();
+
+	loop {
+		match reader.read(buf) {
+			Ok(0) => break,
+			Ok(n) => hasher.update(&buf[..n]),
+			Err(e) => panic!("failed to read {}: {}", suffix.display(), e)
+		}
+	}
+
+	format!("{:08x}", hasher.finalize())
+}
+
+fn csv_field(s: &str) -> String {
+	if s.contains(&[',', '"', '\n'][..]) {
+		format!("\"{}\"", s.replace('"', "\"\""))
+	} else {
+		s.to_owned()
+	}
+}
+
+fn unix_time(time: io::Result<SystemTime>) -> Option<u64> {
+	time.ok()?
+		.duration_since(UNIX_EPOCH)
+		.ok()
+		.map(|d| d.as_secs())
+}
+
+pub fn info(path: &Path, json: bool) {
+	let mut archives = Vec::new();
+
+	for dir in vfs::dirs() {
+		let archive = path.join(vfs::archive_name(&dir));
+
+		let len = match archive.metadata() {
+			Ok(metadata) => metadata.len(),
+			Err(e) if json => {
+				archives.push(json!({ "archive": archive, "error": e.to_string() }));
+				continue;
+			}
+			Err(e) => {
+				println!("{}: {}", archive.display(), e);
+				continue;
+			}
+		};
+
+		let vfs = match Vfs::open_dir(path.into(), &dir) {
+			Ok(vfs) => vfs,
+			Err(e) if json => {
+				archives.push(json!({ "archive": archive, "error": e.to_string() }));
+				continue;
+			}
+			Err(e) => {
+				println!("{}: {}", archive.display(), e);
+				continue;
+			}
+		};
+
+		let (mut dirs, mut files, mut data) = (0, 0, 0);
+
+		for (_, entry) in vfs.entries() {
+			match *entry {
+				Entry::Directory => dirs += 1,
+				Entry::File { len, .. } => {
+					files += 1;
+					data += len;
+				}
+			}
+		}
+
+		let game_version =
+			vfs::game_version(&archive).map(|[a, b, c, d]| format!("{}.{}.{}.{}", a, b, c, d));
+		let wasted = vfs::wasted(&archive);
+
+		if json {
+			archives.push(json!({
+				"archive": archive,
+				"len": len,
+				"files": files,
+				"data": data,
+				"directories": dirs,
+				"game_version": game_version,
+				"wasted": wasted
+			}));
+		} else {
+			println!(
+				"{}: {} bytes, {} files ({} bytes), {} directories, packed against game version {}, {} bytes unreachable",
+				archive.display(),
+				len,
+				files,
+				data,
+				dirs,
+				game_version.as_deref().unwrap_or("unknown"),
+				wasted.map_or_else(|| "unknown".to_owned(), |wasted| wasted.to_string())
+			);
+		}
+	}
+
+	if json {
+		println!("{}", json!(archives));
+	}
+}
+
+// the counters live in a mapping the game's DLL creates, so they're gone once it exits
+#[cfg(windows)]
+pub fn stats(pid: u32, json: bool) {
+	let stats =
+		Stats::open(pid).unwrap_or_else(|| panic!("process {} is not running with the fix", pid));
+
+	if json {
+		println!(
+			"{}",
+			json!({
+				"opens": Stats::get(&stats.opens),
+				"misses": Stats::get(&stats.misses),
+				"overlay": Stats::get(&stats.overlay),
+				"finds": Stats::get(&stats.finds),
+				"bytes_read": Stats::get(&stats.bytes_read),
+				"handles": Stats::get(&stats.handles),
+				"find_handles": Stats::get(&stats.find_handles)
+			})
+		);
+	} else {
+		println!("{}", stats.summary());
+		println!(
+			"{} open handles, {} open find handles",
+			Stats::get(&stats.handles),
+			Stats::get(&stats.find_handles)
+		);
+	}
+}
+
+// entries added, removed or changed relative to the other directory's archives, comparing
+// contents only when the lengths match
+pub fn diff(path: &Path, other: &Path, json: bool) -> anyhow::Result<bool> {
+	let mut changes = Vec::new();
+	let mut buf = vec![0; 1 << 20];
+
+	for dir in vfs::dirs() {
+		let open = |path: &Path| -> anyhow::Result<Option<Vfs>> {
+			let archive = path.join(vfs::archive_name(&dir));
+
+			if !archive.is_file() {
+				return Ok(None);
+			}
+
+			Vfs::open_dir(path.into(), &dir)
+				.map(Some)
+				.with_context(|| format!("failed to open {}", archive.display()))
+		};
+
+		let entries = |vfs: &Option<Vfs>| -> BTreeMap<PathBuf, Entry> {
+			vfs.iter()
+				.flat_map(|vfs| vfs.entries().map(|(k, &e)| (k.to_path_buf(), e)))
+				.collect()
+		};
+
+		let (ours, theirs) = (open(path)?, open(other)?);
+		let (our_entries, their_entries) = (entries(&ours), entries(&theirs));
+
+		for key in our_entries
+			.keys()
+			.chain(their_entries.keys())
+			.collect::<BTreeSet<_>>()
+		{
+			let change = match (our_entries.get(key), their_entries.get(key)) {
+				(Some(_), None) => "added",
+				(None, Some(_)) => "removed",
+				(Some(Entry::Directory), Some(Entry::Directory)) => continue,
+				(Some(&Entry::File { len: a, .. }), Some(&Entry::File { len: b, .. }))
+					if a == b =>
+				{
+					let ours = crc32(ours.as_ref().unwrap(), key, &mut buf);
+
+					if ours == crc32(theirs.as_ref().unwrap(), key, &mut buf) {
+						continue;
+					}
+
+					"changed"
+				}
+				_ => "changed"
+			};
+
+			changes.push((dir.join(key), change));
+		}
+	}
+
+	if json {
+		println!(
+			"{}",
+			json!({
+				"ok": changes.is_empty(),
+				"changes": changes
+					.iter()
+					.map(|(path, change)| json!({ "path": path, "change": change }))
+					.collect::<Vec<_>>()
+			})
+		);
+	} else {
+		for (path, change) in &changes {
+			println!("{} {}", change, path.display());
+		}
+	}
+
+	Ok(changes.is_empty())
+}
+
+pub fn clean_archives(path: &Path) {
+	for dir in vfs::dirs() {
+		remove(&path.join(vfs::archive_name(&dir)), false, true);
+	}
+}
+
+pub fn clean(path: &Path, dry_run: bool) {
+	let archives: Vec<_> = vfs::dirs()
+		.iter()
+		.map(|dir| path.join(vfs::archive_name(dir)))
+		.collect();
+
+	let mut targets = archives.clone();
+
+	// logs, record files, dumps and the crash report directories
+	targets.extend(
+		fs::read_dir(path)
+			.expect(&format!("failed to read dir: {}", path.display()))
+			.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+			.filter(|target| {
+				let name = target.file_name().unwrap().to_string_lossy();
+
+				name.starts_with("underrail_fix.")
+					&& (target.is_dir()
+						|| name.ends_with(".log")
+						|| name.contains(".log.")
+						|| name.ends_with(".dmp")
+						|| name.ends_with(".tsv"))
+			})
+	);
+
+	targets.extend(
+		["create", "find"]
+			.iter()
+			.map(|ty| env::temp_dir().join(format!("underrail_fixer_{}", ty)))
+	);
+
+	for target in targets {
+		remove(&target, dry_run, archives.contains(&target));
+	}
+}
+
+fn remove(target: &Path, dry_run: bool, archive: bool) {
+	if dry_run {
+		if target.exists() {
+			println!("would remove {}", target.display());
+		}
+
+		return;
+	}
+
+	let result = if target.is_dir() {
+		fs::remove_dir_all(target)
+	} else if archive {
+		vfs::remove_archive(target)
+	} else {
+		fs::remove_file(target)
+	};
+
+	match result {
+		Ok(()) => tracing::info!("removed {}", target.display()),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+		Err(e) => tracing::warn!("failed to remove {}: {}", target.display(), e)
+	}
+}
+
+#[cfg(windows)]
+pub fn install(path: &Path) {
+	let exe = path.join(game::exe_name());
+	let original = path.join(game::original_exe_name());
+	let current = env::current_exe().expect("failed to get current exe path");
+	let dll = inject::dll_path();
+
+	assert!(
+		exe.is_file() || original.is_file(),
+		"{} not found",
+		exe.display()
+	);
+
+	if original.is_file() {
+		tracing::info!("{} already exists, keeping it", original.display());
+	} else {
+		fs::rename(&exe, &original).expect(&format!(
+			"failed to rename {} to {}",
+			exe.display(),
+			original.display()
+		));
+	}
+
+	for (source, target) in [
+		(&current, &exe),
+		(&dll, &path.join(dll.file_name().unwrap()))
+	] {
+		if source != target {
+			fs::copy(source, target).expect(&format!(
+				"failed to copy {} to {}",
+				source.display(),
+				target.display()
+			));
+		}
+	}
+
+	tracing::info!("installed into {}", path.display());
+}
+
+#[cfg(windows)]
+pub fn uninstall(path: &Path) {
+	let exe = path.join(game::exe_name());
+	let original = path.join(game::original_exe_name());
+	let dll = inject::dll_path();
+
+	assert!(original.is_file(), "{} not found", original.display());
+
+	fs::rename(&original, &exe).expect(&format!(
+		"failed to rename {} to {}",
+		original.display(),
+		exe.display()
+	));
+
+	let installed = path.join(dll.file_name().unwrap());
+
+	if installed != dll {
+		match fs::remove_file(&installed) {
+			Ok(()) => {}
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => tracing::warn!("failed to remove {}: {}", installed.display(), e)
+		}
+	}
+
+	tracing::info!("restored {}", exe.display());
+}
+
+pub fn lang_install(path: &Path, source: &Path, name: Option<String>) -> anyhow::Result<()> {
+	let name = match name {
+		Some(name) => name,
+		None => {
+			source
+				.file_name()
+				.context("pack source has no name")?
+				.to_string_lossy()
+				.into_owned()
+		}
+	};
+
+	let archive = vfs::pack_path(path, &name);
+	let dir = archive.parent().unwrap();
+
+	fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+	Vfs::create_pack(source, &archive)
+		.with_context(|| format!("failed to pack {}", source.display()))?;
+	tracing::info!("installed {}", archive.display());
+	Ok(())
+}
+
+pub fn lang_remove(path: &Path, name: &str) {
+	let archive = vfs::pack_path(path, name);
+
+	match fs::remove_file(&archive) {
+		Ok(()) => tracing::info!("removed {}", archive.display()),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => {
+			tracing::warn!("no pack named {}", name)
+		}
+		Err(e) => tracing::warn!("failed to remove {}: {}", archive.display(), e)
+	}
+}
+
+pub fn lang_list(path: &Path) -> anyhow::Result<()> {
+	let mut suppliers: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+	let mut sources = Vec::new();
+
+	for pack in vfs::packs(path) {
+		let name = pack.file_stem().unwrap().to_string_lossy().into_owned();
+		let vfs = Vfs::open_pack(path.into(), &pack)
+			.with_context(|| format!("failed to open {}", pack.display()))?;
+		sources.push((name, vfs));
+	}
+
+	for (i, (name, vfs)) in sources.iter().enumerate() {
+		let files = vfs
+			.entries()
+			.filter(|(_, e)| matches!(e, Entry::File { .. }))
+			.count();
+
+		println!("{}. {} ({} files)", i + 1, name, files);
+	}
+
+	if path.join(vfs::archive_name(&vfs::data_dir())).is_file() {
+		sources.push((
+			"base".to_owned(),
+			Vfs::open(path.into()).context("failed to open the base archive")?
+		));
+	}
+
+	for (name, vfs) in &sources {
+		for (key, entry) in vfs.entries() {
+			if let Entry::File { .. } = entry {
+				suppliers.entry(key.into()).or_default().push(name.clone());
+			}
+		}
+	}
+
+	for (key, names) in suppliers.iter().filter(|(_, names)| names.len() > 1) {
+		println!(
+			"{}: {} (shadows {})",
+			key.display(),
+			names[0],
+			names[1..].join(", ")
+		);
+	}
+
+	Ok(())
+}
+
+fn archives(path: &Path) -> anyhow::Result<Vec<Vfs>> {
+	vfs::dirs()
+		.into_iter()
+		.map(|dir| (path.join(vfs::archive_name(&dir)), dir))
+		.filter(|(archive, _)| archive.is_file())
+		.map(|(archive, dir)| {
+			Vfs::open_dir(path.into(), &dir)
+				.with_context(|| format!("failed to open {}", archive.display()))
+		})
+		.collect()
+}
+
+fn matching<'a>(vfs: &'a Vfs, pattern: &str) -> Vec<(&'a Path, &'a Entry)> {
+	vfs.find_recursive(&vfs.path().join(pattern))
+		.unwrap_or_default()
+}
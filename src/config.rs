@@ -0,0 +1,274 @@
+use parking_lot::{const_mutex, Mutex};
+use serde::Deserialize;
+use std::{
+	collections::BTreeMap,
+	env,
+	ffi::OsString,
+	fs,
+	io::ErrorKind,
+	mem,
+	path::{Path, PathBuf}
+};
+
+pub const CONFIG_FILE: &str = "underrail-fix.toml";
+
+// variables set from a config file rather than inherited, which later files and profiles may
+// override
+static SET: Mutex<Vec<&'static str>> = const_mutex(Vec::new());
+// variables already reported as invalid, get reads them all every time
+static INVALID: Mutex<Vec<&'static str>> = const_mutex(Vec::new());
+
+macro_rules! config {
+	($($field:ident: $ty:ty = $var:literal,)*) => {
+		// every key maps onto the environment variable of the same setting, which takes precedence;
+		// the environment also carries the merged settings on into the game, so get reads them back
+		// from there
+		#[derive(Clone, Default, Deserialize)]
+		#[serde(default, deny_unknown_fields)]
+		pub struct Config {
+			$(pub $field: Option<$ty>,)*
+			pub hooks: Hooks,
+			profiles: BTreeMap<String, Profile>,
+			installs: BTreeMap<String, Install>
+		}
+
+		impl Config {
+			fn export(self) {
+				$(set($var, self.$field);)*
+				set("UNDERRAIL_FIX_HOOK_MODE", self.hooks.mode);
+				set("UNDERRAIL_FIX_DISABLE_HOOKS", self.hooks.disable);
+			}
+
+			fn from_env() -> Self {
+				Self {
+					$($field: var($var),)*
+					hooks: Hooks {
+						mode: var("UNDERRAIL_FIX_HOOK_MODE"),
+						disable: var("UNDERRAIL_FIX_DISABLE_HOOKS")
+					},
+					..Self::default()
+				}
+			}
+		}
+	};
+}
+
+config! {
+	exe: PathBuf = "UNDERRAIL_EXE",
+	data: PathBuf = "UNDERRAIL_DATA",
+	store: String = "UNDERRAIL_FIX_STORE",
+	game_exe: String = "UNDERRAIL_FIX_GAME_EXE",
+	game_exes: Vec<String> = "UNDERRAIL_FIX_GAME_EXES",
+	data_dir: PathBuf = "UNDERRAIL_FIX_DATA_DIR",
+	archive: String = "UNDERRAIL_FIX_ARCHIVE",
+	archive_dir: PathBuf = "UNDERRAIL_FIX_ARCHIVE_DIR",
+	extra_dirs: Vec<String> = "UNDERRAIL_FIX_EXTRA_DIRS",
+	detect_dirs: bool = "UNDERRAIL_FIX_DETECT_DIRS",
+	overlay: PathBuf = "UNDERRAIL_FIX_OVERLAY",
+	buffer_writes: Vec<String> = "UNDERRAIL_FIX_BUFFER_WRITES",
+	no_pack: bool = "UNDERRAIL_FIX_NO_PACK",
+	verify_on_launch: bool = "UNDERRAIL_FIX_VERIFY_ON_LAUNCH",
+	pack_threads: usize = "UNDERRAIL_FIX_PACK_THREADS",
+	pack_buffer_size: usize = "UNDERRAIL_FIX_PACK_BUFFER_SIZE",
+	disable: bool = "UNDERRAIL_FIX_DISABLE",
+	require_tested: bool = "UNDERRAIL_FIX_REQUIRE_TESTED",
+	console: bool = "UNDERRAIL_FIX_CONSOLE",
+	portable: bool = "UNDERRAIL_FIX_PORTABLE",
+	priority: String = "UNDERRAIL_FIX_PRIORITY",
+	affinity: String = "UNDERRAIL_FIX_AFFINITY",
+	game_console: String = "UNDERRAIL_FIX_GAME_CONSOLE",
+	pause: bool = "UNDERRAIL_FIX_PAUSE",
+	watchdog: usize = "UNDERRAIL_FIX_WATCHDOG",
+	log: String = "UNDERRAIL_FIX_LOG",
+	log_max_size: usize = "UNDERRAIL_FIX_LOG_MAX_SIZE",
+	trace: String = "UNDERRAIL_FIX_TRACE",
+	profile: String = "UNDERRAIL_FIX_PROFILE",
+	install: String = "UNDERRAIL_FIX_INSTALL",
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+	pub mode: Option<String>,
+	pub disable: Option<Vec<String>>
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Install {
+	exe: Option<PathBuf>,
+	data_dir: Option<PathBuf>,
+	archive: Option<String>,
+	archive_dir: Option<PathBuf>
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Profile {
+	data_dir: Option<PathBuf>,
+	archive: Option<String>,
+	extra_dirs: Option<Vec<String>>,
+	overlay: Option<PathBuf>
+}
+
+// how a setting is written to and read back from its environment variable
+trait Setting: Sized {
+	fn to_env(self) -> OsString;
+	fn from_env(value: OsString) -> Option<Self>;
+}
+
+impl Setting for String {
+	fn to_env(self) -> OsString {
+		self.into()
+	}
+
+	fn from_env(value: OsString) -> Option<Self> {
+		value.into_string().ok()
+	}
+}
+
+impl Setting for PathBuf {
+	fn to_env(self) -> OsString {
+		self.into()
+	}
+
+	fn from_env(value: OsString) -> Option<Self> {
+		Some(value.into())
+	}
+}
+
+impl Setting for bool {
+	fn to_env(self) -> OsString {
+		OsString::from(if self { "1" } else { "0" })
+	}
+
+	fn from_env(value: OsString) -> Option<Self> {
+		match value.to_str()?.trim() {
+			"1" => Some(true),
+			"0" => Some(false),
+			_ => None
+		}
+	}
+}
+
+impl Setting for usize {
+	fn to_env(self) -> OsString {
+		self.to_string().into()
+	}
+
+	fn from_env(value: OsString) -> Option<Self> {
+		value.to_str()?.trim().parse().ok()
+	}
+}
+
+impl Setting for Vec<String> {
+	fn to_env(self) -> OsString {
+		self.join(",").into()
+	}
+
+	fn from_env(value: OsString) -> Option<Self> {
+		Some(
+			value
+				.to_str()?
+				.split(',')
+				.map(str::trim)
+				.filter(|s| !s.is_empty())
+				.map(str::to_owned)
+				.collect()
+		)
+	}
+}
+
+// the settings in effect, from the config files loaded so far and the environment
+pub fn get() -> Config {
+	Config::from_env()
+}
+
+pub fn load(dir: &Path) -> Result<(), String> {
+	let path = dir.join(CONFIG_FILE);
+
+	let s = match fs::read_to_string(&path) {
+		Ok(s) => s,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+		Err(e) => return Err(format!("{}: {}", path.display(), e))
+	};
+
+	let mut config: Config =
+		toml::from_str(&s).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+	let mut installs = mem::take(&mut config.installs);
+	let mut profiles = mem::take(&mut config.profiles);
+
+	// --profile and --install are set after the launcher's own config has been read, so never
+	// override them
+	for (var, value) in vec![
+		("UNDERRAIL_FIX_PROFILE", config.profile.take()),
+		("UNDERRAIL_FIX_INSTALL", config.install.take()),
+	] {
+		if let (Some(value), None) = (value, env::var_os(var)) {
+			env::set_var(var, value);
+		}
+	}
+
+	config.export();
+
+	// the game directory's config and the DLL's load usually have no installs of their own
+	if let Some(install) = get()
+		.install
+		.filter(|_| !installs.is_empty())
+		.map(|name| {
+			installs
+				.remove(&name)
+				.ok_or_else(|| format!("{}: no install named {}", path.display(), name))
+		})
+		.transpose()?
+	{
+		set("UNDERRAIL_EXE", install.exe);
+		set("UNDERRAIL_FIX_DATA_DIR", install.data_dir);
+		set("UNDERRAIL_FIX_ARCHIVE", install.archive);
+		set("UNDERRAIL_FIX_ARCHIVE_DIR", install.archive_dir);
+	}
+
+	// a profile without a table just gets its own overlay directory
+	if let Some(profile) = get().profile.and_then(|name| profiles.remove(&name)) {
+		set("UNDERRAIL_FIX_DATA_DIR", profile.data_dir);
+		set("UNDERRAIL_FIX_ARCHIVE", profile.archive);
+		set("UNDERRAIL_FIX_EXTRA_DIRS", profile.extra_dirs);
+		set("UNDERRAIL_FIX_OVERLAY", profile.overlay);
+	}
+
+	Ok(())
+}
+
+fn set<T: Setting>(var: &'static str, value: Option<T>) {
+	let value = match value {
+		Some(value) => value.to_env(),
+		None => return
+	};
+
+	let mut set = SET.lock();
+
+	if env::var_os(var).is_none() || set.contains(&var) {
+		env::set_var(var, value);
+
+		if !set.contains(&var) {
+			set.push(var);
+		}
+	}
+}
+
+fn var<T: Setting>(var: &'static str) -> Option<T> {
+	let value = env::var_os(var)?;
+	let setting = T::from_env(value.clone());
+
+	if setting.is_none() {
+		let mut invalid = INVALID.lock();
+
+		if !invalid.contains(&var) {
+			invalid.push(var);
+			tracing::warn!("ignoring invalid {}={}", var, value.to_string_lossy());
+		}
+	}
+
+	setting
+}
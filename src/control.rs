@@ -0,0 +1,274 @@
+use crate::{build_info, path_to_wide, trace, unload, FIXER, MODULE};
+use std::{
+	io::Error,
+	mem,
+	path::Path,
+	ptr, slice, str,
+	sync::atomic::{AtomicBool, Ordering},
+	thread,
+	time::Duration
+};
+use winapi::{
+	shared::{
+		minwindef::{DWORD, FALSE},
+		sddl::{
+			ConvertSidToStringSidW, ConvertStringSecurityDescriptorToSecurityDescriptorW,
+			SDDL_REVISION_1
+		},
+		winerror::{ERROR_NO_DATA, ERROR_PIPE_CONNECTED}
+	},
+	um::{
+		errhandlingapi::GetLastError,
+		fileapi::{FlushFileBuffers, ReadFile, WriteFile},
+		handleapi::{CloseHandle, INVALID_HANDLE_VALUE},
+		libloaderapi::FreeLibraryAndExitThread,
+		minwinbase::SECURITY_ATTRIBUTES,
+		namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe},
+		processthreadsapi::{GetCurrentProcess, GetCurrentProcessId, OpenProcessToken},
+		securitybaseapi::GetTokenInformation,
+		winbase::{
+			LocalFree, PIPE_ACCESS_DUPLEX, PIPE_READMODE_MESSAGE, PIPE_REJECT_REMOTE_CLIENTS,
+			PIPE_TYPE_MESSAGE, PIPE_WAIT
+		},
+		winnt::{TokenUser, HANDLE, PSECURITY_DESCRIPTOR, TOKEN_QUERY, TOKEN_USER}
+	}
+};
+
+const PIPE_BUF_LEN: usize = 4096;
+const CONNECT_RETRIES: u32 = 5;
+const CONNECT_BACKOFF: Duration = Duration::from_millis(100);
+
+static UNLOAD: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn pipe_name(pid: u32) -> String {
+	format!(r"\\.\pipe\underrail-fix.{}", pid)
+}
+
+pub(crate) fn serve() {
+	let name = path_to_wide(Path::new(&pipe_name(unsafe { GetCurrentProcessId() })));
+
+	// only the user running the game may connect, the game runs fine without the channel
+	let descriptor = match security_descriptor() {
+		Ok(descriptor) => descriptor,
+		Err(e) => {
+			tracing::warn!(
+				"control pipe security descriptor: {}, no control channel",
+				e
+			);
+			return;
+		}
+	};
+
+	let mut attributes = SECURITY_ATTRIBUTES {
+		nLength: mem::size_of::<SECURITY_ATTRIBUTES>() as _,
+		lpSecurityDescriptor: descriptor,
+		bInheritHandle: FALSE
+	};
+
+	let pipe = unsafe {
+		CreateNamedPipeW(
+			name.as_ptr(),
+			PIPE_ACCESS_DUPLEX,
+			PIPE_TYPE_MESSAGE | PIPE_READMODE_MESSAGE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+			1,
+			PIPE_BUF_LEN as _,
+			PIPE_BUF_LEN as _,
+			0,
+			&mut attributes
+		)
+	};
+
+	let error = Error::last_os_error();
+
+	unsafe {
+		LocalFree(attributes.lpSecurityDescriptor);
+	}
+
+	if pipe == INVALID_HANDLE_VALUE {
+		tracing::warn!("CreateNamedPipeW: {}, no control channel", error);
+		return;
+	}
+
+	let pipe = pipe as usize;
+
+	thread::spawn(move || {
+		let pipe = pipe as HANDLE;
+		let mut buf = vec![0; PIPE_BUF_LEN];
+		let mut failures = 0;
+
+		loop {
+			unsafe {
+				if ConnectNamedPipe(pipe, ptr::null_mut()) == FALSE {
+					match GetLastError() {
+						ERROR_PIPE_CONNECTED => {}
+						// the client already hung up
+						ERROR_NO_DATA => {
+							DisconnectNamedPipe(pipe);
+							continue;
+						}
+						error => {
+							let error = Error::from_raw_os_error(error as _);
+							failures += 1;
+
+							if failures > CONNECT_RETRIES {
+								tracing::error!(
+									"ConnectNamedPipe: {}, closing the control pipe",
+									error
+								);
+								CloseHandle(pipe);
+								return;
+							}
+
+							tracing::warn!("ConnectNamedPipe: {}", error);
+							thread::sleep(CONNECT_BACKOFF * 2u32.pow(failures));
+							continue;
+						}
+					}
+				}
+			}
+
+			failures = 0;
+
+			loop {
+				let mut read = 0;
+
+				let b = unsafe {
+					ReadFile(
+						pipe,
+						buf.as_mut_ptr() as *mut _,
+						buf.len() as _,
+						&mut read,
+						ptr::null_mut()
+					)
+				};
+
+				if b == FALSE {
+					break;
+				}
+
+				let reply = match str::from_utf8(&buf[..read as usize]) {
+					Ok(command) => execute(command.trim()),
+					Err(_) => "error: command not utf-8".into()
+				};
+
+				let mut written = 0;
+
+				unsafe {
+					WriteFile(
+						pipe,
+						reply.as_ptr() as *const _,
+						reply.len() as _,
+						&mut written,
+						ptr::null_mut()
+					);
+
+					FlushFileBuffers(pipe);
+				}
+			}
+
+			unsafe {
+				DisconnectNamedPipe(pipe);
+
+				if UNLOAD.load(Ordering::Relaxed) {
+					CloseHandle(pipe);
+					FreeLibraryAndExitThread(MODULE.load(Ordering::Relaxed) as _, 0);
+				}
+			}
+		}
+	});
+}
+
+fn execute(command: &str) -> String {
+	let (detours, fixer) = FIXER.get().expect("FIXER singleton not initialized");
+	let mut args = command.split_whitespace();
+
+	match (args.next(), args.next()) {
+		(Some("stats"), None) => fixer.stats(),
+		(Some("build-info"), None) => build_info::build_info(),
+		(Some("detach"), None) => {
+			match unsafe { unload() } {
+				Ok(()) => {
+					UNLOAD.store(true, Ordering::Relaxed);
+					"ok".into()
+				}
+				Err(e) => format!("error: {}, staying loaded", e)
+			}
+		}
+		(Some("flush-cache"), None) => {
+			fixer.flush_cache();
+			"ok".into()
+		}
+		(Some("toggle-hook"), Some(name)) => {
+			match detours.toggle(name) {
+				Some(Some(true)) => format!("{} enabled", name),
+				Some(Some(false)) => format!("{} disabled", name),
+				Some(None) => format!("error: {} not attached", name),
+				None => format!("error: unknown hook: {}", name)
+			}
+		}
+		(Some("set-log-level"), Some(level)) => {
+			match trace::set_level(level) {
+				Ok(()) => "ok".into(),
+				Err(e) => format!("error: {}", e)
+			}
+		}
+		(Some("set-log-file-level"), Some(level)) => {
+			match trace::set_log_level(level) {
+				Ok(()) => "ok".into(),
+				Err(e) => format!("error: {}", e)
+			}
+		}
+		_ => format!("error: unknown command: {}", command)
+	}
+}
+
+// D:P grants the current user everything and nobody else anything
+fn security_descriptor() -> Result<PSECURITY_DESCRIPTOR, Error> {
+	unsafe {
+		let mut token = ptr::null_mut();
+
+		if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == FALSE {
+			return Err(Error::last_os_error());
+		}
+
+		let mut len: DWORD = 0;
+		GetTokenInformation(token, TokenUser, ptr::null_mut(), 0, &mut len);
+		let mut user = vec![0u64; (len as usize + 7) / 8];
+
+		let b = GetTokenInformation(token, TokenUser, user.as_mut_ptr() as *mut _, len, &mut len);
+		let error = Error::last_os_error();
+		CloseHandle(token);
+
+		if b == FALSE {
+			return Err(error);
+		}
+
+		let mut sid = ptr::null_mut();
+
+		if ConvertSidToStringSidW((*(user.as_ptr() as *const TOKEN_USER)).User.Sid, &mut sid)
+			== FALSE
+		{
+			return Err(Error::last_os_error());
+		}
+
+		let len = (0..).take_while(|&i| *sid.offset(i) != 0).count();
+		let sid_str = String::from_utf16_lossy(slice::from_raw_parts(sid, len));
+		LocalFree(sid as _);
+
+		let sddl = path_to_wide(Path::new(&format!("D:P(A;;GA;;;{})", sid_str)));
+
+		let mut descriptor = ptr::null_mut();
+
+		if ConvertStringSecurityDescriptorToSecurityDescriptorW(
+			sddl.as_ptr(),
+			SDDL_REVISION_1 as _,
+			&mut descriptor,
+			ptr::null_mut()
+		) == FALSE
+		{
+			return Err(Error::last_os_error());
+		}
+
+		Ok(descriptor)
+	}
+}
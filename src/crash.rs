@@ -0,0 +1,251 @@
+use once_cell::sync::OnceCell;
+use std::{
+	cell::Cell,
+	fmt::{Debug, Write as _},
+	fs::File,
+	io::{Error, Write},
+	iter, mem,
+	os::windows::io::AsRawHandle,
+	panic,
+	path::{Path, PathBuf},
+	ptr,
+	sync::atomic::{AtomicUsize, Ordering},
+	time::SystemTime
+};
+use winapi::{
+	shared::minwindef::{BOOL, DWORD, FALSE},
+	um::{
+		errhandlingapi::SetUnhandledExceptionFilter,
+		processthreadsapi::{GetCurrentProcess, GetCurrentProcessId, GetCurrentThreadId},
+		winnt::{EXCEPTION_POINTERS, HANDLE, LONG},
+		winuser::{MessageBoxW, MB_ICONERROR, MB_OK, MB_SETFOREGROUND}
+	},
+	vc::excpt::EXCEPTION_CONTINUE_SEARCH
+};
+
+pub const MINI_DUMP_WITH_DATA_SEGS: DWORD = 0x1;
+pub const MINI_DUMP_WITH_HANDLE_DATA: DWORD = 0x4;
+pub const MINI_DUMP_WITH_FULL_MEMORY_INFO: DWORD = 0x800;
+pub const MINI_DUMP_WITH_THREAD_INFO: DWORD = 0x1000;
+
+#[repr(C, packed(4))]
+struct MinidumpExceptionInformation {
+	thread_id: DWORD,
+	exception_pointers: *mut EXCEPTION_POINTERS,
+	client_pointers: BOOL
+}
+
+#[link(name = "dbghelp")]
+extern "system" {
+	fn MiniDumpWriteDump(
+		h_process: HANDLE,
+		process_id: DWORD,
+		h_file: HANDLE,
+		dump_type: DWORD,
+		exception_param: *const MinidumpExceptionInformation,
+		user_stream_param: *const u8,
+		callback_param: *const u8
+	) -> BOOL;
+}
+
+static DIR: OnceCell<PathBuf> = OnceCell::new();
+static PREVIOUS_FILTER: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+	static CALL: Cell<Option<(&'static str, *const dyn Debug)>> = Cell::new(None);
+	static HANDLING: Cell<bool> = Cell::new(false);
+}
+
+pub(crate) struct Call(Option<(&'static str, *const dyn Debug)>);
+
+impl Call {
+	pub(crate) fn enter(name: &'static str, args: &(dyn Debug + 'static)) -> Self {
+		Self(CALL.with(|call| call.replace(Some((name, args as *const _)))))
+	}
+}
+
+impl Drop for Call {
+	fn drop(&mut self) {
+		CALL.with(|call| call.set(self.0));
+	}
+}
+
+pub(crate) fn install(dir: PathBuf) {
+	let _ = DIR.set(dir);
+	let default = panic::take_hook();
+
+	panic::set_hook(Box::new(move |info| {
+		tracing::error!("{}", info);
+		let log = report(&info.to_string(), ptr::null_mut());
+		default(info);
+		message_box(&info.to_string(), log);
+	}));
+
+	unsafe {
+		let previous = SetUnhandledExceptionFilter(Some(unhandled_exception_filter));
+		PREVIOUS_FILTER.store(previous.map_or(0, |f| f as usize), Ordering::Relaxed);
+	}
+}
+
+pub(crate) fn uninstall() {
+	let _ = panic::take_hook();
+
+	unsafe {
+		SetUnhandledExceptionFilter(match PREVIOUS_FILTER.swap(0, Ordering::Relaxed) {
+			0 => None,
+			f => Some(mem::transmute(f))
+		});
+	}
+}
+
+pub(crate) fn handling() -> bool {
+	HANDLING.with(Cell::get)
+}
+
+unsafe extern "system" fn unhandled_exception_filter(info: *mut EXCEPTION_POINTERS) -> LONG {
+	let code = if info.is_null() || (*info).ExceptionRecord.is_null() {
+		0
+	} else {
+		(*(*info).ExceptionRecord).ExceptionCode
+	};
+
+	report(&format!("unhandled exception {:#010x}", code), info);
+
+	// whoever was installed before us, like the .NET runtime, still gets its say
+	match PREVIOUS_FILTER.load(Ordering::Relaxed) {
+		0 => EXCEPTION_CONTINUE_SEARCH,
+		f => {
+			let previous: unsafe extern "system" fn(*mut EXCEPTION_POINTERS) -> LONG =
+				mem::transmute(f);
+			previous(info)
+		}
+	}
+}
+
+fn report(message: &str, exception: *mut EXCEPTION_POINTERS) -> Option<PathBuf> {
+	if HANDLING.with(|h| h.replace(true)) {
+		return None;
+	}
+
+	let dir = DIR.get()?;
+
+	let pid = unsafe { GetCurrentProcessId() };
+	let time = SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.map_or(0, |d| d.as_secs());
+
+	let mut log = format!("time: {}\npid: {}\nerror: {}\n", time, pid, message);
+
+	CALL.with(|call| {
+		match call.get() {
+			Some((name, args)) => {
+				let _ = writeln!(log, "api: {}\nargs: {:?}", name, unsafe { &*args });
+			}
+			None => log.push_str("api: none\n")
+		}
+	});
+
+	let dump = dir.join(format!("underrail_fix.{}.dmp", pid));
+
+	if unsafe {
+		write_dump(
+			&dump,
+			GetCurrentProcess(),
+			pid,
+			MINI_DUMP_WITH_DATA_SEGS | MINI_DUMP_WITH_HANDLE_DATA | MINI_DUMP_WITH_THREAD_INFO,
+			exception
+		)
+	}
+	.is_ok()
+	{
+		let _ = writeln!(log, "minidump: {}", dump.display());
+	}
+
+	if !cfg!(feature = "no-console") {
+		eprint!("{}", log);
+	}
+
+	let path = dir.join("underrail_fix.crash.log");
+
+	let written = File::create(&path)
+		.and_then(|mut file| file.write_all(log.as_bytes()))
+		.is_ok();
+
+	HANDLING.with(|h| h.set(false));
+
+	if written {
+		Some(path)
+	} else {
+		None
+	}
+}
+
+// the exception, if any, has to be one the calling thread is handling
+pub unsafe fn write_dump(
+	path: &Path,
+	process: HANDLE,
+	pid: DWORD,
+	dump_type: DWORD,
+	exception: *mut EXCEPTION_POINTERS
+) -> Result<(), Error> {
+	let file = File::create(path)?;
+
+	let param = MinidumpExceptionInformation {
+		thread_id: GetCurrentThreadId(),
+		exception_pointers: exception,
+		client_pointers: FALSE
+	};
+
+	let b = MiniDumpWriteDump(
+		process,
+		pid,
+		file.as_raw_handle(),
+		dump_type,
+		if exception.is_null() {
+			ptr::null()
+		} else {
+			&param
+		},
+		ptr::null(),
+		ptr::null()
+	);
+
+	if b == FALSE {
+		return Err(Error::last_os_error());
+	}
+
+	Ok(())
+}
+
+fn message_box(message: &str, log: Option<PathBuf>) {
+	let mut text = format!(
+		"Underrail Fix hit a fatal error and the game has to close.\n\n{}",
+		message
+	);
+
+	if let Some(log) = log {
+		let _ = write!(
+			text,
+			"\n\nDetails were written to:\n{}\n\nPlease attach this file (and any .dmp file \
+			 next to it) when reporting the problem.",
+			log.display()
+		);
+	}
+
+	let text: Vec<_> = text.encode_utf16().chain(iter::once(0)).collect();
+	let caption: Vec<_> = "Underrail Fix"
+		.encode_utf16()
+		.chain(iter::once(0))
+		.collect();
+
+	HANDLING.with(|h| h.set(true));
+
+	unsafe {
+		MessageBoxW(
+			ptr::null_mut(),
+			text.as_ptr(),
+			caption.as_ptr(),
+			MB_OK | MB_ICONERROR | MB_SETFOREGROUND
+		);
+	}
+}
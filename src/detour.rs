@@ -0,0 +1,252 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) struct Detour<T> {
+	pub(crate) original: Box<T>,
+	pub(crate) detoured: T,
+	pub(crate) attached: AtomicBool,
+	pub(crate) enabled: AtomicBool
+}
+
+// held for the whole of a detoured call, so unloading can wait until no thread is running our code
+pub(crate) struct InFlight;
+
+impl InFlight {
+	pub(crate) fn enter() -> Self {
+		IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+		Self
+	}
+
+	pub(crate) fn count() -> usize {
+		IN_FLIGHT.load(Ordering::SeqCst)
+	}
+}
+
+impl Drop for InFlight {
+	fn drop(&mut self) {
+		IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+// Each hook is declared like an extern item, optionally preceded by attributes (e.g. #[cfg])
+// that gate everything generated for it, and a bracketed option list:
+//
+//     #[cfg(feature = "...")]
+//     [optional] extern "system" fn Name(arg: Type, ...) -> Ret;
+//
+// optional: a failed attach is logged at debug level instead of warning.
+macro_rules! detours {
+	($(
+		$(#[$meta:meta])*
+		$([$($option:ident),*])?
+		extern $abi:tt fn $fn:ident($($arg:ident: $ty:ty),*) -> $ret:ty;
+	)*) => {
+		paste::item! {
+			$(
+				$(#[$meta])*
+				pub(crate) type [<$fn Fn>] = unsafe extern $abi fn($($ty),*) -> $ret;
+
+				$(#[$meta])*
+				#[derive(Clone, Copy, Debug)]
+				pub(crate) struct [<$fn Args>] {
+					$($arg: $ty),*
+				}
+			)*
+
+			pub(crate) struct Detours {
+				$(
+					$(#[$meta])*
+					[<$fn:snake>]: $crate::detour::Detour<[<$fn Fn>]>,
+				)*
+			}
+
+			impl Detours {
+				fn new() -> Self {
+					use std::sync::atomic::AtomicBool;
+
+					Self {
+						$(
+							$(#[$meta])*
+							[<$fn:snake>]: $crate::detour::Detour {
+								original: Box::new($fn as [<$fn Fn>]),
+								detoured: [<detoured_ $fn:snake>] as [<$fn Fn>],
+								attached: AtomicBool::new(false),
+								enabled: AtomicBool::new(false)
+							},
+						)*
+					}
+				}
+
+				// only called once the detours are reachable through FIXER, since a hooked call
+				// can arrive on another thread as soon as its hook is committed
+				unsafe fn attach(&self, disabled: &[String]) {
+					use std::sync::atomic::Ordering;
+
+					$(
+						$(#[$meta])*
+						{
+							let detour = &self.[<$fn:snake>];
+							let optional = false $(|| [$(stringify!($option)),*].contains(&"optional"))?;
+
+							if $crate::hook_enabled(disabled, stringify!($fn)) {
+								let target = $fn as *mut std::ffi::c_void;
+
+								let error = $crate::hook::install(
+									&*detour.original as *const [<$fn Fn>] as *mut *mut std::ffi::c_void,
+									detour.detoured as *mut [<$fn Fn>] as *mut std::ffi::c_void
+								);
+
+								if error == 0 {
+									detour.attached.store(true, Ordering::Relaxed);
+									detour.enabled.store(true, Ordering::Relaxed);
+								} else {
+									let warning = match $crate::hook::owner(target) {
+										Some(owner) => format!(
+											"failed to attach {}: {:#x}, {:p} already jumps into {}",
+											stringify!($fn),
+											error,
+											target,
+											owner.display()
+										),
+										None => format!("failed to attach {}: {:#x}", stringify!($fn), error)
+									};
+
+									if optional {
+										tracing::debug!("{}", warning);
+									} else {
+										tracing::warn!("{}", warning);
+									}
+								}
+							} else {
+								tracing::info!("hook disabled: {}", stringify!($fn));
+							}
+						}
+					)*
+				}
+
+				// every hook is tried, the ones that failed are returned
+				unsafe fn detach(&self) -> Vec<String> {
+					let mut errors = Vec::new();
+
+					$(
+						$(#[$meta])*
+						{
+							let detour = &self.[<$fn:snake>];
+
+							if detour.attached.load(std::sync::atomic::Ordering::Relaxed) {
+								let error = $crate::hook::detach(
+									&*detour.original as *const [<$fn Fn>] as *mut *mut std::ffi::c_void,
+									detour.detoured as *mut [<$fn Fn>] as *mut std::ffi::c_void
+								);
+
+								if error != 0 {
+									let message = format!("detach {}: {:#x}", stringify!($fn), error);
+									tracing::error!("{}", message);
+									errors.push(message);
+								}
+							}
+						}
+					)*
+
+					errors
+				}
+
+				fn attached(&self, name: &str) -> bool {
+					$(
+						$(#[$meta])*
+						{
+							if name.eq_ignore_ascii_case(stringify!($fn)) {
+								return self.[<$fn:snake>].attached.load(std::sync::atomic::Ordering::Relaxed);
+							}
+						}
+					)*
+
+					false
+				}
+
+				fn enabled(&self) -> Vec<&'static str> {
+					let mut enabled = Vec::new();
+
+					$(
+						$(#[$meta])*
+						{
+							if self.[<$fn:snake>].enabled.load(std::sync::atomic::Ordering::Relaxed) {
+								enabled.push(stringify!($fn));
+							}
+						}
+					)*
+
+					enabled
+				}
+
+				fn disable(&self, name: &str) {
+					$(
+						$(#[$meta])*
+						{
+							if name.eq_ignore_ascii_case(stringify!($fn)) {
+								self.[<$fn:snake>].enabled.store(false, std::sync::atomic::Ordering::Relaxed);
+							}
+						}
+					)*
+				}
+
+				pub(crate) fn toggle(&self, name: &str) -> Option<Option<bool>> {
+					$(
+						$(#[$meta])*
+						{
+							if name.eq_ignore_ascii_case(stringify!($fn)) {
+								let detour = &self.[<$fn:snake>];
+
+								return Some(if detour.attached.load(std::sync::atomic::Ordering::Relaxed) {
+									Some(!detour.enabled.fetch_xor(true, std::sync::atomic::Ordering::Relaxed))
+								} else {
+									None
+								});
+							}
+						}
+					)*
+
+					None
+				}
+			}
+
+			$(
+				$(#[$meta])*
+				unsafe extern $abi fn [<detoured_ $fn:snake>]($(
+					#[allow(non_snake_case)] $arg: $ty
+				),*) -> $ret {
+					let _in_flight = $crate::detour::InFlight::enter();
+
+					let (detours, fixer) = $crate::FIXER
+						.get()
+						.expect("FIXER singleton not initialized");
+
+					let original: [<$fn Fn>] = *detours.[<$fn:snake>].original;
+
+					if !detours.[<$fn:snake>].enabled.load(std::sync::atomic::Ordering::Relaxed)
+						|| $crate::crash::handling()
+					{
+						return original($($arg),*);
+					}
+
+					let span = tracing::trace_span!(
+						stringify!($fn),
+						$($arg = ?$arg,)*
+						path = tracing::field::Empty,
+						result = tracing::field::Empty
+					);
+
+					let _enter = span.enter();
+
+					let args = [<$fn Args>] { $($arg),* };
+					let _call = $crate::crash::Call::enter(stringify!($fn), &args);
+					let ret = fixer.[<$fn:snake>](args, |args| original($(args.$arg),*));
+
+					span.record("result", &tracing::field::debug(ret));
+					ret
+				}
+			)*
+		}
+	};
+}
@@ -152,6 +152,7 @@ pub struct _SECURITY_ATTRIBUTES {
 	pub bInheritHandle: BOOL
 }
 #[test]
+#[cfg(target_pointer_width = "32")]
 fn bindgen_test_layout__SECURITY_ATTRIBUTES() {
 	assert_eq!(
 		::std::mem::size_of::<_SECURITY_ATTRIBUTES>(),
@@ -209,6 +210,7 @@ pub struct _PROCESS_INFORMATION {
 	pub dwThreadId: DWORD
 }
 #[test]
+#[cfg(target_pointer_width = "32")]
 fn bindgen_test_layout__PROCESS_INFORMATION() {
 	assert_eq!(
 		::std::mem::size_of::<_PROCESS_INFORMATION>(),
@@ -287,6 +289,7 @@ pub struct _STARTUPINFOA {
 	pub hStdError: HANDLE
 }
 #[test]
+#[cfg(target_pointer_width = "32")]
 fn bindgen_test_layout__STARTUPINFOA() {
 	assert_eq!(
 		::std::mem::size_of::<_STARTUPINFOA>(),
@@ -503,6 +506,7 @@ pub struct _STARTUPINFOW {
 	pub hStdError: HANDLE
 }
 #[test]
+#[cfg(target_pointer_width = "32")]
 fn bindgen_test_layout__STARTUPINFOW() {
 	assert_eq!(
 		::std::mem::size_of::<_STARTUPINFOW>(),
@@ -1034,6 +1038,10 @@ extern "stdcall" {
 		cbData: DWORD
 	) -> BOOL;
 }
+extern "stdcall" {
+	pub fn DetourUpdateProcessWithDll(hProcess: HANDLE, rlpDlls: *mut LPCSTR, nDlls: DWORD)
+		-> BOOL;
+}
 extern "stdcall" {
 	pub fn DetourRestoreAfterWith() -> BOOL;
 }
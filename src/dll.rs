@@ -0,0 +1,629 @@
+#[cfg(not(feature = "minhook"))]
+use crate::detours::{DetourIsHelperProcess, DetourRestoreAfterWith};
+use crate::{
+	build_info, config, control, crash,
+	detour::InFlight,
+	etw,
+	fixer::Fixer,
+	game, hook, record, trace, version,
+	vfs::{self, Vfs}
+};
+use once_cell::sync::OnceCell;
+use parking_lot::{const_mutex, Mutex};
+use std::{
+	ffi::{CStr, CString, OsString},
+	io::Error,
+	mem,
+	os::{
+		raw::{c_char, c_int},
+		windows::ffi::{OsStrExt, OsStringExt}
+	},
+	path::{Path, PathBuf},
+	ptr, slice,
+	sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+	thread::{self, JoinHandle},
+	time::{Duration, Instant}
+};
+use winapi::{
+	shared::{
+		minwindef::{BOOL, DWORD, FALSE, HINSTANCE, LPCVOID, LPDWORD, LPVOID, TRUE},
+		winerror::ERROR_ALREADY_EXISTS
+	},
+	um::{
+		debugapi::OutputDebugStringA,
+		errhandlingapi::GetLastError,
+		fileapi::{
+			CreateFileW, FindClose, FindFirstFileExW, FindFirstFileW, FindNextFileW,
+			FlushFileBuffers, GetFileSize, GetFileSizeEx, GetFileType, GetFullPathNameW, ReadFile,
+			SetFileInformationByHandle, SetFilePointer, SetFilePointerEx, WriteFile
+		},
+		handleapi::{CloseHandle, SetHandleInformation},
+		libloaderapi::{GetModuleFileNameW, GetModuleHandleW, GetProcAddress},
+		minwinbase::{
+			FILE_INFO_BY_HANDLE_CLASS, FINDEX_INFO_LEVELS, FINDEX_SEARCH_OPS, LPOVERLAPPED,
+			LPSECURITY_ATTRIBUTES, LPWIN32_FIND_DATAW
+		},
+		processthreadsapi::{CreateProcessW, LPPROCESS_INFORMATION, LPSTARTUPINFOW},
+		synchapi::CreateMutexW,
+		winnt::{
+			DLL_PROCESS_ATTACH, DLL_PROCESS_DETACH, HANDLE, LARGE_INTEGER, LONG, LPCWSTR, LPWSTR,
+			PLARGE_INTEGER, PLONG
+		}
+	}
+};
+
+const UNLOAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+const DEPENDENCIES: &[(&str, &[&str])] = &[
+	(
+		"CreateFileW",
+		&[
+			"CloseHandle",
+			"GetFileSize",
+			"GetFileSizeEx",
+			"GetFileType",
+			"ReadFile",
+			"SetFilePointer",
+			"SetFilePointerEx"
+		]
+	),
+	("FindFirstFileW", &["FindClose", "FindNextFileW"]),
+	("FindFirstFileExW", &["FindClose", "FindNextFileW"])
+];
+
+pub(crate) static FIXER: OnceCell<(Detours, Fixer)> = OnceCell::new();
+pub(crate) static MODULE: AtomicUsize = AtomicUsize::new(0);
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+static DETACHED: AtomicBool = AtomicBool::new(false);
+static INIT_ERROR: Mutex<Option<CString>> = const_mutex(None);
+static BUILD_INFO: OnceCell<CString> = OnceCell::new();
+
+detours! {
+	extern "system" fn CreateFileW(
+		lp_file_name: LPCWSTR,
+		dw_desired_access: DWORD,
+		dw_share_mode: DWORD,
+		lp_security_attributes: LPSECURITY_ATTRIBUTES,
+		dw_creation_disposition: DWORD,
+		dw_flags_and_attributes: DWORD,
+		h_template_file: HANDLE
+	) -> HANDLE;
+
+	extern "system" fn CloseHandle(h_object: HANDLE) -> BOOL;
+
+	extern "system" fn SetHandleInformation(
+		h_object: HANDLE,
+		dw_mask: DWORD,
+		dw_flags: DWORD
+	) -> BOOL;
+
+	extern "system" fn GetFileType(h_file: HANDLE) -> DWORD;
+	extern "system" fn GetFileSize(h_file: HANDLE, lp_file_size_high: LPDWORD) -> DWORD;
+	extern "system" fn GetFileSizeEx(h_file: HANDLE, lp_file_size: PLARGE_INTEGER) -> BOOL;
+
+	extern "system" fn ReadFile(
+		h_file: HANDLE,
+		lp_buffer: LPVOID,
+		n_number_of_bytes_to_read: DWORD,
+		lp_number_of_bytes_read: LPDWORD,
+		lp_overlapped: LPOVERLAPPED
+	) -> BOOL;
+
+	extern "system" fn WriteFile(
+		h_file: HANDLE,
+		lp_buffer: LPCVOID,
+		n_number_of_bytes_to_write: DWORD,
+		lp_number_of_bytes_written: LPDWORD,
+		lp_overlapped: LPOVERLAPPED
+	) -> BOOL;
+
+	extern "system" fn FlushFileBuffers(h_file: HANDLE) -> BOOL;
+
+	extern "system" fn SetFilePointer(
+		h_file: HANDLE,
+		l_distance_to_move: LONG,
+		lp_distance_to_move_high: PLONG,
+		dw_move_method: DWORD
+	) -> DWORD;
+
+	extern "system" fn SetFilePointerEx(
+		h_file: HANDLE,
+		li_distance_to_move: LARGE_INTEGER,
+		lp_new_file_pointer: PLARGE_INTEGER,
+		dw_move_method: DWORD
+	) -> BOOL;
+
+	extern "system" fn SetFileInformationByHandle(
+		h_file: HANDLE,
+		file_information_class: FILE_INFO_BY_HANDLE_CLASS,
+		lp_file_information: LPVOID,
+		dw_buffer_size: DWORD
+	) -> BOOL;
+
+	extern "system" fn GetFullPathNameW(
+		lp_file_name: LPCWSTR,
+		n_buffer_length: DWORD,
+		lp_buffer: LPWSTR,
+		lp_file_part: *mut LPWSTR
+	) -> DWORD;
+
+	extern "system" fn FindFirstFileW(
+		lp_file_name: LPCWSTR,
+		lp_find_file_data: LPWIN32_FIND_DATAW
+	) -> HANDLE;
+
+	extern "system" fn FindFirstFileExW(
+		lp_file_name: LPCWSTR,
+		f_info_level_id: FINDEX_INFO_LEVELS,
+		lp_find_file_data: LPVOID,
+		f_search_op: FINDEX_SEARCH_OPS,
+		lp_search_filter: LPVOID,
+		dw_additional_flags: DWORD
+	) -> HANDLE;
+
+	extern "system" fn FindNextFileW(
+		h_find_file: HANDLE,
+		lp_find_file_data: LPWIN32_FIND_DATAW
+	) -> BOOL;
+
+	extern "system" fn FindClose(h_find_file: HANDLE) -> BOOL;
+
+	[optional] extern "system" fn CreateProcessW(
+		lp_application_name: LPCWSTR,
+		lp_command_line: LPWSTR,
+		lp_process_attributes: LPSECURITY_ATTRIBUTES,
+		lp_thread_attributes: LPSECURITY_ATTRIBUTES,
+		b_inherit_handles: BOOL,
+		dw_creation_flags: DWORD,
+		lp_environment: LPVOID,
+		lp_current_directory: LPCWSTR,
+		lp_startup_info: LPSTARTUPINFOW,
+		lp_process_information: LPPROCESS_INFORMATION
+	) -> BOOL;
+}
+
+#[no_mangle]
+unsafe extern "system" fn DllMain(module: HINSTANCE, reason: DWORD, reserved: LPVOID) -> BOOL {
+	#[cfg(not(feature = "minhook"))]
+	if DetourIsHelperProcess() == TRUE {
+		return TRUE;
+	}
+
+	match reason {
+		DLL_PROCESS_ATTACH => {
+			MODULE.store(module as usize, Ordering::Relaxed);
+
+			#[cfg(not(feature = "minhook"))]
+			let injected = DetourRestoreAfterWith() == TRUE;
+			#[cfg(feature = "minhook")]
+			let injected = !cfg!(feature = "proxy");
+
+			if !injected && !cfg!(feature = "proxy") {
+				return TRUE;
+			}
+
+			let mut path = module_path(ptr::null_mut());
+
+			// errors are reported by init once logging is up
+			let _ = config::load(path.parent().unwrap());
+
+			if !is_host(&path) {
+				let message = format!("underrail-fix: not hooking {}\n\0", path.display());
+				OutputDebugStringA(message.as_ptr() as _);
+				return TRUE;
+			}
+
+			#[cfg(not(feature = "no-console"))]
+			attach_console();
+			path.pop();
+
+			// packing reads files on worker threads, which can't start under the loader lock, so
+			// a proxy dll finishes on its own thread once the loader lets go
+			if injected {
+				if let Err(e) = init(path, false) {
+					init_failed(&e);
+				}
+			} else {
+				thread::spawn(move || {
+					if let Err(e) = unsafe { init(path, true) } {
+						init_failed(&e);
+					}
+				});
+			}
+		}
+		DLL_PROCESS_DETACH => {
+			if let Some((_, fixer)) = FIXER.get() {
+				fixer.flush_writes();
+				fixer.report_leaks();
+
+				if reserved.is_null() {
+					if let Err(e) = detach() {
+						tracing::error!("{}", e);
+					}
+				}
+			}
+
+			etw::unregister();
+			trace::shutdown();
+		}
+		_ => {}
+	}
+
+	TRUE
+}
+
+/// # Safety
+///
+/// `path` must be null or point to a NUL-terminated UTF-16 game directory path.
+#[no_mangle]
+pub unsafe extern "C" fn UnderrailFixInit(path: LPCWSTR) -> c_int {
+	let path = if path.is_null() {
+		let mut path = module_path(ptr::null_mut());
+		path.pop();
+		path
+	} else {
+		lpcwstr_to_pathbuf(path)
+	};
+
+	match init(path, true) {
+		Ok(()) => 0,
+		Err(e) => {
+			tracing::error!("{}", e);
+			*INIT_ERROR.lock() = Some(CString::new(e).unwrap_or_default());
+			-1
+		}
+	}
+}
+
+/// # Safety
+///
+/// Thread entry point used by the launcher to initialize after late injection.
+#[no_mangle]
+pub unsafe extern "system" fn UnderrailFixAttach(_: LPVOID) -> DWORD {
+	UnderrailFixInit(ptr::null()) as DWORD
+}
+
+#[no_mangle]
+pub extern "C" fn UnderrailFixError() -> *const c_char {
+	INIT_ERROR
+		.lock()
+		.as_ref()
+		.map_or(ptr::null(), |e| e.as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn UnderrailFixBuildInfo() -> *const c_char {
+	BUILD_INFO
+		.get_or_init(|| CString::new(build_info::build_info()).unwrap())
+		.as_ptr()
+}
+
+// the game keeps running unhooked
+fn init_failed(e: &str) {
+	tracing::error!("{}", e);
+
+	let message = format!("underrail-fix: {}\n\0", e.replace('\0', ""));
+
+	unsafe {
+		OutputDebugStringA(message.as_ptr() as _);
+	}
+}
+
+unsafe fn init(path: PathBuf, create_vfs: bool) -> Result<(), String> {
+	if INITIALIZED.load(Ordering::SeqCst) {
+		tracing::info!("underrail-fix already initialized, reusing existing state");
+		return Ok(());
+	}
+
+	// checked before claiming the process so a caller can fix them and try again
+	if !path.is_dir() {
+		return Err(format!("not a directory: {}", path.display()));
+	}
+
+	config::load(&path)?;
+
+	if INITIALIZED.swap(true, Ordering::SeqCst) {
+		tracing::info!("underrail-fix already initialized, reusing existing state");
+		return Ok(());
+	}
+
+	let name: Vec<_> = format!("Local\\underrail_fix_{}\0", std::process::id())
+		.encode_utf16()
+		.collect();
+
+	if !CreateMutexW(ptr::null_mut(), FALSE, name.as_ptr()).is_null()
+		&& GetLastError() == ERROR_ALREADY_EXISTS
+	{
+		let message = format!(
+			"underrail-fix: another copy is already initialized, not hooking from {}\n\0",
+			module_path(MODULE.load(Ordering::Relaxed) as _).display()
+		);
+
+		OutputDebugStringA(message.as_ptr() as _);
+		return Ok(());
+	}
+
+	crash::install(path.clone());
+	trace::init(&path);
+	etw::register();
+	record::init();
+
+	tracing::info!(
+		"{} in {} (pid {}), game dir {}",
+		build_info::build_info(),
+		module_path(ptr::null_mut()).display(),
+		std::process::id(),
+		path.display()
+	);
+
+	vfs::detect_dirs(&path);
+	let config = config::get();
+
+	if config.disable == Some(true) {
+		tracing::info!("UNDERRAIL_FIX_DISABLE=1, not hooking");
+		return Ok(());
+	}
+
+	if create_vfs && config.no_pack != Some(true) {
+		for (i, dir) in vfs::dirs().into_iter().enumerate() {
+			if i == 0 || path.join(&dir).is_dir() {
+				Vfs::create_dir(path.clone(), &dir).map_err(|e| e.to_string())?;
+			}
+		}
+	}
+
+	for dir in vfs::dirs() {
+		let archive = path.join(vfs::archive_name(&dir));
+
+		match archive.metadata() {
+			Ok(metadata) => tracing::info!("{}: {} bytes", archive.display(), metadata.len()),
+			Err(e) => tracing::info!("{}: {}", archive.display(), e)
+		}
+	}
+
+	let archive = vfs::archive_name(&vfs::data_dir());
+
+	if !path.join(&archive).is_file() {
+		let warning = format!(
+			"{} not found in {}, running on loose files",
+			archive,
+			path.display()
+		);
+
+		tracing::warn!("{}", warning);
+		return Ok(());
+	}
+
+	if !version::check(&module_path(ptr::null_mut())) {
+		return Ok(());
+	}
+
+	let fixer = Fixer::new(path).map_err(|e| e.to_string())?;
+
+	let wine = wine_version();
+
+	if let Some(version) = &wine {
+		tracing::info!("running under Wine {}", version);
+	}
+
+	// import table patching only reaches modules loaded by now, so it is never the default
+	let iat = config
+		.hooks
+		.mode
+		.map_or(false, |s| s.eq_ignore_ascii_case("iat"));
+	hook::set_iat(iat);
+
+	let disabled: Vec<_> = config
+		.hooks
+		.disable
+		.unwrap_or_default()
+		.iter()
+		.map(|s| s.to_lowercase())
+		.collect();
+
+	if FIXER.set((Detours::new(), fixer)).is_err() {
+		tracing::warn!("FIXER singleton already initialized");
+		return Ok(());
+	}
+
+	let (detours, fixer) = FIXER.get().unwrap();
+	detours.attach(&disabled);
+
+	for (name, dependencies) in DEPENDENCIES {
+		if !detours.attached(name) {
+			continue;
+		}
+
+		if let Some(dependency) = dependencies.iter().find(|d| !detours.attached(d)) {
+			let warning = format!("{} disabled: {} is not hooked", name, dependency);
+			tracing::warn!("{}", warning);
+			detours.disable(name);
+		}
+	}
+
+	if iat && detours.enabled().is_empty() {
+		return Err(
+			"UNDERRAIL_FIX_HOOK_MODE=iat found no imports to patch, nothing is hooked".into()
+		);
+	}
+
+	tracing::info!("hooks installed: {}", detours.enabled().join(", "));
+	fixer.start(*detours.write_file.original);
+
+	control::serve();
+	Ok(())
+}
+
+// hooks that failed to detach may still jump into the fixer, so it is left running and the
+// dll must stay loaded
+unsafe fn detach() -> Result<Vec<JoinHandle<()>>, String> {
+	let (detours, fixer) = match FIXER.get() {
+		Some(fixer) if !DETACHED.swap(true, Ordering::SeqCst) => fixer,
+		_ => return Ok(Vec::new())
+	};
+
+	if let Err(error) = hook::begin() {
+		DETACHED.store(false, Ordering::SeqCst);
+		return Err(format!("begin detach: {:#x}", error));
+	}
+
+	let mut errors = detours.detach();
+
+	let error = hook::commit();
+
+	if error != 0 {
+		let message = format!("commit detach: {:#x}", error);
+		tracing::error!("{}", message);
+		errors.push(message);
+	}
+
+	if !errors.is_empty() {
+		return Err(errors.join(", "));
+	}
+
+	Ok(fixer.close())
+}
+
+pub(crate) unsafe fn unload() -> Result<(), String> {
+	if let Some((_, fixer)) = FIXER.get() {
+		fixer.flush_writes();
+		fixer.report_leaks();
+	}
+
+	for thread in detach()? {
+		let _ = thread.join();
+	}
+
+	// the hooks are gone, so the count only goes down, unless a call is blocked for good
+	let start = Instant::now();
+
+	while InFlight::count() != 0 {
+		if start.elapsed() >= UNLOAD_TIMEOUT {
+			return Err(format!(
+				"{} hooked calls still running after {}s",
+				InFlight::count(),
+				UNLOAD_TIMEOUT.as_secs()
+			));
+		}
+
+		thread::sleep(Duration::from_millis(10));
+	}
+
+	tracing::info!("detached, unloading");
+	crash::uninstall();
+	etw::unregister();
+	trace::shutdown();
+	Ok(())
+}
+
+fn is_host(exe: &Path) -> bool {
+	let name = match exe.file_name() {
+		Some(name) => name.to_string_lossy().to_lowercase(),
+		None => return false
+	};
+
+	let custom = config::get().exe;
+
+	game::is_game(exe)
+		|| custom.map_or(false, |exe| {
+			exe.file_name()
+				.map_or(false, |n| n.to_string_lossy().to_lowercase() == name)
+		})
+}
+
+fn wine_version() -> Option<String> {
+	unsafe {
+		let ntdll: Vec<_> = "ntdll.dll\0".encode_utf16().collect();
+		let ntdll = GetModuleHandleW(ntdll.as_ptr());
+
+		if ntdll.is_null() {
+			return None;
+		}
+
+		let proc = GetProcAddress(ntdll, b"wine_get_version\0".as_ptr() as _);
+
+		if proc.is_null() {
+			return None;
+		}
+
+		let get_version: unsafe extern "C" fn() -> *const c_char = mem::transmute(proc);
+		Some(CStr::from_ptr(get_version()).to_string_lossy().into_owned())
+	}
+}
+
+#[cfg(not(feature = "no-console"))]
+fn attach_console() {
+	use winapi::um::{
+		consoleapi::AllocConsole,
+		wincon::{AttachConsole, ATTACH_PARENT_PROCESS}
+	};
+
+	unsafe {
+		if wine_version().is_none() && AttachConsole(ATTACH_PARENT_PROCESS) != 0 {
+			return;
+		}
+
+		if config::get().console == Some(true) {
+			AllocConsole();
+		}
+	}
+}
+
+pub(crate) fn hook_enabled(disabled: &[String], name: &str) -> bool {
+	let name = name.to_lowercase();
+
+	!disabled.iter().any(|d| {
+		if d.ends_with('*') {
+			name.starts_with(&d[..d.len() - 1])
+		} else {
+			*d == name
+		}
+	})
+}
+
+pub(crate) fn lpcwstr_to_slice<'a>(s: LPCWSTR) -> &'a [u16] {
+	assert!(!s.is_null());
+
+	unsafe {
+		let mut len = 0;
+
+		for i in 0.. {
+			if *s.add(i) == 0 {
+				len = i;
+				break;
+			}
+		}
+
+		slice::from_raw_parts(s, len)
+	}
+}
+
+pub(crate) fn slice_to_pathbuf(s: &[u16]) -> PathBuf {
+	PathBuf::from(OsString::from_wide(s))
+}
+
+pub(crate) fn lpcwstr_to_pathbuf(s: LPCWSTR) -> PathBuf {
+	slice_to_pathbuf(lpcwstr_to_slice(s))
+}
+
+pub(crate) fn module_path(module: HINSTANCE) -> PathBuf {
+	let mut path = vec![0; 1 << 10];
+
+	loop {
+		let len = unsafe { GetModuleFileNameW(module, path.as_mut_ptr(), path.len() as _) };
+
+		if len == 0 {
+			panic!("GetModuleFileNameW: {}", Error::last_os_error());
+		} else if len >= path.len() as _ {
+			path.resize(path.len() * 2, 0);
+		} else {
+			break lpcwstr_to_pathbuf(path.as_ptr());
+		}
+	}
+}
+
+pub(crate) fn path_to_wide(path: &Path) -> Vec<u16> {
+	let mut wide: Vec<_> = path.as_os_str().encode_wide().collect();
+	wide.push(0);
+	wide
+}
@@ -0,0 +1,112 @@
+use crate::{elevate, game, inject, vfs};
+use std::{
+	fs::File,
+	io::{ErrorKind, Read},
+	iter,
+	path::Path,
+	ptr
+};
+use winapi::um::libloaderapi::{FreeLibrary, LoadLibraryExW, LOAD_LIBRARY_AS_DATAFILE};
+
+const VC_RUNTIME: &str = "vcruntime140.dll";
+
+pub fn problems(path: &Path, exe: &Path) -> Vec<String> {
+	let mut problems = Vec::new();
+
+	if !exe.is_file() {
+		problems.push(format!(
+			"{} not found, pass --exe or set UNDERRAIL_EXE to the game executable",
+			exe.display()
+		));
+	} else if !game::is_game(exe) {
+		problems.push(format!(
+			"{} is neither named {} nor built as the game, add its name to \
+			 UNDERRAIL_FIX_GAME_EXES if the game was renamed",
+			exe.display(),
+			game::exe_names().join(" or ")
+		));
+	}
+
+	for (i, dir) in vfs::dirs().into_iter().enumerate() {
+		let full = vfs::source_dir(path, &dir);
+
+		if !full.is_dir() {
+			if i == 0 {
+				problems.push(format!(
+					"{} not found, check UNDERRAIL_FIX_DATA_DIR or verify the game files",
+					full.display()
+				));
+			}
+
+			continue;
+		}
+
+		let archive = path.join(vfs::archive_name(&dir));
+
+		// the same top level mtime check that pack uses
+		match (full.metadata(), archive.metadata()) {
+			(_, Err(_)) => problems.push(format!("{} not packed yet, run pack", full.display())),
+			(Ok(dir_m), Ok(archive_m))
+				if dir_m.modified().unwrap() > archive_m.modified().unwrap() =>
+			{
+				problems.push(format!(
+					"{} is older than {}, run pack",
+					archive.display(),
+					full.display()
+				))
+			}
+			_ => {}
+		}
+	}
+
+	let dll = inject::dll_path();
+
+	match File::open(&dll).and_then(|mut file| file.read(&mut [0; 2])) {
+		Ok(_) => {}
+		Err(e) if e.kind() == ErrorKind::NotFound => {
+			problems.push(format!(
+				"{} not found, check your antivirus quarantine or reinstall",
+				dll.display()
+			))
+		}
+		Err(e) => {
+			problems.push(format!(
+				"{} can't be read ({}), it may be blocked by your antivirus",
+				dll.display(),
+				e
+			))
+		}
+	}
+
+	if !loadable(VC_RUNTIME) {
+		problems.push(format!(
+			"{} not found, install the Microsoft Visual C++ Redistributable",
+			VC_RUNTIME
+		));
+	}
+
+	if path.is_dir() && !elevate::writable(path) {
+		problems.push(format!(
+			"{} is not writable, run as administrator or let the launcher use a per-user archive \
+			 directory",
+			path.display()
+		));
+	}
+
+	problems
+}
+
+fn loadable(name: &str) -> bool {
+	let name: Vec<_> = name.encode_utf16().chain(iter::once(0)).collect();
+
+	unsafe {
+		let module = LoadLibraryExW(name.as_ptr(), ptr::null_mut(), LOAD_LIBRARY_AS_DATAFILE);
+
+		if module.is_null() {
+			false
+		} else {
+			FreeLibrary(module);
+			true
+		}
+	}
+}
@@ -0,0 +1,97 @@
+use crate::config;
+use std::{
+	env,
+	fs::{self, OpenOptions},
+	io::{self, BufRead, Write},
+	iter,
+	os::windows::ffi::OsStrExt,
+	path::{Path, PathBuf},
+	process, ptr
+};
+use winapi::um::{shellapi::ShellExecuteW, winuser::SW_SHOWNORMAL};
+
+// Program Files installs can't be packed without admin rights, so either relaunch elevated or
+// keep the archives in a per-user directory that the injected DLL inherits through the env
+pub fn check(path: &Path, parameters: &[u16]) {
+	if config::get().archive_dir.is_some() || writable(path) {
+		return;
+	}
+
+	print!(
+		"{} is not writable. Relaunch as administrator? [y/N] ",
+		path.display()
+	);
+
+	io::stdout().flush().ok();
+	let mut answer = String::new();
+	io::stdin().lock().read_line(&mut answer).ok();
+
+	if answer.trim().eq_ignore_ascii_case("y") {
+		unsafe { relaunch(parameters) };
+	}
+
+	assert!(
+		config::get().portable != Some(true),
+		"{} is not writable and portable mode keeps the archives beside the game",
+		path.display()
+	);
+
+	let dir = archive_dir();
+
+	fs::create_dir_all(&dir).expect(&format!("failed to create dir: {}", dir.display()));
+	tracing::warn!(
+		"{} not writable, storing archives in {}",
+		path.display(),
+		dir.display()
+	);
+	env::set_var("UNDERRAIL_FIX_ARCHIVE_DIR", dir);
+}
+
+pub fn writable(path: &Path) -> bool {
+	let probe = path.join(".underrail-fix-write-test");
+
+	match OpenOptions::new().write(true).create(true).open(&probe) {
+		Ok(_) => {
+			fs::remove_file(&probe).ok();
+			true
+		}
+		Err(e) if e.kind() == io::ErrorKind::PermissionDenied => false,
+		Err(e) => panic!("failed to write to {}: {}", path.display(), e)
+	}
+}
+
+fn archive_dir() -> PathBuf {
+	env::var_os("LOCALAPPDATA")
+		.map(PathBuf::from)
+		.unwrap_or_else(env::temp_dir)
+		.join("underrail-fix")
+}
+
+unsafe fn relaunch(parameters: &[u16]) -> ! {
+	let exe: Vec<_> = env::current_exe()
+		.expect("failed to get current exe path")
+		.as_os_str()
+		.encode_wide()
+		.chain(iter::once(0))
+		.collect();
+
+	let verb: Vec<_> = "runas\0".encode_utf16().collect();
+
+	let instance = ShellExecuteW(
+		ptr::null_mut(),
+		verb.as_ptr(),
+		exe.as_ptr(),
+		parameters.as_ptr(),
+		ptr::null(),
+		SW_SHOWNORMAL
+	);
+
+	// values above 32 indicate success
+	assert!(
+		instance as usize > 32,
+		"ShellExecuteW runas: {}",
+		io::Error::last_os_error()
+	);
+
+	process::exit(0);
+}
@@ -0,0 +1,90 @@
+use crate::path_to_wide;
+use std::{
+	path::Path,
+	ptr,
+	sync::atomic::{AtomicU64, Ordering}
+};
+use winapi::shared::{
+	evntprov::{EventProviderEnabled, EventRegister, EventUnregister, EventWriteString},
+	guiddef::GUID
+};
+
+// {6F3B3B0E-3C7A-4A55-9B8E-6D2A2F7C1E41}
+const PROVIDER_ID: GUID = GUID {
+	Data1: 0x6f3b3b0e,
+	Data2: 0x3c7a,
+	Data3: 0x4a55,
+	Data4: [0x9b, 0x8e, 0x6d, 0x2a, 0x2f, 0x7c, 0x1e, 0x41]
+};
+
+const LEVEL_INFORMATION: u8 = 4;
+const LEVEL_VERBOSE: u8 = 5;
+
+const KEYWORD_OPEN: u64 = 0x1;
+const KEYWORD_READ: u64 = 0x2;
+const KEYWORD_FIND: u64 = 0x4;
+
+static PROVIDER: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn register() {
+	let mut handle = 0;
+
+	let error = unsafe { EventRegister(&PROVIDER_ID, None, ptr::null_mut(), &mut handle) };
+
+	if error == 0 {
+		PROVIDER.store(handle, Ordering::Relaxed);
+	} else {
+		tracing::warn!("EventRegister: {:#x}", error);
+	}
+}
+
+pub(crate) fn unregister() {
+	let handle = PROVIDER.swap(0, Ordering::Relaxed);
+
+	if handle != 0 {
+		unsafe {
+			EventUnregister(handle);
+		}
+	}
+}
+
+pub(crate) fn open(path: &Path, source: &str) {
+	write(LEVEL_INFORMATION, KEYWORD_OPEN, || {
+		format!("open source={} path={}", source, path.display())
+	});
+}
+
+pub(crate) fn read(path: &Path, offset: u64, requested: u32, read: usize) {
+	write(LEVEL_VERBOSE, KEYWORD_READ, || {
+		format!(
+			"read path={} offset={} requested={} read={}",
+			path.display(),
+			offset,
+			requested,
+			read
+		)
+	});
+}
+
+pub(crate) fn find(path: &Path, entries: usize) {
+	write(LEVEL_INFORMATION, KEYWORD_FIND, || {
+		format!("find path={} entries={}", path.display(), entries)
+	});
+}
+
+fn write<F>(level: u8, keyword: u64, message: F)
+where
+	F: FnOnce() -> String
+{
+	let handle = PROVIDER.load(Ordering::Relaxed);
+
+	if handle == 0 || unsafe { EventProviderEnabled(handle, level, keyword) } == 0 {
+		return;
+	}
+
+	let message = path_to_wide(message().as_ref());
+
+	unsafe {
+		EventWriteString(handle, level, keyword, message.as_ptr());
+	}
+}
@@ -1,49 +1,151 @@
 use crate::{
-	lpcwstr_to_pathbuf, lpcwstr_to_slice, slice_to_pathbuf,
-	vfs::{Entry, Reader, Vfs},
-	CloseHandleArgs, CreateFileWArgs, FindCloseArgs, FindFirstFileWArgs, FindNextFileWArgs,
-	GetFileSizeArgs, GetFileTypeArgs, GetFullPathNameWArgs, ReadFileArgs, SetFilePointerArgs
+	config, etw, hook, lpcwstr_to_pathbuf, lpcwstr_to_slice, module_path,
+	overlay::Overlay,
+	path_to_wide, record, slice_to_pathbuf,
+	stats::Stats,
+	vfs::{self, Entry, Reader, Vfs},
+	writes::Writes,
+	CloseHandleArgs, CreateFileWArgs, CreateProcessWArgs, FindCloseArgs, FindFirstFileExWArgs,
+	FindFirstFileWArgs, FindNextFileWArgs, FlushFileBuffersArgs, GetFileSizeArgs,
+	GetFileSizeExArgs, GetFileTypeArgs, GetFullPathNameWArgs, ReadFileArgs,
+	SetFileInformationByHandleArgs, SetFilePointerArgs, SetFilePointerExArgs,
+	SetHandleInformationArgs, WriteFileArgs, WriteFileFn, MODULE
 };
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use std::{
+	collections::{HashMap, HashSet},
 	fs::File,
-	io::{Read, Seek, SeekFrom},
+	io::{self, Error, Read, Seek, SeekFrom},
 	mem,
 	os::windows::io::IntoRawHandle,
-	path::PathBuf,
-	ptr, slice
+	path::{Path, PathBuf},
+	ptr, slice,
+	sync::{atomic::Ordering, Arc},
+	thread::JoinHandle,
+	time::{SystemTime, UNIX_EPOCH}
 };
+use tracing::{field::display, Span};
 use winapi::{
 	shared::{
 		minwindef::{BOOL, DWORD, FALSE, TRUE},
-		winerror::{ERROR_FILE_NOT_FOUND, ERROR_NEGATIVE_SEEK, ERROR_NO_MORE_FILES, NO_ERROR}
+		winerror::{
+			ERROR_ACCESS_DENIED, ERROR_FILE_NOT_FOUND, ERROR_INVALID_HANDLE,
+			ERROR_INVALID_PARAMETER, ERROR_NEGATIVE_SEEK, ERROR_NOACCESS, ERROR_NO_MORE_FILES,
+			ERROR_PATH_NOT_FOUND, ERROR_READ_FAULT, ERROR_SHARING_VIOLATION, NO_ERROR
+		}
 	},
 	um::{
 		errhandlingapi::SetLastError,
-		fileapi::INVALID_SET_FILE_POINTER,
-		handleapi::INVALID_HANDLE_VALUE,
-		minwinbase::{LPWIN32_FIND_DATAW, WIN32_FIND_DATAW},
-		winbase::{FILE_BEGIN, FILE_CURRENT, FILE_END, FILE_TYPE_DISK},
-		winnt::{FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_NORMAL, GENERIC_READ, HANDLE}
+		fileapi::{INVALID_FILE_SIZE, INVALID_SET_FILE_POINTER, OPEN_EXISTING},
+		handleapi::{
+			CloseHandle, DuplicateHandle, GetHandleInformation, SetHandleInformation,
+			INVALID_HANDLE_VALUE
+		},
+		memoryapi::{CreateFileMappingW, VirtualQuery},
+		minwinbase::{
+			FileAllocationInfo, FileBasicInfo, FileDispositionInfo, FileDispositionInfoEx,
+			FileEndOfFileInfo, FileIoPriorityHintInfo, FileRenameInfo, FileRenameInfoEx,
+			FindExInfoBasic, FindExInfoStandard, FindExSearchLimitToDirectories,
+			FindExSearchNameMatch, FIND_FIRST_EX_CASE_SENSITIVE, LPWIN32_FIND_DATAW,
+			WIN32_FIND_DATAW
+		},
+		processthreadsapi::{GetCurrentProcess, ResumeThread},
+		winbase::{
+			CREATE_SUSPENDED, FILE_BEGIN, FILE_CURRENT, FILE_END, FILE_FLAG_NO_BUFFERING,
+			FILE_FLAG_SEQUENTIAL_SCAN, FILE_TYPE_DISK, HANDLE_FLAG_INHERIT
+		},
+		winnt::{
+			DELETE, DUPLICATE_SAME_ACCESS, FILE_APPEND_DATA, FILE_ATTRIBUTE_DIRECTORY,
+			FILE_ATTRIBUTE_NORMAL, FILE_READ_DATA, FILE_SHARE_READ, FILE_WRITE_ATTRIBUTES,
+			FILE_WRITE_DATA, FILE_WRITE_EA, GENERIC_ALL, GENERIC_READ, GENERIC_WRITE, HANDLE,
+			MEMORY_BASIC_INFORMATION, MEM_COMMIT, PAGE_EXECUTE_READWRITE, PAGE_EXECUTE_WRITECOPY,
+			PAGE_GUARD, PAGE_NOACCESS, PAGE_READWRITE, PAGE_WRITECOPY, WRITE_DAC, WRITE_OWNER
+		}
 	}
 };
 
+const SECTOR_SIZE: usize = 512;
+const CLOSED_LIMIT: usize = 4096;
+const WRITE_ACCESS: DWORD = GENERIC_WRITE
+	| GENERIC_ALL
+	| FILE_WRITE_DATA
+	| FILE_APPEND_DATA
+	| FILE_WRITE_EA
+	| FILE_WRITE_ATTRIBUTES
+	| DELETE
+	| WRITE_DAC
+	| WRITE_OWNER;
+
 pub(crate) struct Fixer {
-	vfs: Vfs,
-	create: (HANDLE, Mutex<Option<Reader<'static>>>),
-	find: (
-		HANDLE,
-		Mutex<Option<(Vec<(&'static str, &'static Entry)>, usize)>>
-	)
+	vfs: RwLock<Vec<Arc<Vfs>>>,
+	overlay: Overlay,
+	stats: &'static Stats,
+	// each handle has its own lock, so reads on different handles don't wait on each other
+	create: (HANDLE, Mutex<HashMap<usize, Arc<Mutex<Handle>>>>),
+	find: (HANDLE, Mutex<HashMap<usize, Find>>),
+	closed: Mutex<HashSet<usize>>,
+	writes: Writes
+}
+
+struct Handle {
+	path: PathBuf,
+	access: DWORD,
+	share: DWORD,
+	reader: Reader,
+	no_buffering: bool,
+	opened: SystemTime
+}
+
+struct Find {
+	path: PathBuf,
+	entries: Vec<(String, Entry)>,
+	index: usize,
+	opened: SystemTime
 }
 
 impl Fixer {
-	pub(crate) fn new(path: PathBuf) -> Self {
-		Self {
-			vfs: Vfs::open(path),
-			create: (create_temp_file("create"), Mutex::new(None)),
-			find: (create_temp_file("create"), Mutex::new(None))
+	pub(crate) fn new(path: PathBuf) -> io::Result<Self> {
+		let overlay = Overlay::new(path.join(vfs::overlay_dir()));
+		overlay.watch();
+
+		// packs come first so their files take priority over the base archives
+		let mut vfs: Vec<_> = vfs::packs(&path)
+			.into_iter()
+			.filter_map(|pack| {
+				match Vfs::open_pack(path.clone(), &pack) {
+					Ok(vfs) => {
+						tracing::info!("using pack {}", pack.display());
+						Some(Arc::new(vfs))
+					}
+					Err(e) => {
+						tracing::warn!("skipping pack {}: {}", pack.display(), e);
+						None
+					}
+				}
+			})
+			.collect();
+
+		for (i, dir) in vfs::dirs().into_iter().enumerate() {
+			if i == 0 || path.join(vfs::archive_name(&dir)).is_file() {
+				vfs.push(Arc::new(Vfs::open_dir(path.clone(), &dir)?));
+			} else {
+				tracing::warn!(
+					"{} not found, skipping {}",
+					vfs::archive_name(&dir),
+					dir.display()
+				);
+			}
 		}
+
+		Ok(Self {
+			vfs: RwLock::new(vfs),
+			overlay,
+			stats: Stats::shared(),
+			create: (create_temp_file("create"), Mutex::new(HashMap::new())),
+			find: (create_temp_file("find"), Mutex::new(HashMap::new())),
+			closed: Mutex::new(HashSet::new()),
+			writes: Writes::new(&path)
+		})
 	}
 
 	pub(crate) fn create_file_w<F>(&self, args: CreateFileWArgs, create_file_w: F) -> HANDLE
@@ -51,21 +153,90 @@ impl Fixer {
 		F: Fn(CreateFileWArgs) -> HANDLE
 	{
 		let path = lpcwstr_to_pathbuf(args.lp_file_name);
+		Span::current().record("path", &display(path.display()));
 
-		match self.vfs.read(&path) {
-			Some(r) => {
-				assert_eq!(args.dw_desired_access, GENERIC_READ);
+		let vfs = self.vfs(&path);
+
+		if let Some(overlay) = vfs
+			.as_ref()
+			.and_then(|v| v.key(&path))
+			.and_then(|k| self.overlay.get(&k))
+		{
+			etw::open(&overlay, "overlay");
+			let overlay = path_to_wide(&overlay);
+			Stats::add(&self.stats.overlay, 1);
+
+			return create_file_w(CreateFileWArgs {
+				lp_file_name: overlay.as_ptr(),
+				..args
+			});
+		}
 
-				let mut reader = self.create.1.lock();
-				assert!(reader.is_none());
+		// the archives are read-only, anything else goes to the real file system
+		if args.dw_desired_access & WRITE_ACCESS != 0
+			|| args.dw_creation_disposition != OPEN_EXISTING
+		{
+			let handle = create_file_w(args);
 
+			if handle != INVALID_HANDLE_VALUE {
+				self.writes.track(&path, handle);
+			}
+
+			return handle;
+		}
+
+		match vfs.as_ref().and_then(|v| v.read(&path)) {
+			Some(r) => {
 				match r {
-					Some(r) => {
-						let r = unsafe { mem::transmute::<Reader, Reader<'static>>(r) };
-						*reader = Some(r);
-						self.create.0
+					Some(mut r) => {
+						if args.dw_flags_and_attributes & FILE_FLAG_SEQUENTIAL_SCAN != 0 {
+							r.prefetch();
+						}
+
+						let path = vfs.as_ref().unwrap().key(&path).unwrap();
+						let mut handles = self.create.1.lock();
+
+						if handles.values().any(|h| {
+							let h = h.lock();
+
+							h.path == path
+								&& share_conflict(
+									h.access,
+									h.share,
+									args.dw_desired_access,
+									args.dw_share_mode
+								)
+						}) {
+							unsafe {
+								SetLastError(ERROR_SHARING_VIOLATION);
+							}
+
+							return INVALID_HANDLE_VALUE;
+						}
+
+						etw::open(&path, "vfs");
+						let handle = self.pseudo_handle(self.create.0);
+
+						handles.insert(
+							handle as usize,
+							Arc::new(Mutex::new(Handle {
+								path,
+								access: args.dw_desired_access,
+								share: args.dw_share_mode,
+								reader: r,
+								no_buffering: args.dw_flags_and_attributes & FILE_FLAG_NO_BUFFERING
+									!= 0,
+								opened: SystemTime::now()
+							}))
+						);
+
+						Stats::add(&self.stats.opens, 1);
+						Stats::add(&self.stats.handles, 1);
+						handle
 					}
 					None => {
+						Stats::add(&self.stats.misses, 1);
+
 						unsafe {
 							SetLastError(ERROR_FILE_NOT_FOUND);
 						}
@@ -74,21 +245,89 @@ impl Fixer {
 					}
 				}
 			}
-			None => create_file_w(args)
+			None => {
+				let handle = create_file_w(args);
+
+				if handle != INVALID_HANDLE_VALUE {
+					self.writes.track(&path, handle);
+				}
+
+				handle
+			}
 		}
 	}
 
+	pub(crate) fn start(&'static self, write_file: WriteFileFn) {
+		self.writes.start(write_file);
+	}
+
+	pub(crate) fn flush_writes(&self) {
+		self.writes.flush_all();
+	}
+
+	pub(crate) fn write_file<F>(&self, args: WriteFileArgs, write_file: F) -> BOOL
+	where
+		F: Fn(WriteFileArgs) -> BOOL
+	{
+		self.writes.write(args).unwrap_or_else(|| write_file(args))
+	}
+
+	pub(crate) fn flush_file_buffers<F>(
+		&self,
+		args: FlushFileBuffersArgs,
+		flush_file_buffers: F
+	) -> BOOL
+	where
+		F: Fn(FlushFileBuffersArgs) -> BOOL
+	{
+		if self.writes.flush(args.h_file) == FALSE {
+			return FALSE;
+		}
+
+		flush_file_buffers(args)
+	}
+
 	pub(crate) fn close_handle<F>(&self, args: CloseHandleArgs, close_handle: F) -> BOOL
 	where
 		F: Fn(CloseHandleArgs) -> BOOL
 	{
-		if args.h_object == self.create.0 {
-			let mut reader = self.create.1.lock();
-			assert!(reader.is_some());
-			*reader = None;
-			TRUE
+		if self
+			.create
+			.1
+			.lock()
+			.remove(&(args.h_object as usize))
+			.is_some()
+		{
+			Stats::sub(&self.stats.handles, 1);
+			self.mark_closed(args.h_object);
+		} else if self.is_closed(args.h_object) {
+			unsafe { SetLastError(ERROR_INVALID_HANDLE) };
+			return FALSE;
 		} else {
-			close_handle(args)
+			self.writes.close(args.h_object);
+		}
+
+		close_handle(args)
+	}
+
+	pub(crate) fn set_handle_information<F>(
+		&self,
+		args: SetHandleInformationArgs,
+		set_handle_information: F
+	) -> BOOL
+	where
+		F: Fn(SetHandleInformationArgs) -> BOOL
+	{
+		if self.is_pseudo_handle(args.h_object) {
+			let dw_mask = args.dw_mask & !HANDLE_FLAG_INHERIT;
+
+			if dw_mask == 0 {
+				TRUE
+			} else {
+				set_handle_information(SetHandleInformationArgs { dw_mask, ..args })
+			}
+		} else {
+			set_handle_information(args)
 		}
 	}
 
@@ -96,11 +335,9 @@ impl Fixer {
 	where
 		F: Fn(GetFileTypeArgs) -> DWORD
 	{
-		if args.h_file == self.create.0 {
-			assert!(self.create.1.lock().is_some());
-			FILE_TYPE_DISK
-		} else {
-			get_file_type(args)
+		match self.with_handle(args.h_file, |_| FILE_TYPE_DISK) {
+			Some(ty) => ty,
+			None => get_file_type(args)
 		}
 	}
 
@@ -108,20 +345,49 @@ impl Fixer {
 	where
 		F: Fn(GetFileSizeArgs) -> DWORD
 	{
-		if args.h_file == self.create.0 {
-			let mut reader = self.create.1.lock();
-			let reader = reader.as_mut().unwrap();
-			let len = reader.len();
+		self.writes.flush(args.h_file);
 
-			if !args.lp_file_size_high.is_null() {
+		match self.with_handle(args.h_file, |h| h.reader.len()) {
+			Some(len) => {
 				unsafe {
-					*args.lp_file_size_high = 0;
+					if !args.lp_file_size_high.is_null() {
+						if !writable(args.lp_file_size_high, 1) {
+							SetLastError(ERROR_NOACCESS);
+							return INVALID_FILE_SIZE;
+						}
+
+						*args.lp_file_size_high = (len >> 32) as u32;
+					}
+
+					SetLastError(NO_ERROR);
 				}
+
+				len as u32
 			}
+			None => get_file_size(args)
+		}
+	}
 
-			len as u32
-		} else {
-			get_file_size(args)
+	pub(crate) fn get_file_size_ex<F>(&self, args: GetFileSizeExArgs, get_file_size_ex: F) -> BOOL
+	where
+		F: Fn(GetFileSizeExArgs) -> BOOL
+	{
+		self.writes.flush(args.h_file);
+
+		match self.with_handle(args.h_file, |h| h.reader.len()) {
+			Some(len) => {
+				unsafe {
+					if !writable(args.lp_file_size, 1) {
+						SetLastError(ERROR_NOACCESS);
+						return FALSE;
+					}
+
+					*(*args.lp_file_size).QuadPart_mut() = len as i64;
+				}
+
+				TRUE
+			}
+			None => get_file_size_ex(args)
 		}
 	}
 
@@ -129,12 +395,28 @@ impl Fixer {
 	where
 		F: Fn(ReadFileArgs) -> BOOL
 	{
-		if args.h_file == self.create.0 {
-			assert!(!args.lp_number_of_bytes_read.is_null());
-			assert!(args.lp_overlapped.is_null());
+		self.writes.flush(args.h_file);
 
-			let mut reader = self.create.1.lock();
-			let reader = reader.as_mut().unwrap();
+		let b = self.with_handle(args.h_file, |h| {
+			if !args.lp_overlapped.is_null() || !writable(args.lp_number_of_bytes_read, 1) {
+				unsafe {
+					SetLastError(ERROR_INVALID_PARAMETER);
+				}
+
+				return FALSE;
+			}
+
+			if !writable(
+				args.lp_buffer as *mut u8,
+				args.n_number_of_bytes_to_read as usize
+			) {
+				unsafe {
+					*args.lp_number_of_bytes_read = 0;
+					SetLastError(ERROR_NOACCESS);
+				}
+
+				return FALSE;
+			}
 
 			let buf = unsafe {
 				slice::from_raw_parts_mut(
@@ -143,8 +425,27 @@ impl Fixer {
 				)
 			};
 
-			match reader.read(buf) {
+			let offset = h.reader.position();
+
+			if h.no_buffering
+				&& (args.lp_buffer as usize % SECTOR_SIZE != 0
+					|| buf.len() % SECTOR_SIZE != 0
+					|| offset % SECTOR_SIZE as u64 != 0)
+			{
+				unsafe {
+					*args.lp_number_of_bytes_read = 0;
+					SetLastError(ERROR_INVALID_PARAMETER);
+				}
+
+				return FALSE;
+			}
+
+			match h.reader.read(buf) {
 				Ok(read) => {
+					etw::read(&h.path, offset, args.n_number_of_bytes_to_read, read);
+					record::read(&h.path, offset, read);
+					Stats::add(&self.stats.bytes_read, read as u64);
+
 					unsafe {
 						*args.lp_number_of_bytes_read = read as u32;
 					}
@@ -154,14 +455,17 @@ impl Fixer {
 				Err(e) => {
 					unsafe {
 						*args.lp_number_of_bytes_read = 0;
-						SetLastError(e.raw_os_error().unwrap() as u32);
+						SetLastError(e.raw_os_error().map_or(ERROR_READ_FAULT, |e| e as u32));
 					}
 
 					FALSE
 				}
 			}
-		} else {
-			read_file(args)
+		});
+
+		match b {
+			Some(b) => b,
+			None => read_file(args)
 		}
 	}
 
@@ -169,50 +473,145 @@ impl Fixer {
 	where
 		F: Fn(SetFilePointerArgs) -> DWORD
 	{
-		if args.h_file == self.create.0 {
-			let mut reader = self.create.1.lock();
-			let reader = reader.as_mut().unwrap();
+		self.writes.flush(args.h_file);
 
-			let o = unsafe {
-				SetLastError(NO_ERROR);
+		let pos = self.with_handle(args.h_file, |h| {
+			if !args.lp_distance_to_move_high.is_null()
+				&& !writable(args.lp_distance_to_move_high, 1)
+			{
+				unsafe {
+					SetLastError(ERROR_NOACCESS);
+				}
 
+				return INVALID_SET_FILE_POINTER;
+			}
+
+			let distance = unsafe {
 				if args.lp_distance_to_move_high.is_null() {
 					args.l_distance_to_move as i64
 				} else {
-					(args.l_distance_to_move as u64
-						| ((*args.lp_distance_to_move_high as u64) << 32)) as i64
+					((*args.lp_distance_to_move_high as i64) << 32)
+						| args.l_distance_to_move as u32 as i64
 				}
 			};
 
-			let from = match args.dw_move_method {
-				FILE_BEGIN => {
-					if o < 0 {
-						unsafe {
-							SetLastError(ERROR_NEGATIVE_SEEK);
+			// a position the low dword alone can't report is refused without moving the pointer
+			let pos = target(&h.reader, distance, args.dw_move_method).and_then(|pos| {
+				if args.lp_distance_to_move_high.is_null() && pos > u32::MAX as u64 {
+					Err(ERROR_INVALID_PARAMETER)
+				} else {
+					seek(&mut h.reader, pos as i64, FILE_BEGIN)
+				}
+			});
+
+			let error = match pos {
+				Ok(pos) => {
+					unsafe {
+						if !args.lp_distance_to_move_high.is_null() {
+							*args.lp_distance_to_move_high = (pos >> 32) as i32;
 						}
 
-						return INVALID_SET_FILE_POINTER;
+						SetLastError(NO_ERROR);
 					}
 
-					SeekFrom::Start(o as u64)
+					return pos as u32;
 				}
-				FILE_CURRENT => SeekFrom::Current(o),
-				FILE_END => SeekFrom::End(o),
-				_ => unreachable!("set_file_pointer dw_move_method: {}", args.dw_move_method)
+				Err(error) => error
 			};
 
-			match reader.seek(from) {
-				Ok(pos) => pos as u32,
-				Err(_) => {
+			unsafe {
+				SetLastError(error);
+			}
+
+			INVALID_SET_FILE_POINTER
+		});
+
+		match pos {
+			Some(pos) => pos,
+			None => set_file_pointer(args)
+		}
+	}
+
+	pub(crate) fn set_file_pointer_ex<F>(
+		&self,
+		args: SetFilePointerExArgs,
+		set_file_pointer_ex: F
+	) -> BOOL
+	where
+		F: Fn(SetFilePointerExArgs) -> BOOL
+	{
+		self.writes.flush(args.h_file);
+
+		let b = self.with_handle(args.h_file, |h| {
+			if !args.lp_new_file_pointer.is_null() && !writable(args.lp_new_file_pointer, 1) {
+				unsafe {
+					SetLastError(ERROR_NOACCESS);
+				}
+
+				return FALSE;
+			}
+
+			let distance = unsafe { *args.li_distance_to_move.QuadPart() };
+
+			match seek(&mut h.reader, distance, args.dw_move_method) {
+				Ok(pos) => {
+					if !args.lp_new_file_pointer.is_null() {
+						unsafe {
+							*(*args.lp_new_file_pointer).QuadPart_mut() = pos as i64;
+						}
+					}
+
+					TRUE
+				}
+				Err(error) => {
 					unsafe {
-						SetLastError(ERROR_NEGATIVE_SEEK);
+						SetLastError(error);
 					}
 
-					INVALID_SET_FILE_POINTER
+					FALSE
 				}
 			}
-		} else {
-			set_file_pointer(args)
+		});
+
+		match b {
+			Some(b) => b,
+			None => set_file_pointer_ex(args)
+		}
+	}
+
+	pub(crate) fn set_file_information_by_handle<F>(
+		&self,
+		args: SetFileInformationByHandleArgs,
+		set_file_information_by_handle: F
+	) -> BOOL
+	where
+		F: Fn(SetFileInformationByHandleArgs) -> BOOL
+	{
+		self.writes.flush(args.h_file);
+
+		let b = self.with_handle(args.h_file, |_| {
+			let error = match args.file_information_class {
+				FileIoPriorityHintInfo => return TRUE,
+				FileBasicInfo
+				| FileRenameInfo
+				| FileRenameInfoEx
+				| FileDispositionInfo
+				| FileDispositionInfoEx
+				| FileAllocationInfo
+				| FileEndOfFileInfo => ERROR_ACCESS_DENIED,
+				_ => ERROR_INVALID_PARAMETER
+			};
+
+			unsafe {
+				SetLastError(error);
+			}
+
+			FALSE
+		});
+
+		match b {
+			Some(b) => b,
+			None => set_file_information_by_handle(args)
 		}
 	}
 
@@ -226,13 +625,22 @@ impl Fixer {
 	{
 		let path_slice = lpcwstr_to_slice(args.lp_file_name);
 		let path = slice_to_pathbuf(path_slice);
+		Span::current().record("path", &display(path.display()));
 
-		if self.vfs.inside(&path) {
-			assert!(args.lp_file_part.is_null());
-
+		if self.vfs(&path).is_some() {
 			let ret = if (args.n_buffer_length as usize) < path_slice.len() + 1 {
 				path_slice.len() + 1
 			} else {
+				if !writable(args.lp_buffer, path_slice.len() + 1)
+					|| (!args.lp_file_part.is_null() && !writable(args.lp_file_part, 1))
+				{
+					unsafe {
+						SetLastError(ERROR_NOACCESS);
+					}
+
+					return 0;
+				}
+
 				unsafe {
 					ptr::copy_nonoverlapping(
 						path_slice.as_ptr(),
@@ -240,6 +648,16 @@ impl Fixer {
 						path_slice.len()
 					);
 					*args.lp_buffer.add(path_slice.len()) = 0;
+
+					if !args.lp_file_part.is_null() {
+						*args.lp_file_part = match path_slice
+							.iter()
+							.rposition(|&c| c == b'\\' as u16 || c == b'/' as u16)
+						{
+							Some(i) if i + 1 < path_slice.len() => args.lp_buffer.add(i + 1),
+							_ => ptr::null_mut()
+						};
+					}
 				}
 
 				path_slice.len()
@@ -260,32 +678,44 @@ impl Fixer {
 		F: Fn(FindFirstFileWArgs) -> HANDLE
 	{
 		let path = lpcwstr_to_pathbuf(args.lp_file_name);
+		Span::current().record("path", &display(path.display()));
 
-		match self.vfs.find(&path) {
-			Some(vec) => {
-				let mut find = self.find.1.lock();
-				assert!(find.is_none());
+		match self.find_first_file_impl(&path, args.lp_find_file_data, false) {
+			Some(handle) => handle,
+			None => find_first_file_w(args)
+		}
+	}
 
-				let vec = unsafe {
-					mem::transmute::<Vec<(&str, &Entry)>, Vec<(&'static str, &'static Entry)>>(vec)
-				};
+	pub(crate) fn find_first_file_ex_w<F>(
+		&self,
+		args: FindFirstFileExWArgs,
+		find_first_file_ex_w: F
+	) -> HANDLE
+	where
+		F: Fn(FindFirstFileExWArgs) -> HANDLE
+	{
+		let path = lpcwstr_to_pathbuf(args.lp_file_name);
+		Span::current().record("path", &display(path.display()));
 
-				*find = Some((vec, 0));
-				let (entries, index) = find.as_mut().unwrap();
+		// the archives only know names and directories, so anything fancier goes to the disk
+		if (args.f_info_level_id != FindExInfoStandard && args.f_info_level_id != FindExInfoBasic)
+			|| (args.f_search_op != FindExSearchNameMatch
+				&& args.f_search_op != FindExSearchLimitToDirectories)
+			|| !args.lp_search_filter.is_null()
+			|| args.dw_additional_flags & FIND_FIRST_EX_CASE_SENSITIVE != 0
+		{
+			return find_first_file_ex_w(args);
+		}
 
-				if self.find_next_file_impl(args.lp_find_file_data, entries, index) {
-					self.find.0
-				} else {
-					*find = None;
+		let handle = self.find_first_file_impl(
+			&path,
+			args.lp_find_file_data as LPWIN32_FIND_DATAW,
+			args.f_search_op == FindExSearchLimitToDirectories
+		);
 
-					unsafe {
-						SetLastError(ERROR_FILE_NOT_FOUND);
-					}
-
-					INVALID_HANDLE_VALUE
-				}
-			}
-			None => find_first_file_w(args)
+		match handle {
+			Some(handle) => handle,
+			None => find_first_file_ex_w(args)
 		}
 	}
 
@@ -293,21 +723,40 @@ impl Fixer {
 	where
 		F: Fn(FindNextFileWArgs) -> BOOL
 	{
-		if args.h_find_file == self.find.0 {
-			let mut find = self.find.1.lock();
-			let (entries, index) = find.as_mut().unwrap();
+		let found = self
+			.find
+			.1
+			.lock()
+			.get_mut(&(args.h_find_file as usize))
+			.map(|find| {
+				if writable(args.lp_find_file_data, 1) {
+					Ok(self.find_next_file_impl(
+						args.lp_find_file_data,
+						&find.entries,
+						&mut find.index
+					))
+				} else {
+					Err(ERROR_NOACCESS)
+				}
+			});
 
-			if self.find_next_file_impl(args.lp_find_file_data, entries, index) {
-				TRUE
-			} else {
+		match found {
+			Some(Ok(true)) => TRUE,
+			Some(Ok(false)) => {
 				unsafe {
 					SetLastError(ERROR_NO_MORE_FILES);
 				}
 
 				FALSE
 			}
-		} else {
-			find_next_file_w(args)
+			Some(Err(error)) => {
+				unsafe {
+					SetLastError(error);
+				}
+
+				FALSE
+			}
+			None => find_next_file_w(args)
 		}
 	}
 
@@ -315,37 +764,281 @@ impl Fixer {
 	where
 		F: Fn(FindCloseArgs) -> BOOL
 	{
-		if args.h_find_file == self.find.0 {
-			let mut find = self.find.1.lock();
-			assert!(find.is_some());
-			*find = None;
-			TRUE
+		if self
+			.find
+			.1
+			.lock()
+			.remove(&(args.h_find_file as usize))
+			.is_some()
+		{
+			Stats::sub(&self.stats.find_handles, 1);
+
+			// CloseHandle is hooked too and would take the handle back out of closed
+			let b = unsafe { CloseHandle(args.h_find_file) };
+			self.mark_closed(args.h_find_file);
+			b
+		} else if self.is_closed(args.h_find_file) {
+			unsafe { SetLastError(ERROR_INVALID_HANDLE) };
+			FALSE
 		} else {
 			find_close(args)
 		}
 	}
 
+	pub(crate) fn create_process_w<F>(&self, args: CreateProcessWArgs, create_process_w: F) -> BOOL
+	where
+		F: Fn(CreateProcessWArgs) -> BOOL
+	{
+		if args.lp_process_information.is_null() {
+			return create_process_w(args);
+		}
+
+		let b = create_process_w(CreateProcessWArgs {
+			dw_creation_flags: args.dw_creation_flags | CREATE_SUSPENDED,
+			..args
+		});
+
+		if b == FALSE {
+			return b;
+		}
+
+		let pi = unsafe { &*args.lp_process_information };
+		let dll = module_path(MODULE.load(Ordering::Relaxed) as _);
+
+		if !unsafe { hook::update_process(pi.hProcess, &dll) } {
+			tracing::warn!(
+				"failed to inject {} into child process {}: {}",
+				dll.display(),
+				pi.dwProcessId,
+				Error::last_os_error()
+			);
+		}
+
+		if args.dw_creation_flags & CREATE_SUSPENDED == 0 {
+			unsafe {
+				ResumeThread(pi.hThread);
+			}
+		}
+
+		b
+	}
+
+	pub(crate) fn stats(&self) -> String {
+		format!(
+			"opens={} misses={} overlay={} finds={} bytes_read={} handles={} find_handles={}",
+			Stats::get(&self.stats.opens),
+			Stats::get(&self.stats.misses),
+			Stats::get(&self.stats.overlay),
+			Stats::get(&self.stats.finds),
+			Stats::get(&self.stats.bytes_read),
+			Stats::get(&self.stats.handles),
+			Stats::get(&self.stats.find_handles)
+		)
+	}
+
+	pub(crate) fn flush_cache(&self) {
+		self.overlay.flush();
+	}
+
+	pub(crate) fn close(&self) -> Vec<JoinHandle<()>> {
+		let threads = vec![self.writes.stop(), self.overlay.close()];
+
+		self.writes.flush_all();
+		self.create.1.lock().clear();
+		self.find.1.lock().clear();
+		self.vfs.write().clear();
+
+		unsafe {
+			CloseHandle(self.create.0);
+			CloseHandle(self.find.0);
+		}
+
+		threads.into_iter().flatten().collect()
+	}
+
+	pub(crate) fn report_leaks(&self) {
+		let timestamp = |opened: SystemTime| {
+			let since = opened.duration_since(UNIX_EPOCH).unwrap_or_default();
+			format!("{}.{:03}", since.as_secs(), since.subsec_millis())
+		};
+
+		for (handle, h) in self.create.1.lock().iter() {
+			let h = h.lock();
+
+			tracing::warn!(
+				handle = %format!("{:#x}", handle),
+				path = %h.path.display(),
+				opened = %timestamp(h.opened),
+				"leaked handle"
+			);
+		}
+
+		for (handle, find) in self.find.1.lock().iter() {
+			tracing::warn!(
+				handle = %format!("{:#x}", handle),
+				path = %find.path.display(),
+				opened = %timestamp(find.opened),
+				"leaked find handle"
+			);
+		}
+	}
+
+	fn is_pseudo_handle(&self, handle: HANDLE) -> bool {
+		self.create.1.lock().contains_key(&(handle as usize))
+			|| self.find.1.lock().contains_key(&(handle as usize))
+	}
+
+	fn is_closed(&self, handle: HANDLE) -> bool {
+		let mut closed = self.closed.lock();
+
+		if !closed.contains(&(handle as usize)) {
+			return false;
+		}
+
+		let mut flags = 0;
+
+		if unsafe { GetHandleInformation(handle, &mut flags) } != FALSE {
+			closed.remove(&(handle as usize));
+			return false;
+		}
+
+		true
+	}
+
+	// handles that are valid again were reused and are dropped, and if that isn't enough the set
+	// starts over so earlier double closes go undetected
+	fn mark_closed(&self, handle: HANDLE) {
+		let mut closed = self.closed.lock();
+
+		if closed.len() >= CLOSED_LIMIT {
+			closed.retain(|&handle| {
+				let mut flags = 0;
+				unsafe { GetHandleInformation(handle as HANDLE, &mut flags) == FALSE }
+			});
+
+			if closed.len() >= CLOSED_LIMIT {
+				closed.clear();
+			}
+		}
+
+		closed.insert(handle as usize);
+	}
+
+	fn pseudo_handle(&self, base: HANDLE) -> HANDLE {
+		let handle = duplicate_handle(base);
+		self.closed.lock().remove(&(handle as usize));
+		handle
+	}
+
+	fn vfs(&self, path: &Path) -> Option<Arc<Vfs>> {
+		let vfs = self.vfs.read();
+
+		vfs.iter()
+			.find(|v| v.has_file(path))
+			.or_else(|| vfs.iter().find(|v| v.has_dir(path)))
+			.or_else(|| vfs.iter().find(|v| v.inside(path)))
+			.cloned()
+	}
+
+	fn with_handle<F, R>(&self, handle: HANDLE, f: F) -> Option<R>
+	where
+		F: FnOnce(&mut Handle) -> R
+	{
+		let h = self.create.1.lock().get(&(handle as usize)).cloned()?;
+		let mut h = h.lock();
+		Some(f(&mut *h))
+	}
+
+	fn find_first_file_impl(
+		&self,
+		path: &Path,
+		data: LPWIN32_FIND_DATAW,
+		directories_only: bool
+	) -> Option<HANDLE> {
+		let listings: Vec<_> = self
+			.vfs
+			.read()
+			.iter()
+			.filter_map(|v| v.find(path))
+			.collect();
+
+		if listings.is_empty() {
+			return None;
+		}
+
+		let mut entries = match listings.into_iter().flatten().collect::<Vec<_>>() {
+			listings if !listings.is_empty() => vfs::merge(listings),
+			_ => {
+				Stats::add(&self.stats.misses, 1);
+
+				unsafe {
+					SetLastError(ERROR_PATH_NOT_FOUND);
+				}
+
+				return Some(INVALID_HANDLE_VALUE);
+			}
+		};
+
+		etw::find(path, entries.len());
+
+		if !writable(data, 1) {
+			unsafe {
+				SetLastError(ERROR_NOACCESS);
+			}
+
+			return Some(INVALID_HANDLE_VALUE);
+		}
+
+		if directories_only {
+			entries.retain(|(_, entry)| *entry == Entry::Directory);
+		}
+
+		let mut index = 0;
+
+		Some(if self.find_next_file_impl(data, &entries, &mut index) {
+			let handle = self.pseudo_handle(self.find.0);
+			self.find.1.lock().insert(
+				handle as usize,
+				Find {
+					path: path.into(),
+					entries,
+					index,
+					opened: SystemTime::now()
+				}
+			);
+			Stats::add(&self.stats.finds, 1);
+			Stats::add(&self.stats.find_handles, 1);
+			handle
+		} else {
+			unsafe {
+				SetLastError(ERROR_FILE_NOT_FOUND);
+			}
+
+			INVALID_HANDLE_VALUE
+		})
+	}
+
 	pub(crate) fn find_next_file_impl(
 		&self,
 		data: LPWIN32_FIND_DATAW,
-		entries: &[(&str, &Entry)],
+		entries: &[(String, Entry)],
 		index: &mut usize
 	) -> bool {
-		assert!(!data.is_null());
 		let data: &mut WIN32_FIND_DATAW = unsafe { &mut *data };
 		*data = unsafe { mem::zeroed() };
 
 		match entries.get(*index) {
-			Some((name, &entry)) => {
+			Some((name, entry)) => {
 				*index += 1;
 
-				match entry {
+				match *entry {
 					Entry::Directory => {
 						data.dwFileAttributes = FILE_ATTRIBUTE_DIRECTORY;
 					}
 					Entry::File { len, .. } => {
 						data.dwFileAttributes = FILE_ATTRIBUTE_NORMAL;
 						data.nFileSizeLow = len as u32;
+						data.nFileSizeHigh = (len >> 32) as u32;
 					}
 				}
 
@@ -364,8 +1057,137 @@ impl Fixer {
 unsafe impl Send for Fixer {}
 unsafe impl Sync for Fixer {}
 
+// portable mode backs the pseudo handles with an in-memory section so nothing is left in %TEMP%
 fn create_temp_file(ty: &str) -> HANDLE {
-	File::create(std::env::temp_dir().join(format!("underrail_fixer_{}", ty)))
-		.expect("failed to create temp file")
-		.into_raw_handle()
+	let handle = if config::get().portable == Some(true) {
+		let handle = unsafe {
+			CreateFileMappingW(
+				INVALID_HANDLE_VALUE,
+				ptr::null_mut(),
+				PAGE_READWRITE,
+				0,
+				1,
+				ptr::null()
+			)
+		};
+
+		assert!(
+			!handle.is_null(),
+			"CreateFileMappingW: {}",
+			Error::last_os_error()
+		);
+
+		handle
+	} else {
+		File::create(std::env::temp_dir().join(format!("underrail_fixer_{}", ty)))
+			.expect("failed to create temp file")
+			.into_raw_handle()
+	};
+
+	unsafe {
+		assert_ne!(
+			SetHandleInformation(handle, HANDLE_FLAG_INHERIT, 0),
+			0,
+			"SetHandleInformation: {}",
+			Error::last_os_error()
+		);
+	}
+
+	handle
+}
+
+fn duplicate_handle(handle: HANDLE) -> HANDLE {
+	let mut dup = ptr::null_mut();
+
+	unsafe {
+		let process = GetCurrentProcess();
+
+		assert_ne!(
+			DuplicateHandle(
+				process,
+				handle,
+				process,
+				&mut dup,
+				0,
+				FALSE,
+				DUPLICATE_SAME_ACCESS
+			),
+			0,
+			"DuplicateHandle: {}",
+			Error::last_os_error()
+		);
+	}
+
+	dup
+}
+
+fn writable<T>(ptr: *mut T, count: usize) -> bool {
+	let len = match count.checked_mul(mem::size_of::<T>()) {
+		Some(0) => return true,
+		Some(len) => len,
+		None => return false
+	};
+
+	let (mut address, end) = match (ptr as usize).checked_add(len) {
+		Some(end) if !ptr.is_null() => (ptr as usize, end),
+		_ => return false
+	};
+
+	while address < end {
+		let mut info: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+
+		let len = unsafe {
+			VirtualQuery(
+				address as *const _,
+				&mut info,
+				mem::size_of::<MEMORY_BASIC_INFORMATION>()
+			)
+		};
+
+		if len == 0
+			|| info.State != MEM_COMMIT
+			|| info.Protect & (PAGE_GUARD | PAGE_NOACCESS) != 0
+			|| info.Protect
+				& (PAGE_READWRITE
+					| PAGE_WRITECOPY
+					| PAGE_EXECUTE_READWRITE
+					| PAGE_EXECUTE_WRITECOPY)
+				== 0
+		{
+			return false;
+		}
+
+		address = info.BaseAddress as usize + info.RegionSize;
+	}
+
+	true
+}
+
+fn seek(reader: &mut Reader, distance: i64, method: DWORD) -> Result<u64, DWORD> {
+	let pos = target(reader, distance, method)?;
+	reader
+		.seek(SeekFrom::Start(pos))
+		.map_err(|_| ERROR_NEGATIVE_SEEK)
+}
+
+// where a seek would land, without moving the reader
+fn target(reader: &Reader, distance: i64, method: DWORD) -> Result<u64, DWORD> {
+	let base = match method {
+		FILE_BEGIN => 0,
+		FILE_CURRENT => reader.position() as i64,
+		FILE_END => reader.len() as i64,
+		_ => return Err(ERROR_INVALID_PARAMETER)
+	};
+
+	match base.checked_add(distance) {
+		Some(pos) if pos >= 0 => Ok(pos as u64),
+		_ => Err(ERROR_NEGATIVE_SEEK)
+	}
+}
+
+fn share_conflict(access: DWORD, share: DWORD, new_access: DWORD, new_share: DWORD) -> bool {
+	let reads = |access| access & (GENERIC_READ | GENERIC_ALL | FILE_READ_DATA) != 0;
+
+	(reads(new_access) && share & FILE_SHARE_READ == 0)
+		|| (reads(access) && new_share & FILE_SHARE_READ == 0)
 }
@@ -0,0 +1,75 @@
+use crate::config;
+#[cfg(windows)]
+use crate::version;
+use std::path::{Path, PathBuf};
+
+const EXE: &str = "underrail.exe";
+
+pub fn exe_name() -> String {
+	config::get()
+		.game_exe
+		.map_or_else(|| EXE.to_owned(), |s| s.to_lowercase())
+}
+
+pub fn original_exe_name() -> String {
+	original(&exe_name())
+}
+
+// UNDERRAIL_FIX_GAME_EXES lists other names the game may have, like the dev tool or a renamed
+// copy, tried after UNDERRAIL_FIX_GAME_EXE
+pub fn exe_names() -> Vec<String> {
+	let mut names = vec![exe_name()];
+
+	if let Some(exes) = config::get().game_exes {
+		names.extend(
+			exes.iter()
+				.map(|s| s.to_lowercase())
+				.filter(|s| !names.contains(s))
+				.collect::<Vec<_>>()
+		);
+	}
+
+	names
+}
+
+fn original(exe: &str) -> String {
+	let stem = exe.strip_suffix(".exe").unwrap_or(exe);
+	format!("{}.original.exe", stem)
+}
+
+pub fn is_game(exe: &Path) -> bool {
+	let name = match exe.file_name() {
+		Some(name) => name.to_string_lossy().to_lowercase(),
+		None => return false
+	};
+
+	exe_names()
+		.iter()
+		.any(|exe| *exe == name || original(exe) == name)
+		|| built_as_game(exe)
+}
+
+// the game exe in dir, preferring one built as the game over the first listed name that exists
+pub fn find(dir: &Path) -> Option<PathBuf> {
+	let exes: Vec<_> = exe_names()
+		.iter()
+		.map(|name| dir.join(name))
+		.filter(|exe| exe.is_file())
+		.collect();
+
+	exes.iter()
+		.find(|exe| built_as_game(exe))
+		.or_else(|| exes.first())
+		.cloned()
+}
+
+#[cfg(windows)]
+fn built_as_game(exe: &Path) -> bool {
+	version::original_filename(exe).map_or(false, |name| name.to_lowercase() == EXE)
+}
+
+// only the packer is built outside Windows, which just needs the name
+#[cfg(not(windows))]
+fn built_as_game(_exe: &Path) -> bool {
+	false
+}
@@ -0,0 +1,197 @@
+use std::{
+	fmt::Debug,
+	iter, mem, ptr,
+	sync::{
+		atomic::{AtomicBool, AtomicUsize, Ordering},
+		mpsc
+	},
+	thread
+};
+use tracing::{
+	field::{Field, Visit},
+	Event, Level, Subscriber
+};
+use tracing_subscriber::{layer::Context, Layer};
+use winapi::{
+	shared::{
+		minwindef::{LPARAM, LRESULT, UINT, WPARAM},
+		windef::HWND
+	},
+	um::{
+		libloaderapi::GetModuleHandleW,
+		wincon::{GetConsoleProcessList, GetConsoleWindow},
+		winuser::{
+			CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, MessageBoxW,
+			PostMessageW, PostQuitMessage, RegisterClassW, SetWindowTextW, ShowWindow,
+			TranslateMessage, CW_USEDEFAULT, IDYES, MB_ICONERROR, MB_ICONWARNING, MB_OK, MB_YESNO,
+			MSG, SW_HIDE, WM_CLOSE, WM_DESTROY, WNDCLASSW, WS_CAPTION, WS_CHILD, WS_OVERLAPPED,
+			WS_SYSMENU, WS_VISIBLE
+		}
+	}
+};
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static WINDOW: AtomicUsize = AtomicUsize::new(0);
+static STATUS: AtomicUsize = AtomicUsize::new(0);
+
+pub struct GuiLayer;
+
+struct Message(String);
+
+// a console of our own means we were double-clicked rather than started from a terminal
+pub fn wanted() -> bool {
+	let mut processes = [0; 2];
+	unsafe { GetConsoleProcessList(processes.as_mut_ptr(), processes.len() as _) <= 1 }
+}
+
+pub fn open() {
+	let (tx, rx) = mpsc::channel();
+
+	thread::spawn(move || unsafe {
+		let class = wide("UnderrailFixLauncher");
+		let instance = GetModuleHandleW(ptr::null());
+
+		let mut wc: WNDCLASSW = mem::zeroed();
+		wc.lpfnWndProc = Some(window_proc);
+		wc.hInstance = instance;
+		wc.lpszClassName = class.as_ptr();
+		RegisterClassW(&wc);
+
+		let window = CreateWindowExW(
+			0,
+			class.as_ptr(),
+			wide("Underrail Fix").as_ptr(),
+			WS_OVERLAPPED | WS_CAPTION | WS_SYSMENU | WS_VISIBLE,
+			CW_USEDEFAULT,
+			CW_USEDEFAULT,
+			480,
+			120,
+			ptr::null_mut(),
+			ptr::null_mut(),
+			instance,
+			ptr::null_mut()
+		);
+
+		let status = CreateWindowExW(
+			0,
+			wide("STATIC").as_ptr(),
+			wide("Starting...").as_ptr(),
+			WS_CHILD | WS_VISIBLE,
+			12,
+			12,
+			440,
+			60,
+			window,
+			ptr::null_mut(),
+			instance,
+			ptr::null_mut()
+		);
+
+		WINDOW.store(window as usize, Ordering::SeqCst);
+		STATUS.store(status as usize, Ordering::SeqCst);
+		tx.send(()).ok();
+
+		let mut msg: MSG = mem::zeroed();
+
+		while GetMessageW(&mut msg, ptr::null_mut(), 0, 0) > 0 {
+			TranslateMessage(&msg);
+			DispatchMessageW(&msg);
+		}
+
+		STATUS.store(0, Ordering::SeqCst);
+		WINDOW.store(0, Ordering::SeqCst);
+	});
+
+	rx.recv().ok();
+	ACTIVE.store(true, Ordering::SeqCst);
+
+	unsafe {
+		ShowWindow(GetConsoleWindow(), SW_HIDE);
+	}
+}
+
+pub fn active() -> bool {
+	ACTIVE.load(Ordering::SeqCst)
+}
+
+pub fn status(text: &str) {
+	let status = STATUS.load(Ordering::SeqCst);
+
+	if status != 0 {
+		unsafe {
+			SetWindowTextW(status as HWND, wide(text).as_ptr());
+		}
+	}
+}
+
+pub fn close() {
+	let window = WINDOW.load(Ordering::SeqCst);
+
+	if window != 0 {
+		unsafe {
+			PostMessageW(window as HWND, WM_CLOSE, 0, 0);
+		}
+	}
+}
+
+pub fn error(text: &str) {
+	close();
+
+	unsafe {
+		MessageBoxW(
+			ptr::null_mut(),
+			wide(text).as_ptr(),
+			wide("Underrail Fix").as_ptr(),
+			MB_OK | MB_ICONERROR
+		);
+	}
+}
+
+pub fn confirm(text: &str) -> bool {
+	unsafe {
+		MessageBoxW(
+			ptr::null_mut(),
+			wide(text).as_ptr(),
+			wide("Underrail Fix").as_ptr(),
+			MB_YESNO | MB_ICONWARNING
+		) == IDYES
+	}
+}
+
+unsafe extern "system" fn window_proc(
+	window: HWND,
+	msg: UINT,
+	wparam: WPARAM,
+	lparam: LPARAM
+) -> LRESULT {
+	if msg == WM_DESTROY {
+		PostQuitMessage(0);
+		return 0;
+	}
+
+	DefWindowProcW(window, msg, wparam, lparam)
+}
+
+fn wide(s: &str) -> Vec<u16> {
+	s.encode_utf16().chain(iter::once(0)).collect()
+}
+
+impl<S: Subscriber> Layer<S> for GuiLayer {
+	fn on_event(&self, event: &Event, _: Context<S>) {
+		if !active() || *event.metadata().level() > Level::INFO {
+			return;
+		}
+
+		let mut message = Message(String::new());
+		event.record(&mut message);
+		status(&message.0);
+	}
+}
+
+impl Visit for Message {
+	fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+		if field.name() == "message" {
+			self.0 = format!("{:?}", value);
+		}
+	}
+}
@@ -0,0 +1,554 @@
+use std::{
+	ffi::{c_void, OsString},
+	os::windows::ffi::OsStringExt,
+	path::{Path, PathBuf},
+	ptr,
+	sync::atomic::{AtomicBool, Ordering},
+	thread,
+	time::Duration
+};
+use winapi::um::{
+	libloaderapi::{
+		GetModuleFileNameW, GetModuleHandleExW, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+		GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT
+	},
+	winnt::HANDLE
+};
+
+const RETRIES: u32 = 5;
+
+static IAT: AtomicBool = AtomicBool::new(false);
+
+#[cfg(not(feature = "minhook"))]
+mod backend {
+	use crate::{
+		detours::{
+			DetourAttach, DetourDetach, DetourTransactionAbort, DetourTransactionBegin,
+			DetourTransactionCommit, DetourUpdateProcessWithDll, DetourUpdateThread
+		},
+		path_to_wide
+	};
+	use std::{
+		ffi::{c_void, CString},
+		path::Path,
+		ptr
+	};
+	use winapi::{
+		shared::{minwindef::FALSE, winerror::ERROR_NO_UNICODE_TRANSLATION},
+		um::{
+			errhandlingapi::SetLastError,
+			processthreadsapi::GetCurrentThread,
+			stringapiset::WideCharToMultiByte,
+			winnls::{CP_ACP, WC_NO_BEST_FIT_CHARS},
+			winnt::HANDLE
+		}
+	};
+
+	pub(super) unsafe fn begin() -> Result<(), i32> {
+		let error = DetourTransactionBegin();
+
+		if error != 0 {
+			tracing::debug!("DetourTransactionBegin: {:#x}", error);
+			return Err(error);
+		}
+
+		let error = DetourUpdateThread(GetCurrentThread());
+
+		if error != 0 {
+			tracing::debug!("DetourUpdateThread: {:#x}", error);
+			DetourTransactionAbort();
+			return Err(error);
+		}
+
+		Ok(())
+	}
+
+	pub(super) unsafe fn attach(pointer: *mut *mut c_void, detour: *mut c_void) -> i32 {
+		DetourAttach(pointer, detour)
+	}
+
+	pub(super) unsafe fn detach(pointer: *mut *mut c_void, detour: *mut c_void) -> i32 {
+		DetourDetach(pointer, detour)
+	}
+
+	pub(super) unsafe fn commit() -> i32 {
+		DetourTransactionCommit()
+	}
+
+	pub(super) unsafe fn abort() {
+		DetourTransactionAbort();
+	}
+
+	pub(super) unsafe fn update_process(process: HANDLE, dll: &Path) -> bool {
+		let dll = match ansi(dll) {
+			Some(dll) => dll,
+			None => return false
+		};
+
+		let mut dlls = [dll.as_ptr()];
+
+		DetourUpdateProcessWithDll(process as *mut _, dlls.as_mut_ptr(), 1) != 0
+	}
+
+	// Detours only takes ANSI paths, which can't name a DLL whose path has characters outside the
+	// system code page, so fail rather than have the child load the wrong file or none at all
+	unsafe fn ansi(path: &Path) -> Option<CString> {
+		let wide = path_to_wide(path);
+
+		let len = WideCharToMultiByte(
+			CP_ACP,
+			WC_NO_BEST_FIT_CHARS,
+			wide.as_ptr(),
+			-1,
+			ptr::null_mut(),
+			0,
+			ptr::null(),
+			ptr::null_mut()
+		);
+
+		if len == 0 {
+			return None;
+		}
+
+		let mut ansi = vec![0u8; len as usize];
+		let mut used_default = FALSE;
+
+		if WideCharToMultiByte(
+			CP_ACP,
+			WC_NO_BEST_FIT_CHARS,
+			wide.as_ptr(),
+			-1,
+			ansi.as_mut_ptr() as *mut _,
+			len,
+			ptr::null(),
+			&mut used_default
+		) == 0
+		{
+			return None;
+		}
+
+		if used_default != FALSE {
+			SetLastError(ERROR_NO_UNICODE_TRANSLATION);
+			return None;
+		}
+
+		ansi.pop();
+		CString::new(ansi).ok()
+	}
+}
+
+#[cfg(feature = "minhook")]
+mod backend {
+	use crate::path_to_wide;
+	use minhook_sys as _;
+	use parking_lot::{const_mutex, Mutex};
+	use std::{collections::HashMap, ffi::c_void, mem, path::Path, ptr};
+	use winapi::{
+		shared::winerror::ERROR_NOT_SUPPORTED,
+		um::{
+			errhandlingapi::SetLastError,
+			handleapi::CloseHandle,
+			libloaderapi::LoadLibraryW,
+			memoryapi::{VirtualAllocEx, VirtualFreeEx, WriteProcessMemory},
+			processthreadsapi::{CreateRemoteThread, GetCurrentProcess, GetExitCodeThread},
+			synchapi::WaitForSingleObject,
+			winbase::{INFINITE, WAIT_OBJECT_0},
+			winnt::{HANDLE, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_READWRITE},
+			wow64apiset::IsWow64Process
+		}
+	};
+
+	const MH_OK: i32 = 0;
+	const MH_ERROR_ALREADY_INITIALIZED: i32 = 1;
+
+	static TARGETS: Mutex<Option<HashMap<usize, usize>>> = const_mutex(None);
+
+	extern "system" {
+		fn MH_Initialize() -> i32;
+		fn MH_CreateHook(
+			target: *mut c_void,
+			detour: *mut c_void,
+			original: *mut *mut c_void
+		) -> i32;
+		fn MH_QueueEnableHook(target: *mut c_void) -> i32;
+		fn MH_QueueDisableHook(target: *mut c_void) -> i32;
+		fn MH_ApplyQueued() -> i32;
+	}
+
+	pub(super) unsafe fn begin() -> Result<(), i32> {
+		let status = MH_Initialize();
+
+		if status == MH_OK || status == MH_ERROR_ALREADY_INITIALIZED {
+			Ok(())
+		} else {
+			tracing::debug!("MH_Initialize: {}", status);
+			Err(status)
+		}
+	}
+
+	pub(super) unsafe fn attach(pointer: *mut *mut c_void, detour: *mut c_void) -> i32 {
+		let target = *pointer;
+		let mut original = target;
+
+		let status = MH_CreateHook(target, detour, &mut original);
+
+		if status != MH_OK {
+			return status;
+		}
+
+		let status = MH_QueueEnableHook(target);
+
+		if status == MH_OK {
+			TARGETS
+				.lock()
+				.get_or_insert_with(HashMap::new)
+				.insert(original as usize, target as usize);
+
+			*pointer = original;
+		}
+
+		status
+	}
+
+	pub(super) unsafe fn detach(pointer: *mut *mut c_void, _: *mut c_void) -> i32 {
+		let target = match TARGETS
+			.lock()
+			.as_mut()
+			.and_then(|targets| targets.remove(&(*pointer as usize)))
+		{
+			Some(target) => target as *mut c_void,
+			None => return -1
+		};
+
+		let status = MH_QueueDisableHook(target);
+
+		if status == MH_OK {
+			*pointer = target;
+		}
+
+		status
+	}
+
+	pub(super) unsafe fn commit() -> i32 {
+		MH_ApplyQueued()
+	}
+
+	pub(super) unsafe fn abort() {}
+
+	// minhook has no DetourUpdateProcessWithDll, so the dll is loaded by a remote LoadLibraryW
+	// like the launcher does, and its DllMain decides whether the child is worth hooking
+	pub(super) unsafe fn update_process(process: HANDLE, dll: &Path) -> bool {
+		let (mut wow64, mut target_wow64) = (0, 0);
+		IsWow64Process(GetCurrentProcess(), &mut wow64);
+		IsWow64Process(process, &mut target_wow64);
+
+		if wow64 != target_wow64 {
+			SetLastError(ERROR_NOT_SUPPORTED);
+			return false;
+		}
+
+		let dll = path_to_wide(dll);
+		let len = dll.len() * 2;
+
+		let remote = VirtualAllocEx(
+			process,
+			ptr::null_mut(),
+			len,
+			MEM_COMMIT | MEM_RESERVE,
+			PAGE_READWRITE
+		);
+
+		if remote.is_null() {
+			return false;
+		}
+
+		if WriteProcessMemory(
+			process,
+			remote,
+			dll.as_ptr() as *const _,
+			len,
+			ptr::null_mut()
+		) == 0
+		{
+			VirtualFreeEx(process, remote, 0, MEM_RELEASE);
+			return false;
+		}
+
+		let thread = CreateRemoteThread(
+			process,
+			ptr::null_mut(),
+			0,
+			Some(mem::transmute(LoadLibraryW as usize)),
+			remote,
+			0,
+			ptr::null_mut()
+		);
+
+		if thread.is_null() {
+			VirtualFreeEx(process, remote, 0, MEM_RELEASE);
+			return false;
+		}
+
+		let mut module = 0;
+
+		let finished = WaitForSingleObject(thread, INFINITE) == WAIT_OBJECT_0
+			&& GetExitCodeThread(thread, &mut module) != 0;
+
+		CloseHandle(thread);
+		VirtualFreeEx(process, remote, 0, MEM_RELEASE);
+		finished && module != 0
+	}
+}
+
+mod iat {
+	use crate::MODULE;
+	use parking_lot::{const_mutex, Mutex};
+	use std::{ffi::c_void, mem, ptr, sync::atomic::Ordering};
+	use winapi::{
+		shared::{
+			minwindef::{DWORD, HMODULE},
+			winerror::ERROR_PROC_NOT_FOUND
+		},
+		um::{
+			errhandlingapi::GetLastError,
+			memoryapi::VirtualProtect,
+			processthreadsapi::GetCurrentProcess,
+			psapi::EnumProcessModules,
+			winnt::{
+				IMAGE_DIRECTORY_ENTRY_IMPORT, IMAGE_DOS_HEADER, IMAGE_IMPORT_DESCRIPTOR,
+				IMAGE_NT_HEADERS, PAGE_READWRITE
+			}
+		}
+	};
+
+	static PATCHED: Mutex<Vec<(usize, usize)>> = const_mutex(Vec::new());
+
+	pub(super) unsafe fn attach(pointer: *mut *mut c_void, detour: *mut c_void) -> i32 {
+		let entries = entries(*pointer);
+
+		// nothing would be hooked, underrail.exe itself only imports mscoree
+		if entries.is_empty() {
+			return ERROR_PROC_NOT_FOUND as _;
+		}
+
+		// entries patched before a failure stay recorded so detach restores them
+		for entry in entries {
+			if let Err(error) = write(entry, detour) {
+				return error as _;
+			}
+
+			PATCHED.lock().push((entry as usize, *pointer as usize));
+		}
+
+		0
+	}
+
+	pub(super) unsafe fn detach(pointer: *mut *mut c_void, _: *mut c_void) -> i32 {
+		let mut result = 0;
+
+		PATCHED.lock().retain(|&(entry, original)| {
+			if original != *pointer as usize {
+				return true;
+			}
+
+			match write(entry as *mut *mut c_void, original as *mut c_void) {
+				Ok(()) => false,
+				Err(error) => {
+					result = error as _;
+					true
+				}
+			}
+		});
+
+		result
+	}
+
+	unsafe fn write(entry: *mut *mut c_void, value: *mut c_void) -> Result<(), DWORD> {
+		let mut protect: DWORD = 0;
+		let len = mem::size_of::<*mut c_void>();
+
+		if VirtualProtect(entry as *mut _, len, PAGE_READWRITE, &mut protect) == 0 {
+			let error = GetLastError();
+			tracing::debug!("VirtualProtect {:?}: {}", entry, error);
+			return Err(error);
+		}
+
+		*entry = value;
+		VirtualProtect(entry as *mut _, len, protect, &mut protect);
+		Ok(())
+	}
+
+	// the game's file calls come from the runtime (clr.dll and friends), so every loaded module
+	// but this one is patched wherever it imports target, under any dll name
+	unsafe fn entries(target: *mut c_void) -> Vec<*mut *mut c_void> {
+		let mut modules: Vec<HMODULE> = vec![ptr::null_mut(); 1024];
+		let mut needed = 0;
+
+		if EnumProcessModules(
+			GetCurrentProcess(),
+			modules.as_mut_ptr(),
+			(modules.len() * mem::size_of::<HMODULE>()) as _,
+			&mut needed
+		) == 0
+		{
+			tracing::warn!("EnumProcessModules: {}", Error::last_os_error());
+			return Vec::new();
+		}
+
+		modules.truncate(needed as usize / mem::size_of::<HMODULE>());
+
+		let this = MODULE.load(Ordering::Relaxed) as HMODULE;
+		let mut entries = Vec::new();
+
+		for module in modules.into_iter().filter(|&m| m != this) {
+			let base = module as *mut u8;
+			let dos = &*(base as *const IMAGE_DOS_HEADER);
+			let nt = &*(base.offset(dos.e_lfanew as isize) as *const IMAGE_NT_HEADERS);
+			let dir = nt.OptionalHeader.DataDirectory[IMAGE_DIRECTORY_ENTRY_IMPORT as usize];
+
+			if dir.VirtualAddress == 0 {
+				continue;
+			}
+
+			let mut desc = base.add(dir.VirtualAddress as usize) as *const IMAGE_IMPORT_DESCRIPTOR;
+
+			while (*desc).Name != 0 {
+				let mut thunk = base.add((*desc).FirstThunk as usize) as *mut *mut c_void;
+
+				while !(*thunk).is_null() {
+					if *thunk == target {
+						entries.push(thunk);
+					}
+
+					thunk = thunk.add(1);
+				}
+
+				desc = desc.add(1);
+			}
+		}
+
+		entries
+	}
+}
+
+pub(crate) fn set_iat(iat: bool) {
+	IAT.store(iat, Ordering::Relaxed);
+}
+
+pub(crate) unsafe fn begin() -> Result<(), i32> {
+	if IAT.load(Ordering::Relaxed) {
+		Ok(())
+	} else {
+		backend::begin()
+	}
+}
+
+pub(crate) unsafe fn attach(pointer: *mut *mut c_void, detour: *mut c_void) -> i32 {
+	if IAT.load(Ordering::Relaxed) {
+		iat::attach(pointer, detour)
+	} else {
+		backend::attach(pointer, detour)
+	}
+}
+
+pub(crate) unsafe fn detach(pointer: *mut *mut c_void, detour: *mut c_void) -> i32 {
+	if IAT.load(Ordering::Relaxed) {
+		iat::detach(pointer, detour)
+	} else {
+		backend::detach(pointer, detour)
+	}
+}
+
+pub(crate) unsafe fn commit() -> i32 {
+	if IAT.load(Ordering::Relaxed) {
+		0
+	} else {
+		backend::commit()
+	}
+}
+
+pub(crate) unsafe fn abort() {
+	if !IAT.load(Ordering::Relaxed) {
+		backend::abort();
+	}
+}
+
+pub(crate) unsafe fn install(pointer: *mut *mut c_void, detour: *mut c_void) -> i32 {
+	let mut error = 0;
+
+	for retry in 0..=RETRIES {
+		if retry > 0 {
+			tracing::debug!("attach failed: {:#x}, retry {}/{}", error, retry, RETRIES);
+			thread::sleep(Duration::from_millis(10 << retry));
+		}
+
+		error = match begin() {
+			Ok(()) => {
+				let error = attach(pointer, detour);
+
+				if error == 0 {
+					commit()
+				} else {
+					abort();
+					error
+				}
+			}
+			Err(error) => error
+		};
+
+		// patching import tables either works or it doesn't
+		if error == 0 || IAT.load(Ordering::Relaxed) {
+			break;
+		}
+	}
+
+	error
+}
+
+// module containing the destination of a jmp already patched over target
+pub(crate) unsafe fn owner(target: *mut c_void) -> Option<PathBuf> {
+	let code = target as *const u8;
+
+	let destination = match (*code, *code.add(1)) {
+		(0xe9, _) => code.offset(5 + (code.add(1) as *const i32).read_unaligned() as isize),
+		(0xff, 0x25) => {
+			let disp = (code.add(2) as *const i32).read_unaligned();
+
+			let slot = if cfg!(target_pointer_width = "64") {
+				code.offset(6 + disp as isize)
+			} else {
+				disp as u32 as usize as *const u8
+			};
+
+			(slot as *const *const u8).read_unaligned()
+		}
+		(0x68, _) if *code.add(5) == 0xc3 => {
+			(code.add(1) as *const u32).read_unaligned() as usize as *const u8
+		}
+		_ => return None
+	};
+
+	let mut module = ptr::null_mut();
+
+	if GetModuleHandleExW(
+		GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+		destination as *const _,
+		&mut module
+	) == 0
+	{
+		return None;
+	}
+
+	let mut path = [0; 1 << 10];
+	let len = GetModuleFileNameW(module, path.as_mut_ptr(), path.len() as _) as usize;
+
+	if len == 0 {
+		None
+	} else {
+		Some(OsString::from_wide(&path[..len]).into())
+	}
+}
+
+pub(crate) unsafe fn update_process(process: HANDLE, dll: &Path) -> bool {
+	backend::update_process(process, dll)
+}
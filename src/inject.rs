@@ -0,0 +1,292 @@
+use crate::config;
+use std::{env, ffi::OsStr, io::Error, iter, mem, os::windows::ffi::OsStrExt, path::PathBuf, ptr};
+use winapi::{
+	shared::minwindef::{DWORD, FALSE, HMODULE, LPVOID, TRUE},
+	um::{
+		handleapi::CloseHandle,
+		libloaderapi::{
+			FreeLibrary, GetProcAddress, LoadLibraryExW, LoadLibraryW, DONT_RESOLVE_DLL_REFERENCES
+		},
+		memoryapi::{VirtualAllocEx, VirtualFreeEx, WriteProcessMemory},
+		processenv::GetStdHandle,
+		processthreadsapi::{
+			CreateRemoteThread, GetCurrentProcess, GetExitCodeThread, OpenProcess,
+			PROCESS_INFORMATION, STARTUPINFOW
+		},
+		psapi::{EnumProcessModulesEx, GetModuleBaseNameW, LIST_MODULES_ALL},
+		synchapi::WaitForSingleObject,
+		winbase::{
+			CREATE_DEFAULT_ERROR_MODE, CREATE_NO_WINDOW, CREATE_SUSPENDED, DETACHED_PROCESS,
+			INFINITE, STARTF_USESTDHANDLES, STD_ERROR_HANDLE, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE
+		},
+		winnt::{
+			HANDLE, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_READWRITE, PROCESS_CREATE_THREAD,
+			PROCESS_QUERY_INFORMATION, PROCESS_VM_OPERATION, PROCESS_VM_READ, PROCESS_VM_WRITE
+		},
+		wow64apiset::IsWow64Process
+	}
+};
+
+const DLL: &str = "underrail_fix.dll";
+
+pub const CONSOLE_MODES: &[&str] = &["inherit", "hidden", "detached"];
+
+#[cfg(not(feature = "minhook"))]
+pub unsafe fn create_process(
+	exe: &[u16],
+	command_line: &mut [u16],
+	dir: &[u16]
+) -> Result<PROCESS_INFORMATION, Error> {
+	use crate::detours::DetourCreateProcessWithDllExW;
+
+	let mut si = startup_info();
+	let mut pi: PROCESS_INFORMATION = mem::zeroed();
+	let dll = format!("{}\0", DLL);
+
+	let b = DetourCreateProcessWithDllExW(
+		exe.as_ptr(),
+		command_line.as_mut_ptr(),
+		ptr::null_mut(),
+		ptr::null_mut(),
+		TRUE,
+		CREATE_DEFAULT_ERROR_MODE | CREATE_SUSPENDED | console_flags(),
+		ptr::null_mut(),
+		dir.as_ptr(),
+		&mut si as *mut _ as *mut _,
+		&mut pi as *mut _ as *mut _,
+		dll.as_ptr() as _,
+		None
+	);
+
+	if b != TRUE {
+		return Err(Error::last_os_error());
+	}
+
+	Ok(pi)
+}
+
+#[cfg(feature = "minhook")]
+pub unsafe fn create_process(
+	exe: &[u16],
+	command_line: &mut [u16],
+	dir: &[u16]
+) -> Result<PROCESS_INFORMATION, Error> {
+	use winapi::um::processthreadsapi::CreateProcessW;
+
+	let mut si = startup_info();
+	let mut pi: PROCESS_INFORMATION = mem::zeroed();
+
+	let b = CreateProcessW(
+		exe.as_ptr(),
+		command_line.as_mut_ptr(),
+		ptr::null_mut(),
+		ptr::null_mut(),
+		TRUE,
+		CREATE_DEFAULT_ERROR_MODE | CREATE_SUSPENDED | console_flags(),
+		ptr::null_mut(),
+		dir.as_ptr(),
+		&mut si,
+		&mut pi
+	);
+
+	if b != TRUE {
+		return Err(Error::last_os_error());
+	}
+
+	load_library(pi.hProcess, &wide(OsStr::new(DLL)));
+	Ok(pi)
+}
+
+// UNDERRAIL_FIX_GAME_CONSOLE=hidden gives the game a console nobody sees, detached none at all, so
+// a Steam launch doesn't flash the launcher's window
+fn console_flags() -> DWORD {
+	match config::get().game_console.as_deref() {
+		None | Some("inherit") => 0,
+		Some("hidden") => CREATE_NO_WINDOW,
+		Some("detached") => DETACHED_PROCESS,
+		Some(mode) => {
+			panic!(
+				"unknown game console mode {}, expected one of {}",
+				mode,
+				CONSOLE_MODES.join(", ")
+			)
+		}
+	}
+}
+
+unsafe fn startup_info() -> STARTUPINFOW {
+	let mut si: STARTUPINFOW = mem::zeroed();
+	si.cb = mem::size_of::<STARTUPINFOW>() as _;
+	si.dwFlags = STARTF_USESTDHANDLES;
+	si.hStdInput = GetStdHandle(STD_INPUT_HANDLE);
+	si.hStdOutput = GetStdHandle(STD_OUTPUT_HANDLE);
+	si.hStdError = GetStdHandle(STD_ERROR_HANDLE);
+	si
+}
+
+pub unsafe fn attach(pid: DWORD) {
+	let process = OpenProcess(
+		PROCESS_CREATE_THREAD
+			| PROCESS_QUERY_INFORMATION
+			| PROCESS_VM_OPERATION
+			| PROCESS_VM_READ
+			| PROCESS_VM_WRITE,
+		FALSE,
+		pid
+	);
+
+	assert!(
+		!process.is_null(),
+		"OpenProcess {}: {}",
+		pid,
+		Error::last_os_error()
+	);
+
+	let dll = dll_path();
+	let dll_wide = wide(dll.as_os_str());
+
+	load_library(process, &dll_wide);
+
+	let local = LoadLibraryExW(
+		dll_wide.as_ptr(),
+		ptr::null_mut(),
+		DONT_RESOLVE_DLL_REFERENCES
+	);
+
+	assert!(
+		!local.is_null(),
+		"LoadLibraryExW {}: {}",
+		dll.display(),
+		Error::last_os_error()
+	);
+
+	let init = GetProcAddress(local, b"UnderrailFixAttach\0".as_ptr() as _);
+	assert!(!init.is_null(), "UnderrailFixAttach not exported");
+
+	let offset = init as usize - local as usize;
+	FreeLibrary(local);
+
+	let remote = remote_module(process, DLL).expect("injected DLL not found in target process");
+	let status = remote_thread(process, remote as usize + offset, ptr::null_mut());
+
+	CloseHandle(process);
+	assert_eq!(status, 0, "UnderrailFixAttach failed in target process");
+}
+
+pub fn dll_path() -> PathBuf {
+	let mut path = env::current_exe().expect("failed to get current exe path");
+	path.set_file_name(DLL);
+	path
+}
+
+fn wide(s: &OsStr) -> Vec<u16> {
+	s.encode_wide().chain(iter::once(0)).collect()
+}
+
+unsafe fn load_library(process: HANDLE, dll: &[u16]) {
+	let (mut wow64, mut target_wow64) = (0, 0);
+	IsWow64Process(GetCurrentProcess(), &mut wow64);
+	IsWow64Process(process, &mut target_wow64);
+
+	assert_eq!(
+		wow64, target_wow64,
+		"LoadLibraryW injection requires the launcher and game to share an architecture"
+	);
+
+	let len = dll.len() * 2;
+
+	let remote = VirtualAllocEx(
+		process,
+		ptr::null_mut(),
+		len,
+		MEM_COMMIT | MEM_RESERVE,
+		PAGE_READWRITE
+	);
+
+	assert!(
+		!remote.is_null(),
+		"VirtualAllocEx: {}",
+		Error::last_os_error()
+	);
+
+	assert_ne!(
+		WriteProcessMemory(
+			process,
+			remote,
+			dll.as_ptr() as *const _,
+			len,
+			ptr::null_mut()
+		),
+		0,
+		"WriteProcessMemory: {}",
+		Error::last_os_error()
+	);
+
+	let module = remote_thread(process, LoadLibraryW as usize, remote);
+	VirtualFreeEx(process, remote, 0, MEM_RELEASE);
+
+	assert_ne!(module, 0, "LoadLibraryW {} failed in target process", DLL);
+}
+
+unsafe fn remote_thread(process: HANDLE, routine: usize, param: LPVOID) -> DWORD {
+	let thread = CreateRemoteThread(
+		process,
+		ptr::null_mut(),
+		0,
+		Some(mem::transmute(routine)),
+		param,
+		0,
+		ptr::null_mut()
+	);
+
+	assert!(
+		!thread.is_null(),
+		"CreateRemoteThread: {}",
+		Error::last_os_error()
+	);
+
+	assert_ne!(
+		WaitForSingleObject(thread, INFINITE),
+		!0,
+		"WaitForSingleObject: {}",
+		Error::last_os_error()
+	);
+
+	let mut status = 0;
+
+	assert_ne!(
+		GetExitCodeThread(thread, &mut status),
+		0,
+		"GetExitCodeThread: {}",
+		Error::last_os_error()
+	);
+
+	CloseHandle(thread);
+	status
+}
+
+unsafe fn remote_module(process: HANDLE, name: &str) -> Option<HMODULE> {
+	let mut modules = vec![ptr::null_mut(); 1024];
+	let mut needed = 0;
+
+	assert_ne!(
+		EnumProcessModulesEx(
+			process,
+			modules.as_mut_ptr(),
+			(modules.len() * mem::size_of::<HMODULE>()) as _,
+			&mut needed,
+			LIST_MODULES_ALL
+		),
+		0,
+		"EnumProcessModulesEx: {}",
+		Error::last_os_error()
+	);
+
+	modules.truncate(needed as usize / mem::size_of::<HMODULE>());
+
+	modules.into_iter().find(|&module| {
+		let mut buf = [0; 260];
+		let len = GetModuleBaseNameW(process, module, buf.as_mut_ptr(), buf.len() as _);
+		String::from_utf16_lossy(&buf[..len as usize]).eq_ignore_ascii_case(name)
+	})
+}
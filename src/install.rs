@@ -0,0 +1,167 @@
+use crate::{config, game};
+use std::{env, fs, path::PathBuf};
+#[cfg(windows)]
+use std::{ffi::OsString, iter, os::windows::ffi::OsStringExt, ptr};
+#[cfg(windows)]
+use winapi::{
+	shared::winerror::ERROR_SUCCESS,
+	um::{
+		winnt::KEY_READ,
+		winreg::{
+			RegCloseKey, RegEnumKeyExW, RegGetValueW, RegOpenKeyExW, HKEY, HKEY_CURRENT_USER,
+			HKEY_LOCAL_MACHINE, RRF_RT_REG_SZ
+		}
+	}
+};
+
+const STEAM_DIR: &str = "Underrail";
+
+// UNDERRAIL_FIX_STORE picks the install when the game is in more than one library
+pub fn find() -> Option<PathBuf> {
+	match config::get().store.map(|s| s.to_lowercase()).as_deref() {
+		Some("gog") => gog().or_else(steam),
+		_ => steam().or_else(gog)
+	}
+}
+
+pub fn steam() -> Option<PathBuf> {
+	let steam = steam_root()?;
+	let mut libraries = vec![steam.clone()];
+
+	if let Ok(vdf) = fs::read_to_string(steam.join("steamapps").join("libraryfolders.vdf")) {
+		libraries.extend(library_folders(&vdf));
+	}
+
+	libraries
+		.into_iter()
+		.map(|library| library.join("steamapps").join("common").join(STEAM_DIR))
+		.find(|dir| game::find(dir).is_some())
+}
+
+#[cfg(windows)]
+fn steam_root() -> Option<PathBuf> {
+	[
+		(HKEY_CURRENT_USER, r"Software\Valve\Steam", "SteamPath"),
+		(
+			HKEY_LOCAL_MACHINE,
+			r"SOFTWARE\WOW6432Node\Valve\Steam",
+			"InstallPath"
+		),
+		(HKEY_LOCAL_MACHINE, r"SOFTWARE\Valve\Steam", "InstallPath")
+	]
+	.iter()
+	.find_map(|&(key, subkey, value)| unsafe { reg_string(key, subkey, value) })
+	.map(PathBuf::from)
+}
+
+// the Steam Deck and most distro packages use one of these
+#[cfg(not(windows))]
+fn steam_root() -> Option<PathBuf> {
+	let home = PathBuf::from(env::var_os("HOME")?);
+
+	[".steam/steam", ".local/share/Steam"]
+		.iter()
+		.map(|dir| home.join(dir))
+		.find(|dir| dir.is_dir())
+}
+
+#[cfg(windows)]
+pub fn gog() -> Option<PathBuf> {
+	[
+		r"SOFTWARE\WOW6432Node\GOG.com\Games",
+		r"SOFTWARE\GOG.com\Games"
+	]
+	.iter()
+	.flat_map(|&games| {
+		unsafe { subkeys(HKEY_LOCAL_MACHINE, games) }
+			.into_iter()
+			.map(move |game| format!(r"{}\{}", games, game))
+	})
+	.filter_map(|game| unsafe { reg_string(HKEY_LOCAL_MACHINE, &game, "path") })
+	.map(PathBuf::from)
+	.find(|dir| game::find(dir).is_some())
+}
+
+// GOG copies on Linux live in launcher specific Wine prefixes, so pass --exe for those
+#[cfg(not(windows))]
+pub fn gog() -> Option<PathBuf> {
+	None
+}
+
+// both the old ("1" "D:\\Steam") and new ("path" "D:\\Steam") layouts
+fn library_folders(vdf: &str) -> Vec<PathBuf> {
+	vdf.lines()
+		.filter_map(|line| {
+			let mut quoted = line.split('"').skip(1).step_by(2);
+			let (key, value) = (quoted.next()?, quoted.next()?);
+
+			if key == "path" || key.parse::<u32>().is_ok() {
+				Some(PathBuf::from(value.replace(r"\\", r"\")))
+			} else {
+				None
+			}
+		})
+		.collect()
+}
+
+#[cfg(windows)]
+unsafe fn reg_string(key: HKEY, subkey: &str, value: &str) -> Option<String> {
+	let subkey: Vec<_> = subkey.encode_utf16().chain(iter::once(0)).collect();
+	let value: Vec<_> = value.encode_utf16().chain(iter::once(0)).collect();
+	let mut buf = vec![0u16; 1024];
+	let mut len = (buf.len() * 2) as u32;
+
+	let error = RegGetValueW(
+		key,
+		subkey.as_ptr(),
+		value.as_ptr(),
+		RRF_RT_REG_SZ,
+		ptr::null_mut(),
+		buf.as_mut_ptr() as _,
+		&mut len
+	);
+
+	if error as u32 != ERROR_SUCCESS {
+		return None;
+	}
+
+	buf.truncate((len as usize / 2).saturating_sub(1));
+	Some(OsString::from_wide(&buf).to_string_lossy().into_owned())
+}
+
+#[cfg(windows)]
+unsafe fn subkeys(key: HKEY, subkey: &str) -> Vec<String> {
+	let subkey: Vec<_> = subkey.encode_utf16().chain(iter::once(0)).collect();
+	let mut handle = ptr::null_mut();
+
+	if RegOpenKeyExW(key, subkey.as_ptr(), 0, KEY_READ, &mut handle) as u32 != ERROR_SUCCESS {
+		return Vec::new();
+	}
+
+	let mut keys = Vec::new();
+
+	for i in 0.. {
+		let mut buf = [0u16; 256];
+		let mut len = buf.len() as u32;
+
+		let error = RegEnumKeyExW(
+			handle,
+			i,
+			buf.as_mut_ptr(),
+			&mut len,
+			ptr::null_mut(),
+			ptr::null_mut(),
+			ptr::null_mut(),
+			ptr::null_mut()
+		);
+
+		if error as u32 != ERROR_SUCCESS {
+			break;
+		}
+
+		keys.push(String::from_utf16_lossy(&buf[..len as usize]));
+	}
+
+	RegCloseKey(handle);
+	keys
+}
@@ -0,0 +1,196 @@
+use crate::config;
+use parking_lot::Mutex;
+use std::{
+	fmt::{Debug, Write as _},
+	fs::{self, File, OpenOptions},
+	io::Write,
+	path::{Path, PathBuf},
+	process,
+	sync::atomic::{AtomicUsize, Ordering},
+	time::{SystemTime, UNIX_EPOCH}
+};
+use tracing::{
+	field::{Field, Visit},
+	Event, Level, Subscriber
+};
+use tracing_subscriber::{filter::LevelFilter, layer::Context, Layer};
+
+pub const LOG_FILE: &str = "underrail_fix.log";
+
+const LOG_MAX_SIZE: u64 = 1 << 20;
+const LOG_BACKUPS: usize = 3;
+
+const LEVELS: [LevelFilter; 6] = [
+	LevelFilter::OFF,
+	LevelFilter::ERROR,
+	LevelFilter::WARN,
+	LevelFilter::INFO,
+	LevelFilter::DEBUG,
+	LevelFilter::TRACE
+];
+
+static LEVEL: AtomicUsize = AtomicUsize::new(0);
+
+pub struct LogLayer {
+	file: Option<Mutex<LogFile>>,
+	dir: PathBuf,
+	max_size: u64,
+	source: &'static str,
+	console: bool
+}
+
+// len is the size at open plus what this process appended since, which falls behind when the dll
+// and the launcher both write to the file
+struct LogFile {
+	file: File,
+	len: u64
+}
+
+struct Visitor(String);
+
+impl LogLayer {
+	pub fn new(dir: &Path, rotate: bool, source: &'static str, console: bool) -> Self {
+		let max_size = max_size();
+
+		if rotate {
+			self::rotate(dir, max_size);
+		}
+
+		set_level(level());
+		Self {
+			file: open(dir).map(Mutex::new),
+			dir: dir.to_path_buf(),
+			max_size,
+			source,
+			console
+		}
+	}
+}
+
+impl<S: Subscriber> Layer<S> for LogLayer {
+	fn on_event(&self, event: &Event, _: Context<S>) {
+		let metadata = event.metadata();
+
+		if *metadata.level() > current_level() {
+			return;
+		}
+
+		let mut visitor = Visitor(String::new());
+		event.record(&mut visitor);
+
+		if self.console && *metadata.level() <= Level::INFO {
+			eprintln!("{}", visitor.0);
+		}
+
+		if let Some(file) = &self.file {
+			let since = SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.unwrap_or_default();
+
+			let line = format!(
+				"{}.{:03} {} {} {:5} {}: {}\n",
+				since.as_secs(),
+				since.subsec_millis(),
+				process::id(),
+				self.source,
+				metadata.level(),
+				metadata.target(),
+				visitor.0
+			);
+
+			let mut file = file.lock();
+
+			if file.file.write_all(line.as_bytes()).is_ok() {
+				file.len += line.len() as u64;
+			}
+
+			// a long session would otherwise grow the log until the next launch
+			if file.len >= self.max_size {
+				// unless the other process sharing the log already rotated it
+				let log = self.dir.join(LOG_FILE);
+
+				if log.metadata().map_or(true, |m| m.len() >= self.max_size) {
+					rotate(&self.dir, self.max_size);
+				}
+
+				if let Some(reopened) = open(&self.dir) {
+					*file = reopened;
+				}
+			}
+		}
+	}
+}
+
+impl Visit for Visitor {
+	fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+		if field.name() == "message" {
+			let _ = write!(self.0, "{:?}", value);
+		} else {
+			let _ = write!(self.0, " {}={:?}", field.name(), value);
+		}
+	}
+}
+
+fn open(dir: &Path) -> Option<LogFile> {
+	let file = OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(dir.join(LOG_FILE))
+		.ok()?;
+
+	let len = file.metadata().map_or(0, |m| m.len());
+	Some(LogFile { file, len })
+}
+
+fn max_size() -> u64 {
+	config::get()
+		.log_max_size
+		.map_or(LOG_MAX_SIZE, |n| n as u64)
+}
+
+// launches through Steam never show the console, so keep a few runs around instead of truncating
+fn rotate(dir: &Path, max_size: u64) {
+	let log = dir.join(LOG_FILE);
+
+	if log.metadata().map_or(true, |m| m.len() < max_size) {
+		return;
+	}
+
+	for i in (1..LOG_BACKUPS).rev() {
+		fs::rename(backup(dir, i), backup(dir, i + 1)).ok();
+	}
+
+	fs::rename(&log, backup(dir, 1)).ok();
+}
+
+fn backup(dir: &Path, i: usize) -> PathBuf {
+	dir.join(format!("{}.{}", LOG_FILE, i))
+}
+
+pub fn level() -> LevelFilter {
+	config::get()
+		.log
+		.and_then(|s| parse_level(&s))
+		.unwrap_or(LevelFilter::INFO)
+}
+
+pub fn current_level() -> LevelFilter {
+	LEVELS[LEVEL.load(Ordering::Relaxed)]
+}
+
+pub fn set_level(level: LevelFilter) {
+	let index = LEVELS.iter().position(|&l| l == level).unwrap();
+	LEVEL.store(index, Ordering::Relaxed);
+}
+
+pub fn parse_level(s: &str) -> Option<LevelFilter> {
+	match s.to_lowercase().as_str() {
+		"off" | "0" => Some(LevelFilter::OFF),
+		"error" => Some(LevelFilter::ERROR),
+		"warn" => Some(LevelFilter::WARN),
+		"info" => Some(LevelFilter::INFO),
+		"debug" => Some(LevelFilter::DEBUG),
+		"trace" | "1" => Some(LevelFilter::TRACE),
+		_ => None
+	}
+}
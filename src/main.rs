@@ -1,93 +1,716 @@
-#[cfg(not(windows))]
-compile_error!("not windows");
-
-use crate::detours::DetourCreateProcessWithDllExW;
-use std::{env, io::Error, mem, os::windows::ffi::OsStrExt, path::PathBuf, ptr};
-use vfs::Vfs;
+use anyhow::Context;
+use clap::Parser;
+#[cfg(windows)]
+use cli::TraceCommand;
+use cli::{Cli, Command, LangCommand};
+use logging::LogLayer;
+#[cfg(windows)]
+use priority::Priority;
+#[cfg(windows)]
+use stats::Stats;
+use std::{
+	env, panic,
+	path::{Path, PathBuf},
+	process,
+	sync::atomic::{AtomicBool, Ordering}
+};
+#[cfg(windows)]
+use std::{
+	ffi::{OsStr, OsString},
+	fs::OpenOptions,
+	io::{self, Error, Read, Write},
+	iter,
+	os::windows::ffi::OsStrExt,
+	thread,
+	time::Duration
+};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+#[cfg(windows)]
+use underrail_fix::bench;
+use underrail_fix::{config, vfs};
+#[cfg(windows)]
 use winapi::{
-	shared::minwindef::TRUE,
+	shared::minwindef::{BOOL, DWORD, FALSE, TRUE},
 	um::{
-		processthreadsapi::{GetExitCodeProcess, ResumeThread, PROCESS_INFORMATION, STARTUPINFOW},
+		consoleapi::SetConsoleCtrlHandler,
+		processthreadsapi::{GetExitCodeProcess, ResumeThread, TerminateProcess},
 		synchapi::WaitForSingleObject,
-		winbase::{CREATE_DEFAULT_ERROR_MODE, CREATE_SUSPENDED, INFINITE}
+		winbase::INFINITE,
+		wincon::{GetConsoleWindow, CTRL_BREAK_EVENT, CTRL_C_EVENT}
 	}
 };
 
+mod build_info;
+mod cli;
+#[cfg(all(windows, not(feature = "minhook")))]
 mod detours;
-mod vfs;
+#[cfg(windows)]
+mod doctor;
+#[cfg(windows)]
+mod elevate;
+mod game;
+#[cfg(feature = "gui")]
+mod gui;
+#[cfg(windows)]
+mod inject;
+mod install;
+mod logging;
+#[cfg(windows)]
+mod priority;
+#[cfg(not(windows))]
+mod proton;
+#[cfg(windows)]
+mod replay;
+#[cfg(windows)]
+mod report;
+#[cfg(windows)]
+mod shortcut;
+#[cfg(windows)]
+mod startup;
+#[cfg(windows)]
+mod stats;
+#[cfg(windows)]
+mod steam;
+#[cfg(feature = "update")]
+mod update;
+#[cfg(windows)]
+mod version;
+#[cfg(windows)]
+mod watch;
+#[cfg(windows)]
+mod watchdog;
+
+// set once errors have somewhere to be logged
+static LOGGING: AtomicBool = AtomicBool::new(false);
 
 fn main() {
-	let exe = env::var("UNDERRAIL_EXE").map_or_else(
-		|_| {
-			let mut exe = env::current_exe().expect("failed to get current exe path");
+	if let Err(e) = launch() {
+		fail(e);
+	}
+}
+
+fn launch() -> anyhow::Result<()> {
+	let current = env::current_exe().context("failed to get the launcher's path")?;
+	load_config(current.parent().unwrap())?;
+	let replaced = current
+		.file_name()
+		.unwrap()
+		.to_string_lossy()
+		.to_lowercase()
+		== game::exe_name();
+
+	// installed in place of the game exe, so every argument belongs to the game
+	#[cfg(windows)]
+	let cli = if replaced {
+		Cli {
+			exe: None,
+			profile: None,
+			install: None,
+			verbose: 0,
+			quiet: false,
+			json: false,
+			command: Some(Command::Run {
+				no_pack: false,
+				verify_on_launch: false,
+				priority: None,
+				affinity: None,
+				console: None,
+				pause: false,
+				watchdog: None,
+				args: env::args_os().skip(1).collect()
+			})
+		}
+	} else if let Some(exe) = steam_command() {
+		Cli {
+			exe: Some(exe),
+			profile: None,
+			install: None,
+			verbose: 0,
+			quiet: false,
+			json: false,
+			command: Some(Command::Run {
+				no_pack: false,
+				verify_on_launch: false,
+				priority: None,
+				affinity: None,
+				console: None,
+				pause: false,
+				watchdog: None,
+				args: env::args_os().skip(2).collect()
+			})
+		}
+	} else {
+		Cli::parse()
+	};
+
+	// only the packer is built outside Windows
+	#[cfg(not(windows))]
+	let cli = Cli::parse();
+
+	if let Some(profile) = &cli.profile {
+		env::set_var("UNDERRAIL_FIX_PROFILE", profile);
+	}
+
+	if let Some(install) = &cli.install {
+		env::set_var("UNDERRAIL_FIX_INSTALL", install);
+	}
+
+	// apply the selected install and profile from the launcher's own config too
+	load_config(current.parent().unwrap())?;
+
+	let exe = cli
+		.exe
+		.clone()
+		.or_else(|| config::get().exe)
+		.unwrap_or_else(|| {
+			let dir = current.parent().unwrap();
 
-			let s = if exe.file_name().unwrap().to_str().unwrap() == "underrail.exe" {
-				"underrail.original.exe"
+			if replaced {
+				dir.join(game::original_exe_name())
 			} else {
-				"underrail.exe"
-			};
+				game::find(dir)
+					.or_else(|| install::find().and_then(|dir| game::find(&dir)))
+					.unwrap_or_else(|| dir.join(game::exe_name()))
+			}
+		});
 
-			exe.set_file_name(s);
-			exe
-		},
-		PathBuf::from
-	);
+	let path = exe.parent().unwrap();
+	load_config(path)?;
 
-	Vfs::create(exe.parent().unwrap().into());
+	if let Some(level) = cli::log_level(cli.verbose, cli.quiet) {
+		env::set_var("UNDERRAIL_FIX_LOG", level);
+	}
+
+	#[cfg(feature = "gui")]
+	if gui::wanted() {
+		gui::open();
+	}
+
+	let registry = tracing_subscriber::registry()
+		.with(logging::level())
+		.with(LogLayer::new(path, true, "launcher", !cli.json));
 
-	let mut exe: Vec<_> = exe.as_os_str().encode_wide().collect();
-	exe.push(0);
+	#[cfg(feature = "gui")]
+	let registry = registry.with(gui::GuiLayer);
 
+	registry.init();
+	LOGGING.store(true, Ordering::Relaxed);
+	vfs::detect_dirs(path);
+
+	#[cfg(windows)]
+	if let Some(version) = version::read(&exe) {
+		vfs::set_game_version(version.0);
+	}
+
+	#[cfg(windows)]
 	unsafe {
-		let mut si: STARTUPINFOW = mem::zeroed();
-		si.cb = mem::size_of::<STARTUPINFOW>() as _;
-
-		let mut pi: PROCESS_INFORMATION = mem::zeroed();
-
-		let b = DetourCreateProcessWithDllExW(
-			exe.as_ptr(),
-			ptr::null_mut(),
-			ptr::null_mut(),
-			ptr::null_mut(),
-			TRUE,
-			CREATE_DEFAULT_ERROR_MODE | CREATE_SUSPENDED,
-			ptr::null_mut(),
-			ptr::null_mut(),
-			&mut si as *mut _ as *mut _,
-			&mut pi as *mut _ as *mut _,
-			b"underrail_fix.dll\0".as_ptr() as _,
-			None
-		);
-
-		assert_eq!(
-			b,
-			TRUE,
-			"DetourCreateProcessWithDllExW: {}",
-			Error::last_os_error()
-		);
-
-		assert_ne!(
-			ResumeThread(pi.hThread),
-			!0,
-			"ResumeThread: {}",
-			Error::last_os_error()
-		);
-
-		assert_ne!(
-			WaitForSingleObject(pi.hProcess, INFINITE),
-			!0,
-			"WaitForSingleObject: {}",
-			Error::last_os_error()
-		);
+		SetConsoleCtrlHandler(Some(ctrl_handler), TRUE);
+	}
+
+	let default = panic::take_hook();
+
+	// the location and backtrace only matter to us, so they go to the log unless asked for
+	panic::set_hook(Box::new(move |info| {
+		let message = info
+			.payload()
+			.downcast_ref::<&str>()
+			.map(|s| s.to_string())
+			.or_else(|| info.payload().downcast_ref::<String>().cloned())
+			.unwrap_or_else(|| info.to_string());
+
+		tracing::error!("{}", message);
+		tracing::debug!("{}", info);
+
+		#[cfg(feature = "gui")]
+		if gui::active() {
+			gui::error(&message);
+		}
+
+		if env::var_os("RUST_BACKTRACE").is_some() {
+			default(info);
+		}
+
+		process::exit(1);
+	}));
+
+	#[cfg(not(windows))]
+	let command = cli
+		.command
+		.context("no command given, only the packing commands are available outside Windows")?;
+
+	#[cfg(windows)]
+	let command = cli.command.unwrap_or(Command::Run {
+		no_pack: false,
+		verify_on_launch: false,
+		priority: None,
+		affinity: None,
+		console: None,
+		pause: false,
+		watchdog: None,
+		args: Vec::new()
+	});
+
+	#[cfg(windows)]
+	if let Command::Pack { .. }
+	| Command::Run { .. }
+	| Command::Attach { .. }
+	| Command::Bench { .. }
+	| Command::Put { .. }
+	| Command::Watch = command
+	{
+		let args: Vec<_> = env::args_os().skip(1).collect();
+		elevate::check(path, &quote(args.iter().map(|a| a.as_os_str())));
+	}
+
+	match command {
+		Command::Pack {
+			threads,
+			buffer_size,
+			force
+		} => {
+			if let Some(threads) = threads {
+				env::set_var("UNDERRAIL_FIX_PACK_THREADS", threads.to_string());
+			}
+
+			if let Some(buffer_size) = buffer_size {
+				env::set_var("UNDERRAIL_FIX_PACK_BUFFER_SIZE", buffer_size.to_string());
+			}
+
+			// only the top level mtime is checked, which misses edits deeper in the tree
+			if force {
+				cli::clean_archives(path);
+			}
+
+			cli::pack(path)?;
+		}
+		#[cfg(windows)]
+		Command::Watch => {
+			cli::pack(path)?;
+			watch::watch(path)?;
+		}
+		Command::Verify => {
+			if !cli::verify(path, cli.json)? {
+				process::exit(1);
+			}
+		}
+		Command::List { pattern } => cli::list(path, &pattern, cli.json)?,
+		Command::Cat { entry, output } => cli::cat(path, &entry, output.as_deref())?,
+		Command::Put { source, entry } => cli::put(path, &source, &entry)?,
+		Command::DumpIndex {
+			output,
+			format,
+			no_hash
+		} => {
+			cli::dump_index(
+				path,
+				output.as_deref(),
+				cli.json || format == "json",
+				!no_hash
+			)?
+		}
+		Command::Extract { dest, pattern } => cli::extract(path, &pattern, &dest)?,
+		Command::Info => cli::info(path, cli.json),
+		#[cfg(windows)]
+		Command::Stats { pid } => cli::stats(pid, cli.json),
+		Command::Diff { other } => {
+			if !cli::diff(path, &other, cli.json)? {
+				process::exit(1);
+			}
+		}
+		#[cfg(windows)]
+		Command::Run {
+			no_pack,
+			verify_on_launch,
+			priority,
+			affinity,
+			console,
+			pause,
+			watchdog,
+			args
+		} => {
+			if let Some(priority) = priority {
+				env::set_var("UNDERRAIL_FIX_PRIORITY", priority);
+			}
+
+			if let Some(affinity) = affinity {
+				env::set_var("UNDERRAIL_FIX_AFFINITY", affinity);
+			}
+
+			if let Some(console) = console {
+				env::set_var("UNDERRAIL_FIX_GAME_CONSOLE", console);
+			}
+
+			if pause {
+				env::set_var("UNDERRAIL_FIX_PAUSE", "1");
+			}
+
+			if let Some(watchdog) = watchdog {
+				env::set_var("UNDERRAIL_FIX_WATCHDOG", watchdog.to_string());
+			}
+
+			tracing::info!("{}, launching {}", build_info::build_info(), exe.display());
+
+			if no_pack || config::get().no_pack == Some(true) {
+				tracing::info!("skipping pack check");
+			} else {
+				cli::pack(path)?;
+				cli::update_if_outdated(path, &exe)?;
+			}
+
+			if (verify_on_launch || config::get().verify_on_launch == Some(true))
+				&& !cli::verify_or_repack(path)?
+			{
+				anyhow::bail!("archive verification failed, not launching");
+			}
+
+			run(&exe, &args)?;
+		}
+		#[cfg(windows)]
+		Command::Attach { pid } => {
+			cli::pack(path)?;
+			unsafe { inject::attach(pid) };
+			tracing::info!("attached to {}", pid);
+		}
+		#[cfg(windows)]
+		Command::Install => cli::install(path),
+		#[cfg(windows)]
+		Command::Uninstall => cli::uninstall(path),
+		#[cfg(windows)]
+		Command::Shortcut { start_menu } => shortcut::create(&exe, start_menu),
+		#[cfg(windows)]
+		Command::SteamOptions { clipboard } => {
+			let options = steam::launch_options();
+			println!("{}", options);
+
+			for problem in steam::problems(&exe) {
+				tracing::warn!("{}", problem);
+			}
+
+			if clipboard {
+				steam::copy_to_clipboard(&options);
+				tracing::info!("copied launch options to the clipboard");
+			}
+		}
+		#[cfg(windows)]
+		Command::Trace { command } => {
+			match command {
+				TraceCommand::Record { output } => {
+					let output = output.unwrap_or_else(|| path.join(replay::RECORD_FILE));
+					env::set_var("UNDERRAIL_FIX_RECORD", &output);
+					cli::pack(path)?;
+					tracing::info!("recording reads to {}", output.display());
+					run(&exe, &[])?;
+				}
+				TraceCommand::Replay { input } => {
+					let input = input.unwrap_or_else(|| path.join(replay::RECORD_FILE));
+					print!("{}", replay::replay(path, &input)?);
+				}
+			}
+		}
+		Command::Lang { command } => {
+			match command {
+				LangCommand::Install { source, name } => cli::lang_install(path, &source, name)?,
+				LangCommand::Remove { name } => cli::lang_remove(path, &name),
+				LangCommand::List => cli::lang_list(path)?
+			}
+		}
+		Command::Clean { dry_run } => cli::clean(path, dry_run),
+		#[cfg(not(windows))]
+		Command::Proton { proxy } => {
+			cli::pack(path)?;
+			let problems = proton::setup(path, &proxy);
+
+			println!("Steam launch options: {}", proton::launch_options(&proxy));
+
+			for problem in &problems {
+				tracing::warn!("{}", problem);
+			}
+
+			if !problems.is_empty() {
+				process::exit(1);
+			}
+		}
+		#[cfg(feature = "update")]
+		Command::CheckUpdate => update::check(),
+		#[cfg(windows)]
+		Command::Doctor => {
+			let problems = doctor::problems(path, &exe);
+
+			if cli.json {
+				println!("{}", serde_json::json!({ "problems": problems }));
+			} else {
+				for problem in &problems {
+					println!("{}", problem);
+				}
+
+				if problems.is_empty() {
+					println!("no problems found");
+				}
+			}
+
+			if !problems.is_empty() {
+				process::exit(1);
+			}
+		}
+		#[cfg(windows)]
+		Command::Bench { launch, runs } => {
+			cli::pack(path)?;
+
+			if launch {
+				print!("{}", startup::bench(&exe, runs));
+			} else {
+				print!(
+					"{}",
+					bench::run(path.into()).context("failed to open the archive")?
+				);
+			}
+		}
+	}
+
+	Ok(())
+}
+
+// the config is read before logging is set up, since it decides where and how much to log
+fn load_config(dir: &Path) -> anyhow::Result<()> {
+	config::load(dir)
+		.map_err(anyhow::Error::msg)
+		.context("failed to load the config")
+}
+
+#[cfg(windows)]
+// Steam launch options of `"launcher.exe" %command%` pass the game exe as the first argument
+fn steam_command() -> Option<PathBuf> {
+	let exe = PathBuf::from(env::args_os().nth(1)?);
+
+	if exe.is_file() && game::is_game(&exe) {
+		Some(exe)
+	} else {
+		None
+	}
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn ctrl_handler(ctrl_type: DWORD) -> BOOL {
+	if (ctrl_type == CTRL_C_EVENT || ctrl_type == CTRL_BREAK_EVENT) && vfs::abort() {
+		TRUE
+	} else {
+		FALSE
+	}
+}
+
+#[cfg(windows)]
+fn run(exe: &Path, args: &[OsString]) -> anyhow::Result<()> {
+	if !version::check(exe) {
+		process::exit(1);
+	}
+
+	let priority = Priority::from_env();
+	let watchdog = watchdog::timeout();
+	let mut command_line = command_line(exe, args);
+	let dir = exe.parent().unwrap();
+	let dir_wide: Vec<_> = dir.as_os_str().encode_wide().chain(iter::once(0)).collect();
+	let exe_wide: Vec<_> = exe.as_os_str().encode_wide().chain(iter::once(0)).collect();
+
+	unsafe {
+		let pi =
+			inject::create_process(&exe_wide, &mut command_line, &dir_wide).with_context(|| {
+				format!(
+					"Couldn't start {} with the fix injected. Check that the game is installed \
+					 there and that your antivirus hasn't quarantined {}",
+					exe.display(),
+					inject::dll_path().display()
+				)
+			})?;
+
+		if let Err(e) = priority.apply(pi.hProcess) {
+			TerminateProcess(pi.hProcess, 1);
+			return Err(e).context(
+				"Couldn't set the game's priority or affinity, check that the cores exist on this \
+				 machine"
+			);
+		}
+
+		if ResumeThread(pi.hThread) == !0 {
+			return Err(Error::last_os_error()).context(
+				"Couldn't resume the game after injecting the fix. Try running the launcher as \
+				 administrator"
+			);
+		}
+
+		#[cfg(feature = "gui")]
+		gui::close();
+
+		let pid = pi.dwProcessId;
+		tracing::info!("started the game as pid {} with the fix injected", pid);
+		thread::spawn(move || verify_build(pid));
+		let stats = thread::spawn(move || open_stats(pid));
+
+		if let Some(timeout) = watchdog {
+			let (dir, process) = (dir.to_owned(), pi.hProcess as usize);
+			thread::spawn(move || watchdog::watch(&dir, pid, process, timeout));
+		}
+
+		if WaitForSingleObject(pi.hProcess, INFINITE) == !0 {
+			return Err(Error::last_os_error())
+				.context("Lost track of the game while waiting for it to exit");
+		}
 
 		let mut exit_code = 0;
 
-		assert_ne!(
-			GetExitCodeProcess(pi.hProcess, &mut exit_code),
-			0,
-			"GetExitCodeProcess: {:#x} {}",
-			exit_code,
-			Error::last_os_error()
-		);
+		if GetExitCodeProcess(pi.hProcess, &mut exit_code) == 0 {
+			return Err(Error::last_os_error())
+				.context("Couldn't tell how the game exited, check underrail_fix.log for errors");
+		}
+
+		if let Some(stats) = stats.join().unwrap() {
+			tracing::info!("{}", stats.summary());
+		}
+
+		if report::crashed(exit_code) {
+			report::crash(dir, pid, exit_code);
+		} else if exit_code != 0 {
+			tracing::warn!("game exited with {:#x}", exit_code);
+		} else {
+			tracing::info!("game exited normally");
+		}
+
+		pause();
+		process::exit(exit_code as i32);
+	}
+}
+
+// UNDERRAIL_FIX_PAUSE=1 keeps the console open for reading the log after a launch from Steam or
+// Explorer, which would otherwise close it with the game
+#[cfg(windows)]
+fn pause() {
+	if config::get().pause != Some(true) || unsafe { GetConsoleWindow() }.is_null() {
+		return;
+	}
+
+	eprint!("press Enter to close");
+	io::stdin().read_line(&mut String::new()).ok();
+}
+
+fn fail(e: anyhow::Error) -> ! {
+	// Ctrl+C stopped a pack, which already removed the partial archive and logged it
+	if e.chain()
+		.filter_map(|e| e.downcast_ref::<std::io::Error>())
+		.any(vfs::aborted)
+	{
+		process::exit(130);
+	}
+
+	if LOGGING.load(Ordering::Relaxed) {
+		tracing::error!("{:#}", e);
+	} else {
+		eprintln!("error: {:#}", e);
+	}
+
+	#[cfg(feature = "gui")]
+	if gui::active() {
+		gui::error(&format!("{:#}", e));
+	}
+
+	#[cfg(windows)]
+	pause();
+
+	process::exit(1);
+}
+
+#[cfg(windows)]
+fn command_line(exe: &Path, args: &[OsString]) -> Vec<u16> {
+	quote(iter::once(exe.as_os_str()).chain(args.iter().map(|a| a.as_os_str())))
+}
+
+#[cfg(windows)]
+fn quote<'a>(args: impl Iterator<Item = &'a OsStr>) -> Vec<u16> {
+	let mut line = Vec::new();
+
+	for (i, arg) in args.enumerate() {
+		if i > 0 {
+			line.push(b' ' as u16);
+		}
+
+		let arg: Vec<_> = arg.encode_wide().collect();
+		let quote = arg.is_empty()
+			|| arg
+				.iter()
+				.any(|&c| c == b' ' as u16 || c == b'\t' as u16 || c == b'"' as u16);
+
+		if !quote {
+			line.extend(arg);
+			continue;
+		}
+
+		line.push(b'"' as u16);
+		let mut backslashes = 0;
+
+		for c in arg {
+			if c == b'\\' as u16 {
+				backslashes += 1;
+			} else {
+				if c == b'"' as u16 {
+					line.extend(iter::repeat(b'\\' as u16).take(backslashes + 1));
+				}
+
+				backslashes = 0;
+			}
+
+			line.push(c);
+		}
+
+		line.extend(iter::repeat(b'\\' as u16).take(backslashes));
+		line.push(b'"' as u16);
+	}
+
+	line.push(0);
+	line
+}
+
+#[cfg(windows)]
+fn verify_build(pid: u32) {
+	let pipe = format!(r"\\.\pipe\underrail-fix.{}", pid);
+
+	for _ in 0..50 {
+		if let Ok(mut pipe) = OpenOptions::new().read(true).write(true).open(&pipe) {
+			let mut reply = vec![0; 4096];
+
+			let len = pipe
+				.write_all(b"build-info")
+				.and_then(|()| pipe.read(&mut reply))
+				.unwrap_or(0);
+
+			let reply = String::from_utf8_lossy(&reply[..len]);
+
+			if reply == build_info::build_info() {
+				tracing::debug!("injected DLL matches launcher build");
+			} else {
+				tracing::warn!(
+					"injected DLL build differs from launcher: {} vs {}",
+					reply,
+					build_info::build_info()
+				);
+			}
+
+			return;
+		}
+
+		thread::sleep(Duration::from_millis(200));
+	}
+
+	tracing::debug!("control pipe not available, skipping DLL build check");
+}
+
+#[cfg(windows)]
+fn open_stats(pid: u32) -> Option<&'static Stats> {
+	for _ in 0..50 {
+		if let Some(stats) = Stats::open(pid) {
+			return Some(stats);
+		}
+
+		thread::sleep(Duration::from_millis(200));
 	}
+
+	tracing::debug!("shared stats not available, skipping summary");
+	None
 }
@@ -0,0 +1,157 @@
+use crate::path_to_wide;
+use parking_lot::Mutex;
+use std::{
+	collections::HashMap,
+	io::Error,
+	os::windows::io::AsRawHandle,
+	path::{Path, PathBuf},
+	ptr,
+	sync::Arc,
+	thread::{self, JoinHandle}
+};
+use winapi::{
+	shared::minwindef::{FALSE, TRUE},
+	um::{
+		fileapi::{CreateFileW, OPEN_EXISTING},
+		handleapi::{CloseHandle, INVALID_HANDLE_VALUE},
+		ioapiset::CancelSynchronousIo,
+		winbase::{ReadDirectoryChangesW, FILE_FLAG_BACKUP_SEMANTICS},
+		winnt::{
+			FILE_LIST_DIRECTORY, FILE_NOTIFY_CHANGE_DIR_NAME, FILE_NOTIFY_CHANGE_FILE_NAME,
+			FILE_NOTIFY_CHANGE_LAST_WRITE, FILE_NOTIFY_CHANGE_SIZE, FILE_SHARE_DELETE,
+			FILE_SHARE_READ, FILE_SHARE_WRITE, HANDLE
+		}
+	}
+};
+
+const NOTIFY_BUF_LEN: usize = 1 << 16;
+
+pub(crate) struct Overlay {
+	path: PathBuf,
+	cache: Arc<Mutex<HashMap<PathBuf, Option<PathBuf>>>>,
+	dir: Option<HANDLE>,
+	thread: Mutex<Option<JoinHandle<()>>>
+}
+
+impl Overlay {
+	pub(crate) fn new(path: PathBuf) -> Self {
+		let dir = if path.is_dir() {
+			let wide = path_to_wide(&path);
+
+			let dir = unsafe {
+				CreateFileW(
+					wide.as_ptr(),
+					FILE_LIST_DIRECTORY,
+					FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+					ptr::null_mut(),
+					OPEN_EXISTING,
+					FILE_FLAG_BACKUP_SEMANTICS,
+					ptr::null_mut()
+				)
+			};
+
+			if dir == INVALID_HANDLE_VALUE {
+				tracing::warn!(
+					"CreateFileW overlay: {} {}, overlay disabled",
+					path.display(),
+					Error::last_os_error()
+				);
+
+				None
+			} else {
+				Some(dir)
+			}
+		} else {
+			None
+		};
+
+		Self {
+			path,
+			cache: Arc::new(Mutex::new(HashMap::new())),
+			dir,
+			thread: Mutex::new(None)
+		}
+	}
+
+	pub(crate) fn watch(&self) {
+		let dir = match self.dir {
+			Some(dir) => dir as usize,
+			None => return
+		};
+
+		let cache = self.cache.clone();
+
+		let thread = thread::spawn(move || {
+			let mut buf = vec![0u32; NOTIFY_BUF_LEN / 4];
+
+			loop {
+				let mut returned = 0;
+
+				let b = unsafe {
+					ReadDirectoryChangesW(
+						dir as HANDLE,
+						buf.as_mut_ptr() as *mut _,
+						NOTIFY_BUF_LEN as _,
+						TRUE,
+						FILE_NOTIFY_CHANGE_FILE_NAME
+							| FILE_NOTIFY_CHANGE_DIR_NAME
+							| FILE_NOTIFY_CHANGE_LAST_WRITE
+							| FILE_NOTIFY_CHANGE_SIZE,
+						&mut returned,
+						ptr::null_mut(),
+						None
+					)
+				};
+
+				if b == FALSE {
+					break;
+				}
+
+				cache.lock().clear();
+			}
+		});
+
+		*self.thread.lock() = Some(thread);
+	}
+
+	pub(crate) fn close(&self) -> Option<JoinHandle<()>> {
+		let thread = self.thread.lock().take();
+
+		if let Some(thread) = &thread {
+			unsafe {
+				CancelSynchronousIo(thread.as_raw_handle() as _);
+			}
+		}
+
+		if let Some(dir) = self.dir {
+			unsafe {
+				CloseHandle(dir);
+			}
+		}
+
+		thread
+	}
+
+	pub(crate) fn flush(&self) {
+		self.cache.lock().clear();
+	}
+
+	pub(crate) fn get(&self, suffix: &Path) -> Option<PathBuf> {
+		self.dir?;
+
+		let mut cache = self.cache.lock();
+
+		cache
+			.entry(suffix.into())
+			.or_insert_with(|| {
+				let path = self.path.join(suffix);
+
+				if path.is_file() {
+					Some(path)
+				} else {
+					None
+				}
+			})
+			.clone()
+	}
+}
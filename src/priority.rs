@@ -0,0 +1,98 @@
+use crate::config;
+use std::io::Error;
+use winapi::{
+	shared::minwindef::DWORD,
+	um::{
+		processthreadsapi::SetPriorityClass,
+		winbase::{
+			SetProcessAffinityMask, ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS,
+			HIGH_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS
+		},
+		winnt::HANDLE
+	}
+};
+
+pub const PRIORITIES: &[&str] = &["idle", "below_normal", "normal", "above_normal", "high"];
+
+pub struct Priority {
+	class: Option<DWORD>,
+	mask: Option<usize>
+}
+
+impl Priority {
+	// UNDERRAIL_FIX_PRIORITY and UNDERRAIL_FIX_AFFINITY, read before launching so a typo fails
+	// without leaving a suspended game behind
+	pub fn from_env() -> Self {
+		let config = config::get();
+
+		Self {
+			class: config.priority.map(|p| class(&p)),
+			mask: config.affinity.map(|a| mask(&a))
+		}
+	}
+
+	pub unsafe fn apply(&self, process: HANDLE) -> Result<(), Error> {
+		if let Some(class) = self.class {
+			if SetPriorityClass(process, class) == 0 {
+				return Err(Error::last_os_error());
+			}
+
+			tracing::info!("set the game's priority class to {:#x}", class);
+		}
+
+		if let Some(mask) = self.mask {
+			if SetProcessAffinityMask(process, mask) == 0 {
+				return Err(Error::last_os_error());
+			}
+
+			tracing::info!("set the game's affinity to {:#x}", mask);
+		}
+
+		Ok(())
+	}
+}
+
+fn class(priority: &str) -> DWORD {
+	match priority.to_lowercase().as_str() {
+		"idle" => IDLE_PRIORITY_CLASS,
+		"below_normal" => BELOW_NORMAL_PRIORITY_CLASS,
+		"normal" => NORMAL_PRIORITY_CLASS,
+		"above_normal" => ABOVE_NORMAL_PRIORITY_CLASS,
+		"high" => HIGH_PRIORITY_CLASS,
+		_ => {
+			panic!(
+				"unknown priority {}, expected one of {}",
+				priority,
+				PRIORITIES.join(", ")
+			)
+		}
+	}
+}
+
+// either a hex mask like 0xff or a list of cores like 0-7,16
+fn mask(affinity: &str) -> usize {
+	if let Some(hex) = affinity.strip_prefix("0x") {
+		return usize::from_str_radix(hex, 16)
+			.unwrap_or_else(|e| panic!("invalid affinity mask {}: {}", affinity, e));
+	}
+
+	let core = |s: &str| -> u32 {
+		let core = s
+			.trim()
+			.parse()
+			.unwrap_or_else(|e| panic!("invalid core {} in {}: {}", s, affinity, e));
+		assert!(core < usize::BITS, "core {} is out of range", core);
+		core
+	};
+
+	affinity
+		.split(',')
+		.map(|range| {
+			match range.split_once('-') {
+				Some((first, last)) => (core(first), core(last)),
+				None => (core(range), core(range))
+			}
+		})
+		.flat_map(|(first, last)| first..=last)
+		.fold(0, |mask, core| mask | 1 << core)
+}
@@ -0,0 +1,142 @@
+use crate::config;
+use std::{
+	env, fs,
+	path::{Component, Path, PathBuf}
+};
+
+const APP_ID: u32 = 250520;
+const DLL: &str = "underrail_fix.dll";
+
+pub fn launch_options(proxy: &str) -> String {
+	format!("WINEDLLOVERRIDES=\"{}=n,b\" %command%", proxy)
+}
+
+// Wine loads the proxy build from the game directory in place of the system DLL it forwards to
+pub fn setup(path: &Path, proxy: &str) -> Vec<String> {
+	let mut problems = Vec::new();
+	let dll = dll_path();
+	let target = path.join(format!("{}.dll", proxy));
+
+	match fs::read(&dll) {
+		Ok(data) if !forwards(&data, proxy) => {
+			problems.push(format!(
+				"{} is not a proxy build for {}.dll, build it with --features proxy",
+				dll.display(),
+				proxy
+			))
+		}
+		Ok(_) => {
+			fs::copy(&dll, &target).expect(&format!(
+				"failed to copy {} to {}",
+				dll.display(),
+				target.display()
+			));
+
+			tracing::info!("installed {}", target.display());
+		}
+		Err(e) => problems.push(format!("failed to read {}: {}", dll.display(), e))
+	}
+
+	let prefix = path
+		.parent()
+		.and_then(Path::parent)
+		.map(|steamapps| {
+			steamapps
+				.join("compatdata")
+				.join(APP_ID.to_string())
+				.join("pfx")
+		})
+		.filter(|prefix| prefix.is_dir());
+
+	let prefix = match prefix {
+		Some(prefix) => prefix,
+		None => {
+			problems.push(
+				"no Proton prefix found next to the game, launch it once through Proton first"
+					.to_owned()
+			);
+
+			return problems;
+		}
+	};
+
+	match wine_path(&prefix, &target) {
+		Some(wine) => tracing::info!("the game will load {}", wine),
+		None => {
+			problems.push(format!(
+				"{} is not reachable from any drive in {}",
+				target.display(),
+				prefix.display()
+			))
+		}
+	}
+
+	if let Some(dir) = config::get().archive_dir {
+		problems.push(match wine_path(&prefix, &dir) {
+			Some(wine) => {
+				format!(
+				"archives are kept in {}, set archive_dir = '{}' in the game directory's config so \
+				 the DLL finds them",
+				dir.display(),
+				wine
+			)
+			}
+			None => {
+				format!(
+					"archives are kept in {}, which the game can't reach under Proton",
+					dir.display()
+				)
+			}
+		});
+	}
+
+	problems
+}
+
+fn dll_path() -> PathBuf {
+	let mut dll = env::current_exe().expect("failed to get current exe path");
+	dll.set_file_name(DLL);
+	dll
+}
+
+// the forwarders name the system DLL, e.g. C:\Windows\System32\version.GetFileVersionInfoW
+fn forwards(data: &[u8], proxy: &str) -> bool {
+	let forwarder = format!("System32\\{}.", proxy);
+
+	data.windows(forwarder.len())
+		.any(|window| window == forwarder.as_bytes())
+}
+
+// the deepest drive in dosdevices that contains the path, e.g. Z: for /
+fn wine_path(prefix: &Path, path: &Path) -> Option<String> {
+	let path = fs::canonicalize(path).ok()?;
+
+	fs::read_dir(prefix.join("dosdevices"))
+		.ok()?
+		.flatten()
+		.filter_map(|entry| {
+			let drive = entry.file_name().to_string_lossy().to_uppercase();
+			let root = fs::canonicalize(entry.path()).ok()?;
+			let rest = path.strip_prefix(&root).ok()?;
+
+			if drive.len() != 2 || !drive.ends_with(':') {
+				return None;
+			}
+
+			Some((root.components().count(), drive, rest.to_path_buf()))
+		})
+		.max_by_key(|(depth, _, _)| *depth)
+		.map(|(_, drive, rest)| {
+			let rest: Vec<_> = rest
+				.components()
+				.filter_map(|c| {
+					match c {
+						Component::Normal(s) => Some(s.to_string_lossy().into_owned()),
+						_ => None
+					}
+				})
+				.collect();
+
+			format!("{}\\{}", drive, rest.join("\\"))
+		})
+}
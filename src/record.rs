@@ -0,0 +1,20 @@
+use parking_lot::{const_mutex, Mutex};
+use std::{env, fs::File, io::Write, path::Path};
+
+static FILE: Mutex<Option<File>> = const_mutex(None);
+
+// UNDERRAIL_FIX_RECORD names a file that every VFS read is appended to for `trace replay`
+pub(crate) fn init() {
+	if let Some(path) = env::var_os("UNDERRAIL_FIX_RECORD") {
+		match File::create(&path) {
+			Ok(file) => *FILE.lock() = Some(file),
+			Err(e) => tracing::warn!("failed to create {}: {}", Path::new(&path).display(), e)
+		}
+	}
+}
+
+pub(crate) fn read(key: &Path, offset: u64, len: usize) {
+	if let Some(file) = FILE.lock().as_mut() {
+		let _ = writeln!(file, "{}\t{}\t{}", key.display(), offset, len);
+	}
+}
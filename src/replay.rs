@@ -0,0 +1,124 @@
+use crate::vfs::{self, Vfs};
+use anyhow::Context;
+use std::{
+	fmt::{self, Display, Formatter},
+	fs::{self, File},
+	io::{Read, Seek, SeekFrom},
+	path::{Path, PathBuf},
+	time::{Duration, Instant}
+};
+
+pub const RECORD_FILE: &str = "underrail_fix.record.tsv";
+
+pub struct Report {
+	reads: usize,
+	missing: usize,
+	mismatched: usize,
+	vfs: (u64, Duration),
+	loose: (u64, Duration)
+}
+
+impl Display for Report {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		writeln!(f, "reads: {} ({} missing)", self.reads, self.missing)?;
+
+		for (name, (bytes, elapsed)) in [("vfs:  ", self.vfs), ("loose:", self.loose)].iter() {
+			writeln!(
+				f,
+				"{} {} bytes in {:.3}s",
+				name,
+				bytes,
+				elapsed.as_secs_f64()
+			)?;
+		}
+
+		writeln!(f, "mismatched reads: {}", self.mismatched)
+	}
+}
+
+// replays a file written by the DLL with UNDERRAIL_FIX_RECORD against the archives and the loose
+// files, checking that both return the same bytes
+pub fn replay(path: &Path, record: &Path) -> anyhow::Result<Report> {
+	let text = fs::read_to_string(record).expect(&format!("failed to read {}", record.display()));
+
+	let archives = vfs::dirs()
+		.into_iter()
+		.map(|dir| (path.join(vfs::archive_name(&dir)), dir))
+		.filter(|(archive, _)| archive.is_file())
+		.map(|(archive, dir)| {
+			Vfs::open_dir(path.into(), &dir)
+				.with_context(|| format!("failed to open {}", archive.display()))
+		})
+		.collect::<anyhow::Result<Vec<_>>>()?;
+
+	let mut reads = Vec::new();
+	let mut missing = 0;
+
+	for line in text.lines() {
+		let mut fields = line.split('\t');
+
+		let (key, offset, len) = match (fields.next(), fields.next(), fields.next()) {
+			(Some(key), Some(offset), Some(len)) => {
+				(
+					PathBuf::from(key),
+					offset.parse::<u64>().expect("invalid offset"),
+					len.parse::<usize>().expect("invalid length")
+				)
+			}
+			_ => continue
+		};
+
+		match archives
+			.iter()
+			.find_map(|v| v.resolve(&key).map(|p| (v, p)))
+		{
+			Some((vfs, path)) => reads.push((vfs, path, offset, len)),
+			None => missing += 1
+		}
+	}
+
+	let mut vfs_data = Vec::with_capacity(reads.len());
+	let instant = Instant::now();
+
+	for (vfs, path, offset, len) in &reads {
+		let mut reader = vfs.read(path).flatten().unwrap();
+		let mut buf = vec![0; *len];
+
+		reader
+			.seek(SeekFrom::Start(*offset))
+			.expect("failed to seek");
+		reader
+			.read_exact(&mut buf)
+			.expect("failed to read from VFS");
+		vfs_data.push(buf);
+	}
+
+	let vfs_elapsed = instant.elapsed();
+	let mut loose_data = Vec::with_capacity(reads.len());
+	let instant = Instant::now();
+
+	for (_, path, offset, len) in &reads {
+		let mut file = File::open(path).expect(&format!("failed to open {}", path.display()));
+		let mut buf = vec![0; *len];
+
+		file.seek(SeekFrom::Start(*offset)).expect("failed to seek");
+		file.read_exact(&mut buf)
+			.expect("failed to read loose file");
+		loose_data.push(buf);
+	}
+
+	let loose_elapsed = instant.elapsed();
+	let bytes = reads.iter().map(|&(_, _, _, len)| len as u64).sum();
+
+	Ok(Report {
+		reads: reads.len(),
+		missing,
+		mismatched: vfs_data
+			.iter()
+			.zip(&loose_data)
+			.filter(|(a, b)| a != b)
+			.count(),
+		vfs: (bytes, vfs_elapsed),
+		loose: (bytes, loose_elapsed)
+	})
+}
@@ -0,0 +1,89 @@
+use crate::logging::LOG_FILE;
+use std::{fs, path::Path};
+
+// the exit code the watchdog kills a hung game with
+pub const HUNG: u32 = 0xE048_4E47;
+
+const EXCEPTIONS: [(u32, &str); 11] = [
+	(0x8000_0003, "breakpoint"),
+	(0xC000_0005, "access violation"),
+	(0xC000_001D, "illegal instruction"),
+	(0xC000_008E, "floating point division by zero"),
+	(0xC000_0094, "integer division by zero"),
+	(0xC000_00FD, "stack overflow"),
+	(0xC000_0135, "DLL not found"),
+	(0xC000_0142, "DLL initialization failed"),
+	(0xC000_0409, "stack buffer overrun"),
+	(0xE043_4352, "unhandled .NET exception"),
+	(HUNG, "hung while starting")
+];
+
+pub fn crashed(exit_code: u32) -> bool {
+	exit_code & 0xC000_0000 == 0xC000_0000 || EXCEPTIONS.iter().any(|&(c, _)| c == exit_code)
+}
+
+pub fn describe(exit_code: u32) -> &'static str {
+	EXCEPTIONS
+		.iter()
+		.find(|&&(c, _)| c == exit_code)
+		.map_or("unknown exception", |&(_, name)| name)
+}
+
+// copies whatever the DLL left behind into one directory the user can zip up
+pub fn crash(dir: &Path, pid: u32, exit_code: u32) {
+	let bundle = dir.join(format!("underrail_fix.crash.{}", pid));
+
+	let files = [
+		LOG_FILE.to_owned(),
+		"underrail_fix.crash.log".to_owned(),
+		"underrail_fix.trace.log".to_owned(),
+		format!("underrail_fix.{}.dmp", pid)
+	];
+
+	let mut collected = Vec::new();
+
+	for file in &files {
+		let source = dir.join(file);
+
+		if !source.is_file() {
+			continue;
+		}
+
+		if let Err(e) =
+			fs::create_dir_all(&bundle).and_then(|()| fs::copy(&source, bundle.join(file)))
+		{
+			tracing::warn!("failed to copy {}: {}", source.display(), e);
+		} else {
+			collected.push(file);
+		}
+	}
+
+	tracing::error!(
+		"the game crashed: {:#x} ({})",
+		exit_code,
+		describe(exit_code)
+	);
+
+	let message = if collected.is_empty() {
+		format!(
+			"The game crashed ({:#x}, {}). No logs were found in {}.",
+			exit_code,
+			describe(exit_code),
+			dir.display()
+		)
+	} else {
+		format!(
+			"The game crashed ({:#x}, {}). Please attach the contents of\n{}\nwhen reporting the problem.",
+			exit_code,
+			describe(exit_code),
+			bundle.display()
+		)
+	};
+
+	eprintln!("\n{}", message);
+
+	#[cfg(feature = "gui")]
+	if crate::gui::active() {
+		crate::gui::error(&message);
+	}
+}
@@ -0,0 +1,121 @@
+use std::{
+	env,
+	ffi::OsString,
+	io::Error,
+	iter,
+	os::windows::ffi::{OsStrExt, OsStringExt},
+	path::{Path, PathBuf},
+	ptr, slice
+};
+use winapi::{
+	shared::{
+		guiddef::GUID, minwindef::TRUE, winerror::SUCCEEDED, wtypesbase::CLSCTX_INPROC_SERVER
+	},
+	um::{
+		combaseapi::{CoCreateInstance, CoInitializeEx, CoTaskMemFree},
+		knownfolders::{FOLDERID_Desktop, FOLDERID_Programs},
+		objbase::COINIT_APARTMENTTHREADED,
+		objidl::IPersistFile,
+		shlobj::SHGetKnownFolderPath,
+		shobjidl_core::{CLSID_ShellLink, IShellLinkW}
+	},
+	Interface
+};
+
+const NAME: &str = "Underrail (fixed).lnk";
+
+pub fn create(exe: &Path, start_menu: bool) {
+	let mut folders = vec![&FOLDERID_Desktop];
+
+	if start_menu {
+		folders.push(&FOLDERID_Programs);
+	}
+
+	unsafe {
+		CoInitializeEx(ptr::null_mut(), COINIT_APARTMENTTHREADED);
+
+		for folder in folders {
+			let lnk = known_folder(folder).join(NAME);
+			save(&lnk, exe);
+			tracing::info!("created {}", lnk.display());
+		}
+	}
+}
+
+unsafe fn save(lnk: &Path, exe: &Path) {
+	let launcher = env::current_exe().expect("failed to get current exe path");
+	let dir = exe.parent().unwrap();
+
+	let mut link: *mut IShellLinkW = ptr::null_mut();
+
+	check(
+		"CoCreateInstance",
+		CoCreateInstance(
+			&CLSID_ShellLink,
+			ptr::null_mut(),
+			CLSCTX_INPROC_SERVER,
+			&IShellLinkW::uuidof(),
+			&mut link as *mut _ as *mut _
+		)
+	);
+
+	let link = &*link;
+
+	check("SetPath", link.SetPath(wide(&launcher).as_ptr()));
+	check(
+		"SetWorkingDirectory",
+		link.SetWorkingDirectory(wide(dir).as_ptr())
+	);
+	check(
+		"SetIconLocation",
+		link.SetIconLocation(wide(exe).as_ptr(), 0)
+	);
+
+	// the launcher only finds the game on its own when it sits next to it
+	if launcher.parent() != Some(dir) {
+		let arguments: Vec<_> = format!("--exe \"{}\"", exe.display())
+			.encode_utf16()
+			.chain(iter::once(0))
+			.collect();
+
+		check("SetArguments", link.SetArguments(arguments.as_ptr()));
+	}
+
+	let mut file: *mut IPersistFile = ptr::null_mut();
+
+	check(
+		"QueryInterface",
+		link.QueryInterface(&IPersistFile::uuidof(), &mut file as *mut _ as *mut _)
+	);
+
+	check("Save", (*file).Save(wide(lnk).as_ptr(), TRUE));
+
+	(*file).Release();
+	link.Release();
+}
+
+unsafe fn known_folder(id: &GUID) -> PathBuf {
+	let mut path = ptr::null_mut();
+
+	check(
+		"SHGetKnownFolderPath",
+		SHGetKnownFolderPath(id, 0, ptr::null_mut(), &mut path)
+	);
+
+	let len = (0..).take_while(|&i| *path.offset(i) != 0).count();
+	let folder = OsString::from_wide(slice::from_raw_parts(path, len));
+	CoTaskMemFree(path as _);
+
+	folder.into()
+}
+
+fn check(name: &str, hr: i32) {
+	assert!(SUCCEEDED(hr), "{}: {}", name, Error::from_raw_os_error(hr));
+}
+
+fn wide(path: &Path) -> Vec<u16> {
+	path.as_os_str()
+		.encode_wide()
+		.chain(iter::once(0))
+		.collect()
+}
@@ -0,0 +1,90 @@
+use crate::inject;
+use std::{
+	env,
+	fmt::{self, Display, Formatter},
+	io::Error,
+	iter,
+	os::windows::ffi::OsStrExt,
+	path::Path,
+	time::{Duration, Instant}
+};
+use winapi::um::{
+	handleapi::CloseHandle,
+	processthreadsapi::{ResumeThread, TerminateProcess},
+	synchapi::WaitForSingleObject,
+	winbase::INFINITE,
+	winuser::WaitForInputIdle
+};
+
+const IDLE_TIMEOUT_MS: u32 = 5 * 60 * 1000;
+
+pub struct Report {
+	runs: u32,
+	vfs: Duration,
+	loose: Duration
+}
+
+impl Display for Report {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		writeln!(f, "runs: {}", self.runs)?;
+		writeln!(f, "ready vfs:   {:.3}s", self.vfs.as_secs_f64())?;
+		writeln!(f, "ready loose: {:.3}s", self.loose.as_secs_f64())?;
+		writeln!(
+			f,
+			"ratio:       {:.2}x",
+			self.loose.as_secs_f64() / self.vfs.as_secs_f64()
+		)
+	}
+}
+
+// best of `runs` launches each, timed until the game first waits for input
+pub fn bench(exe: &Path, runs: u32) -> Report {
+	assert!(runs > 0, "runs must be at least 1");
+
+	let best = |disable| (0..runs).map(|_| launch(exe, disable)).min().unwrap();
+
+	Report {
+		runs,
+		vfs: best(false),
+		loose: best(true)
+	}
+}
+
+fn launch(exe: &Path, disable: bool) -> Duration {
+	// the DLL still loads without the VFS, so both runs pay the same injection cost
+	env::set_var("UNDERRAIL_FIX_DISABLE", if disable { "1" } else { "0" });
+
+	let mut command_line = crate::command_line(exe, &[]);
+	let dir: Vec<_> = exe
+		.parent()
+		.unwrap()
+		.as_os_str()
+		.encode_wide()
+		.chain(iter::once(0))
+		.collect();
+	let exe: Vec<_> = exe.as_os_str().encode_wide().chain(iter::once(0)).collect();
+
+	unsafe {
+		let pi = inject::create_process(&exe, &mut command_line, &dir)
+			.unwrap_or_else(|e| panic!("failed to start the game: {}", e));
+		let instant = Instant::now();
+
+		assert_ne!(
+			ResumeThread(pi.hThread),
+			!0,
+			"ResumeThread: {}",
+			Error::last_os_error()
+		);
+
+		let wait = WaitForInputIdle(pi.hProcess, IDLE_TIMEOUT_MS);
+		let elapsed = instant.elapsed();
+
+		TerminateProcess(pi.hProcess, 0);
+		WaitForSingleObject(pi.hProcess, INFINITE);
+		CloseHandle(pi.hThread);
+		CloseHandle(pi.hProcess);
+
+		assert_eq!(wait, 0, "WaitForInputIdle: {}", Error::last_os_error());
+		elapsed
+	}
+}
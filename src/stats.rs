@@ -0,0 +1,133 @@
+#![allow(dead_code)]
+
+use std::{
+	ffi::OsStr,
+	iter, mem,
+	os::windows::ffi::OsStrExt,
+	ptr,
+	sync::atomic::{AtomicU64, Ordering}
+};
+use winapi::um::{
+	handleapi::INVALID_HANDLE_VALUE,
+	memoryapi::{
+		CreateFileMappingW, MapViewOfFile, OpenFileMappingW, FILE_MAP_ALL_ACCESS, FILE_MAP_READ
+	},
+	processthreadsapi::GetCurrentProcessId,
+	winnt::PAGE_READWRITE
+};
+
+pub const STATS_VERSION: u64 = 1;
+
+// rough cost of opening a loose file in the game's data directories, for the summary only
+const LOOSE_OPEN_MICROS: u64 = 200;
+
+#[derive(Default)]
+#[repr(C)]
+pub struct Stats {
+	pub version: AtomicU64,
+	pub opens: AtomicU64,
+	pub misses: AtomicU64,
+	pub overlay: AtomicU64,
+	pub finds: AtomicU64,
+	pub bytes_read: AtomicU64,
+	pub handles: AtomicU64,
+	pub find_handles: AtomicU64
+}
+
+impl Stats {
+	pub fn shared() -> &'static Self {
+		let name: Vec<_> = OsStr::new(&shared_name(unsafe { GetCurrentProcessId() }))
+			.encode_wide()
+			.chain(iter::once(0))
+			.collect();
+
+		let stats = unsafe {
+			let mapping = CreateFileMappingW(
+				INVALID_HANDLE_VALUE,
+				ptr::null_mut(),
+				PAGE_READWRITE,
+				0,
+				mem::size_of::<Self>() as _,
+				name.as_ptr()
+			);
+
+			if mapping.is_null() {
+				ptr::null()
+			} else {
+				MapViewOfFile(mapping, FILE_MAP_ALL_ACCESS, 0, 0, mem::size_of::<Self>())
+					as *const Self
+			}
+		};
+
+		let stats = if stats.is_null() {
+			tracing::warn!("failed to map shared stats, falling back to private stats");
+			Box::leak(Box::new(Self::default()))
+		} else {
+			unsafe { &*stats }
+		};
+
+		stats.version.store(STATS_VERSION, Ordering::Relaxed);
+		stats
+	}
+
+	// the mapping goes away with the game, so this has to be opened while it is still running
+	pub fn open(pid: u32) -> Option<&'static Self> {
+		let name: Vec<_> = OsStr::new(&shared_name(pid))
+			.encode_wide()
+			.chain(iter::once(0))
+			.collect();
+
+		unsafe {
+			let mapping = OpenFileMappingW(FILE_MAP_READ, 0, name.as_ptr());
+
+			if mapping.is_null() {
+				return None;
+			}
+
+			let stats =
+				MapViewOfFile(mapping, FILE_MAP_READ, 0, 0, mem::size_of::<Self>()) as *const Self;
+
+			if stats.is_null() || (*stats).version.load(Ordering::Relaxed) != STATS_VERSION {
+				None
+			} else {
+				Some(&*stats)
+			}
+		}
+	}
+
+	pub fn summary(&self) -> String {
+		let opens = Self::get(&self.opens);
+		let misses = Self::get(&self.misses);
+		let hit_rate = if opens + misses == 0 {
+			0.0
+		} else {
+			opens as f64 * 100.0 / (opens + misses) as f64
+		};
+
+		format!(
+			"served {:.1} MiB from the VFS in {} opens ({:.1}% hit rate, {} from the overlay), \
+			 saving about {:.1}s of loose file opens",
+			Self::get(&self.bytes_read) as f64 / (1 << 20) as f64,
+			opens,
+			hit_rate,
+			Self::get(&self.overlay),
+			(opens * LOOSE_OPEN_MICROS) as f64 / 1e6
+		)
+	}
+
+	pub fn add(counter: &AtomicU64, n: u64) {
+		counter.fetch_add(n, Ordering::Relaxed);
+	}
+
+	pub fn sub(counter: &AtomicU64, n: u64) {
+		counter.fetch_sub(n, Ordering::Relaxed);
+	}
+
+	pub fn get(counter: &AtomicU64) -> u64 {
+		counter.load(Ordering::Relaxed)
+	}
+}
+
+pub fn shared_name(pid: u32) -> String {
+	format!(r"Local\underrail-fix.stats.{}", pid)
+}
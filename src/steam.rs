@@ -0,0 +1,85 @@
+use crate::{game, inject, install};
+use std::{env, io::Error, path::Path, ptr};
+use winapi::um::{
+	winbase::{GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GMEM_MOVEABLE},
+	winuser::{CloseClipboard, EmptyClipboard, OpenClipboard, SetClipboardData, CF_UNICODETEXT}
+};
+
+pub fn launch_options() -> String {
+	let launcher = env::current_exe().expect("failed to get current exe path");
+	format!("\"{}\" %command%", launcher.display())
+}
+
+pub fn problems(exe: &Path) -> Vec<String> {
+	let mut problems = Vec::new();
+	let launcher = env::current_exe().expect("failed to get current exe path");
+
+	if !exe.is_file() {
+		problems.push(format!("{} not found", exe.display()));
+	}
+
+	if !inject::dll_path().is_file() {
+		problems.push(format!(
+			"{} not found next to the launcher",
+			inject::dll_path().display()
+		));
+	}
+
+	let name = launcher
+		.file_name()
+		.unwrap()
+		.to_string_lossy()
+		.to_lowercase();
+
+	// Steam would then run the launcher with itself as %command%
+	if name == game::exe_name() {
+		problems.push(format!(
+			"the launcher is installed as {}, run uninstall first or skip the launch options",
+			name
+		));
+	}
+
+	match install::steam() {
+		Some(dir) if exe.parent() != Some(dir.as_path()) => {
+			problems.push(format!(
+				"the Steam copy of the game is in {}, not {}",
+				dir.display(),
+				exe.parent().unwrap().display()
+			))
+		}
+		None => problems.push("no Steam install of the game found".to_owned()),
+		_ => {}
+	}
+
+	problems
+}
+
+pub fn copy_to_clipboard(text: &str) {
+	let wide: Vec<u16> = text.encode_utf16().chain(Some(0)).collect();
+
+	unsafe {
+		assert_ne!(
+			OpenClipboard(ptr::null_mut()),
+			0,
+			"OpenClipboard: {}",
+			Error::last_os_error()
+		);
+
+		EmptyClipboard();
+
+		let memory = GlobalAlloc(GMEM_MOVEABLE, wide.len() * 2);
+		assert!(!memory.is_null(), "GlobalAlloc: {}", Error::last_os_error());
+
+		ptr::copy_nonoverlapping(wide.as_ptr(), GlobalLock(memory) as *mut u16, wide.len());
+		GlobalUnlock(memory);
+
+		// the clipboard owns the memory once this succeeds
+		if SetClipboardData(CF_UNICODETEXT, memory).is_null() {
+			GlobalFree(memory);
+			CloseClipboard();
+			panic!("SetClipboardData: {}", Error::last_os_error());
+		}
+
+		CloseClipboard();
+	}
+}
@@ -0,0 +1,310 @@
+use crate::{
+	fixer::Fixer, path_to_wide, vfs::Vfs, CloseHandleArgs, CreateFileWArgs, FindCloseArgs,
+	FindFirstFileWArgs, FindNextFileWArgs, GetFileSizeArgs, ReadFileArgs, SetFilePointerArgs
+};
+use parking_lot::RwLock;
+use std::{
+	env, fs, mem,
+	path::{Path, PathBuf},
+	process, ptr,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc, Barrier
+	},
+	thread
+};
+use winapi::{
+	shared::minwindef::{FALSE, TRUE},
+	um::{
+		fileapi::{
+			CreateFileW, FindClose, FindFirstFileW, FindNextFileW, GetFileSize, ReadFile,
+			SetFilePointer, INVALID_SET_FILE_POINTER, OPEN_EXISTING
+		},
+		handleapi::{CloseHandle, INVALID_HANDLE_VALUE},
+		minwinbase::WIN32_FIND_DATAW,
+		winbase::FILE_BEGIN,
+		winnt::{FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, GENERIC_READ, HANDLE}
+	}
+};
+
+const THREADS: usize = 8;
+const ITERATIONS: usize = 200;
+const DIRS: usize = 4;
+const FILES: usize = 64;
+
+struct Rng(u32);
+
+impl Rng {
+	fn next(&mut self, n: usize) -> usize {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 17;
+		self.0 ^= self.0 << 5;
+		self.0 as usize % n
+	}
+}
+
+fn content(i: usize) -> Vec<u8> {
+	(0..(i * 997) % 65536 + 1).map(|j| (i + j) as u8).collect()
+}
+
+fn root() -> PathBuf {
+	env::temp_dir().join(format!("underrail_fix_stress_{}", process::id()))
+}
+
+fn file_path(i: usize) -> PathBuf {
+	root()
+		.join("Data")
+		.join(format!("dir{}", i % DIRS))
+		.join(format!("file{}.bin", i))
+}
+
+fn setup() -> (PathBuf, Arc<Fixer>) {
+	let root = root();
+	let _ = fs::remove_dir_all(&root);
+
+	for i in 0..FILES {
+		let path = file_path(i);
+		fs::create_dir_all(path.parent().unwrap()).unwrap();
+		fs::write(path, content(i)).unwrap();
+	}
+
+	Vfs::create(root.clone()).unwrap();
+	let fixer = Arc::new(Fixer::new(root.clone()).unwrap());
+	(root, fixer)
+}
+
+fn open(fixer: &Fixer, path: &Path) -> HANDLE {
+	let path = path_to_wide(path);
+
+	fixer.create_file_w(
+		CreateFileWArgs {
+			lp_file_name: path.as_ptr(),
+			dw_desired_access: GENERIC_READ,
+			dw_share_mode: FILE_SHARE_READ,
+			lp_security_attributes: ptr::null_mut(),
+			dw_creation_disposition: OPEN_EXISTING,
+			dw_flags_and_attributes: FILE_ATTRIBUTE_NORMAL,
+			h_template_file: ptr::null_mut()
+		},
+		|a| unsafe {
+			CreateFileW(
+				a.lp_file_name,
+				a.dw_desired_access,
+				a.dw_share_mode,
+				a.lp_security_attributes,
+				a.dw_creation_disposition,
+				a.dw_flags_and_attributes,
+				a.h_template_file
+			)
+		}
+	)
+}
+
+fn read(fixer: &Fixer, h_file: HANDLE, buf: &mut [u8]) -> Option<usize> {
+	let mut read = 0;
+
+	let b = fixer.read_file(
+		ReadFileArgs {
+			h_file,
+			lp_buffer: buf.as_mut_ptr() as *mut _,
+			n_number_of_bytes_to_read: buf.len() as _,
+			lp_number_of_bytes_read: &mut read,
+			lp_overlapped: ptr::null_mut()
+		},
+		|a| unsafe {
+			ReadFile(
+				a.h_file,
+				a.lp_buffer,
+				a.n_number_of_bytes_to_read,
+				a.lp_number_of_bytes_read,
+				a.lp_overlapped
+			)
+		}
+	);
+
+	if b == TRUE {
+		Some(read as usize)
+	} else {
+		None
+	}
+}
+
+fn close(fixer: &Fixer, h_object: HANDLE) -> bool {
+	fixer.close_handle(CloseHandleArgs { h_object }, |a| unsafe {
+		CloseHandle(a.h_object)
+	}) == TRUE
+}
+
+fn read_all(fixer: &Fixer, i: usize, rng: &mut Rng) {
+	let expected = content(i);
+	let h = open(fixer, &file_path(i));
+	assert_ne!(h, INVALID_HANDLE_VALUE);
+
+	let size = fixer.get_file_size(
+		GetFileSizeArgs {
+			h_file: h,
+			lp_file_size_high: ptr::null_mut()
+		},
+		|a| unsafe { GetFileSize(a.h_file, a.lp_file_size_high) }
+	);
+
+	assert_eq!(size as usize, expected.len());
+
+	let mut actual = Vec::new();
+	let mut buf = vec![0; rng.next(4096) + 1];
+
+	while let Some(read) = read(fixer, h, &mut buf) {
+		if read == 0 {
+			break;
+		}
+
+		actual.extend_from_slice(&buf[..read]);
+	}
+
+	assert_eq!(actual, expected);
+
+	let offset = rng.next(expected.len());
+
+	let pos = fixer.set_file_pointer(
+		SetFilePointerArgs {
+			h_file: h,
+			l_distance_to_move: offset as _,
+			lp_distance_to_move_high: ptr::null_mut(),
+			dw_move_method: FILE_BEGIN
+		},
+		|a| unsafe {
+			SetFilePointer(
+				a.h_file,
+				a.l_distance_to_move,
+				a.lp_distance_to_move_high,
+				a.dw_move_method
+			)
+		}
+	);
+
+	assert_ne!(pos, INVALID_SET_FILE_POINTER);
+	assert_eq!(pos as usize, offset);
+
+	let mut buf = vec![0; expected.len() - offset];
+	assert_eq!(read(fixer, h, &mut buf), Some(buf.len()));
+	assert_eq!(buf, expected[offset..]);
+
+	assert!(close(fixer, h));
+}
+
+fn find_all(fixer: &Fixer, dir: usize) {
+	let pattern = path_to_wide(
+		&root()
+			.join("Data")
+			.join(format!("dir{}", dir))
+			.join("*.bin")
+	);
+	let mut data: WIN32_FIND_DATAW = unsafe { mem::zeroed() };
+
+	let h = fixer.find_first_file_w(
+		FindFirstFileWArgs {
+			lp_file_name: pattern.as_ptr(),
+			lp_find_file_data: &mut data
+		},
+		|a| unsafe { FindFirstFileW(a.lp_file_name, a.lp_find_file_data) }
+	);
+
+	assert_ne!(h, INVALID_HANDLE_VALUE);
+	let mut found = 1;
+
+	while fixer.find_next_file_w(
+		FindNextFileWArgs {
+			h_find_file: h,
+			lp_find_file_data: &mut data
+		},
+		|a| unsafe { FindNextFileW(a.h_find_file, a.lp_find_file_data) }
+	) != FALSE
+	{
+		found += 1;
+	}
+
+	assert_eq!(found, FILES / DIRS);
+
+	assert_eq!(
+		fixer.find_close(FindCloseArgs { h_find_file: h }, |a| unsafe {
+			FindClose(a.h_find_file)
+		}),
+		TRUE
+	);
+}
+
+// once closed the handle value can be handed out again by another thread's open, so the reader
+// must never use it after the close
+fn close_while_reading(fixer: &Arc<Fixer>, i: usize) {
+	let h = open(fixer, &file_path(i)) as usize;
+	let open = Arc::new(RwLock::new(true));
+	let reads = Arc::new(AtomicUsize::new(0));
+
+	let reader = {
+		let fixer = fixer.clone();
+		let open = open.clone();
+		let reads = reads.clone();
+
+		thread::spawn(move || {
+			let mut buf = [0; 64];
+
+			loop {
+				let open = open.read();
+
+				if !*open {
+					break;
+				}
+
+				assert!(read(&fixer, h as HANDLE, &mut buf).is_some());
+				reads.fetch_add(1, Ordering::Relaxed);
+			}
+		})
+	};
+
+	while reads.load(Ordering::Relaxed) == 0 {
+		thread::yield_now();
+	}
+
+	let mut open = open.write();
+	assert!(close(fixer, h as HANDLE));
+	*open = false;
+	drop(open);
+
+	reader.join().unwrap();
+}
+
+pub fn run() {
+	let (root, fixer) = setup();
+	let barrier = Arc::new(Barrier::new(THREADS));
+
+	let threads: Vec<_> = (0..THREADS)
+		.map(|t| {
+			let fixer = fixer.clone();
+			let barrier = barrier.clone();
+
+			thread::spawn(move || {
+				let mut rng = Rng(0x9e37_79b9 ^ (t as u32 + 1));
+				barrier.wait();
+
+				for _ in 0..ITERATIONS {
+					match rng.next(8) {
+						0..=4 => read_all(&fixer, rng.next(FILES), &mut rng),
+						5 | 6 => find_all(&fixer, rng.next(DIRS)),
+						_ => close_while_reading(&fixer, rng.next(FILES))
+					}
+				}
+			})
+		})
+		.collect();
+
+	for thread in threads {
+		thread.join().unwrap();
+	}
+
+	for thread in fixer.close() {
+		thread.join().unwrap();
+	}
+
+	let _ = fs::remove_dir_all(root);
+	println!("ok");
+}
@@ -0,0 +1,116 @@
+use crate::{
+	config,
+	logging::{self, LogLayer}
+};
+use parking_lot::{const_mutex, Mutex};
+use std::path::Path;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{
+	filter::{EnvFilter, LevelFilter},
+	fmt::{self, format::FmtSpan},
+	layer::SubscriberExt,
+	reload::{self, Handle},
+	util::SubscriberInitExt,
+	Registry
+};
+
+static GUARD: Mutex<Option<WorkerGuard>> = const_mutex(None);
+static FILTER: Mutex<Option<Handle<EnvFilter, Registry>>> = const_mutex(None);
+static DIRECTIVES: Mutex<String> = const_mutex(String::new());
+
+pub(crate) fn init(path: &Path) {
+	let log = LogLayer::new(path, false, "dll", !cfg!(feature = "no-console"));
+
+	let mut directives = config::get().trace.unwrap_or_default();
+	let invalid = filter(&directives).err();
+
+	// tracing is off unless asked for, a typo shouldn't flood the disk
+	if invalid.is_some() {
+		directives.clear();
+	}
+
+	let trace = filter(&directives).unwrap();
+
+	let (writer, guard) = if directives.is_empty() {
+		(None, None)
+	} else {
+		let (writer, guard) = tracing_appender::non_blocking(tracing_appender::rolling::never(
+			path,
+			"underrail_fix.trace.log"
+		));
+
+		let layer = fmt::layer()
+			.with_writer(writer)
+			.with_span_events(FmtSpan::CLOSE)
+			.with_ansi(false);
+
+		(Some(layer), Some(guard))
+	};
+
+	*DIRECTIVES.lock() = directives;
+	let (filter, handle) = reload::Layer::new(trace);
+
+	tracing_subscriber::registry()
+		.with(filter)
+		.with(log)
+		.with(writer)
+		.init();
+
+	*GUARD.lock() = guard;
+	*FILTER.lock() = Some(handle);
+
+	if let Some(e) = invalid {
+		tracing::warn!("ignoring UNDERRAIL_FIX_TRACE, tracing is off: {}", e);
+	}
+}
+
+pub(crate) fn shutdown() {
+	GUARD.lock().take();
+}
+
+pub(crate) fn set_level(directives: &str) -> Result<(), String> {
+	let filter = filter(directives)?;
+	*DIRECTIVES.lock() = directives.into();
+	reload(filter)
+}
+
+pub(crate) fn set_log_level(level: &str) -> Result<(), String> {
+	let level =
+		logging::parse_level(level).ok_or_else(|| format!("invalid log level: {}", level))?;
+	logging::set_level(level);
+	reload(filter(&DIRECTIVES.lock())?)
+}
+
+fn reload(filter: EnvFilter) -> Result<(), String> {
+	FILTER
+		.lock()
+		.as_ref()
+		.ok_or_else(|| "tracing not initialized".to_owned())?
+		.reload(filter)
+		.map_err(|e| e.to_string())
+}
+
+fn filter(directives: &str) -> Result<EnvFilter, String> {
+	let mut level = logging::current_level();
+	let mut rest = Vec::new();
+
+	for directive in directives
+		.split(',')
+		.map(str::trim)
+		.filter(|s| !s.is_empty())
+	{
+		match logging::parse_level(directive) {
+			Some(l) => level = level.max(l),
+			None => rest.push(directive)
+		}
+	}
+
+	let mut filter = level.to_string();
+
+	for directive in rest {
+		filter.push(',');
+		filter.push_str(directive);
+	}
+
+	EnvFilter::try_new(filter).map_err(|e| format!("invalid filter: {}", e))
+}
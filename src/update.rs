@@ -0,0 +1,40 @@
+use serde::Deserialize;
+
+const LATEST_RELEASE: &str = "https://api.github.com/repos/styg2/underrail-fix/releases/latest";
+
+#[derive(Deserialize)]
+struct Release {
+	tag_name: String,
+	html_url: String
+}
+
+pub fn check() {
+	let release: Release = ureq::get(LATEST_RELEASE)
+		.set(
+			"User-Agent",
+			concat!("underrail-fix/", env!("CARGO_PKG_VERSION"))
+		)
+		.call()
+		.unwrap_or_else(|e| panic!("failed to query {}: {}", LATEST_RELEASE, e))
+		.into_json()
+		.expect("failed to parse the latest release");
+
+	let current = env!("CARGO_PKG_VERSION");
+
+	if parse(&release.tag_name) > parse(current) {
+		println!(
+			"{} is available (running {}): {}",
+			release.tag_name, current, release.html_url
+		);
+	} else {
+		println!("{} is the latest release", current);
+	}
+}
+
+fn parse(version: &str) -> Vec<u32> {
+	version
+		.trim_start_matches('v')
+		.split(|c: char| !c.is_ascii_digit())
+		.map_while(|part| part.parse().ok())
+		.collect()
+}
@@ -0,0 +1,119 @@
+use crate::config;
+use std::{ffi::c_void, fmt, iter, os::windows::ffi::OsStrExt, path::Path, ptr, slice};
+use winapi::um::{
+	verrsrc::VS_FIXEDFILEINFO,
+	winver::{GetFileVersionInfoSizeW, GetFileVersionInfoW, VerQueryValueW}
+};
+
+const TESTED: &[Version] = &[Version([1, 1, 4, 6]), Version([1, 1, 5, 2])];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Version(pub [u16; 4]);
+
+impl fmt::Display for Version {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let [a, b, c, d] = self.0;
+		write!(f, "{}.{}.{}.{}", a, b, c, d)
+	}
+}
+
+unsafe fn info(exe: &Path) -> Option<Vec<u8>> {
+	let mut exe: Vec<_> = exe.as_os_str().encode_wide().collect();
+	exe.push(0);
+
+	let len = GetFileVersionInfoSizeW(exe.as_ptr(), ptr::null_mut());
+
+	if len == 0 {
+		return None;
+	}
+
+	let mut data = vec![0u8; len as usize];
+
+	if GetFileVersionInfoW(exe.as_ptr(), 0, len, data.as_mut_ptr() as *mut _) == 0 {
+		return None;
+	}
+
+	Some(data)
+}
+
+unsafe fn query(data: &[u8], name: &str) -> Option<&[u8]> {
+	let name: Vec<_> = name.encode_utf16().chain(iter::once(0)).collect();
+	let mut value: *mut c_void = ptr::null_mut();
+	let mut len = 0;
+
+	if VerQueryValueW(
+		data.as_ptr() as *const _,
+		name.as_ptr(),
+		&mut value,
+		&mut len
+	) == 0 || value.is_null()
+	{
+		return None;
+	}
+
+	Some(slice::from_raw_parts(value as *const u8, len as usize))
+}
+
+pub fn read(exe: &Path) -> Option<Version> {
+	unsafe {
+		let data = info(exe)?;
+		let info = &*(query(&data, "\\")?.as_ptr() as *const VS_FIXEDFILEINFO);
+
+		Some(Version([
+			(info.dwFileVersionMS >> 16) as u16,
+			info.dwFileVersionMS as u16,
+			(info.dwFileVersionLS >> 16) as u16,
+			info.dwFileVersionLS as u16
+		]))
+	}
+}
+
+// the name the exe was built with, which survives renaming it
+pub fn original_filename(exe: &Path) -> Option<String> {
+	unsafe {
+		let data = info(exe)?;
+		let translation = query(&data, "\\VarFileInfo\\Translation")?;
+		let lang = u16::from_le_bytes([*translation.get(0)?, *translation.get(1)?]);
+		let code_page = u16::from_le_bytes([*translation.get(2)?, *translation.get(3)?]);
+
+		let value = query(
+			&data,
+			&format!(
+				"\\StringFileInfo\\{:04x}{:04x}\\OriginalFilename",
+				lang, code_page
+			)
+		)?;
+
+		// the length is in characters, including the terminator
+		let value = slice::from_raw_parts(value.as_ptr() as *const u16, value.len());
+		let value: Vec<_> = value.iter().copied().take_while(|&c| c != 0).collect();
+		Some(String::from_utf16_lossy(&value))
+	}
+}
+
+// warns about untested game builds, returning false for them only with
+// UNDERRAIL_FIX_REQUIRE_TESTED=1
+pub fn check(exe: &Path) -> bool {
+	let version = read(exe);
+
+	if version.map_or(false, |v| TESTED.contains(&v)) {
+		tracing::debug!("{}: tested version {}", exe.display(), version.unwrap());
+		return true;
+	}
+
+	let version = version.map_or_else(|| "unknown".to_owned(), |v| v.to_string());
+	let require = config::get().require_tested == Some(true);
+
+	tracing::warn!(
+		"{}: untested game version {}{}",
+		exe.display(),
+		version,
+		if require {
+			", refusing to run (unset UNDERRAIL_FIX_REQUIRE_TESTED to run anyway)"
+		} else {
+			""
+		}
+	);
+
+	!require
+}
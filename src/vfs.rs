@@ -1,153 +1,537 @@
 #![allow(dead_code)]
 
+use crate::config;
+use indicatif::{ProgressBar, ProgressStyle};
 use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
 	collections::BTreeMap,
-	convert::TryInto,
-	fs::File,
+	env, fmt,
+	fs::{self, File, OpenOptions},
 	io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
-	os::windows::fs::FileExt,
-	path::{Component, Path, PathBuf},
-	time::{Duration, Instant}
+	path::{is_separator, Component, Path, PathBuf, MAIN_SEPARATOR},
+	sync::{
+		atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+		mpsc, Arc
+	},
+	thread,
+	time::UNIX_EPOCH
 };
 
 const BUF_LEN: usize = 1 << 20;
+const READ_ALIGN: u64 = 1 << 16;
+const PREFETCH_BUF_LEN: usize = 8 << 20;
+// reads in a row without a seek before the reader starts reading ahead
+const READAHEAD_AFTER: u32 = 2;
+const DATA_DIR: &str = "Data";
+const OVERLAY_DIR: &str = "Overlay";
+const PACKS_DIR: &str = "Localization";
+// these follow the map, where readers that predate them never look
+const GAME_VERSION_TAG: &[u8; 4] = b"UFGV";
+const MTIMES_TAG: &[u8; 4] = b"UFMT";
+const WASTE_TAG: &[u8; 4] = b"UFWS";
+// update_dir repacks once more than one in this many bytes is data no map points at anymore
+const REPACK_WASTE: u64 = 4;
+
+static PACKING: AtomicBool = AtomicBool::new(false);
+static ABORT: AtomicBool = AtomicBool::new(false);
+static GAME_VERSION: AtomicU64 = AtomicU64::new(0);
 
 pub struct Vfs {
 	path: PathBuf,
+	// where the loose files live, which differs from path when UNDERRAIL_DATA is set
+	source: PathBuf,
+	prefix: PathBuf,
 	map: BTreeMap<PathBuf, Entry>,
-	file: File
+	file: Arc<File>
 }
 
 struct Walker {
 	path: PathBuf,
 	map: BTreeMap<PathBuf, Entry>,
+	// keys are lowercased, which only finds the files again on a case-insensitive filesystem
+	paths: BTreeMap<PathBuf, PathBuf>,
 	size: u64
 }
 
-pub struct Reader<'a> {
-	file: &'a File,
+pub struct Reader {
+	file: Arc<File>,
 	offset: u64,
-	len: usize,
-	index: usize
+	len: u64,
+	index: u64,
+	streak: u32,
+	buf: Vec<u8>,
+	buf_len: usize,
+	buf_index: u64
+}
+
+// what follows the map, each part missing from archives packed before it existed
+struct Trailer {
+	version: Option<u64>,
+	mtimes: Option<BTreeMap<PathBuf, u64>>,
+	waste: Option<u64>
+}
+
+// packing was stopped by abort, with the partial archive already removed; it comes wrapped in an
+// Interrupted io::Error, see aborted
+#[derive(Debug)]
+pub struct Aborted;
+
+// io errors don't say which archive or file they are about
+trait PathContext<T> {
+	fn with_path(self, what: &str, path: &Path) -> io::Result<T>;
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum Entry {
 	Directory,
-	File { offset: u64, len: usize }
+	File { offset: u64, len: u64 }
 }
 
 impl Vfs {
-	pub fn open(mut path: PathBuf) -> Self {
-		let vfs_file = path.join("data.vfs");
-		path.push("Data");
+	pub fn open(path: PathBuf) -> io::Result<Self> {
+		Self::open_dir(path, &data_dir())
+	}
+
+	pub fn open_dir(mut path: PathBuf, dir: &Path) -> io::Result<Self> {
+		let vfs_file = path.join(archive_name(dir));
+		let source = source_dir(&path, dir);
+		path.push(dir);
+
+		let prefix = if dir == data_dir() {
+			PathBuf::new()
+		} else {
+			suffix(Path::new(""), dir).ok_or_else(|| {
+				io::Error::new(
+					ErrorKind::InvalidInput,
+					format!("{} is not a relative path", dir.display())
+				)
+			})?
+		};
+
+		Self::open_file(&vfs_file, path, source, prefix)
+	}
 
-		let mut file = File::open(&vfs_file).expect("failed to open VFS");
+	// a pack archive overlays the data directory of the game in `path`
+	pub fn open_pack(mut path: PathBuf, archive: &Path) -> io::Result<Self> {
+		let source = source_dir(&path, &data_dir());
+		path.push(data_dir());
+		Self::open_file(archive, path, source, PathBuf::new())
+	}
+
+	fn open_file(
+		vfs_file: &Path,
+		path: PathBuf,
+		source: PathBuf,
+		prefix: PathBuf
+	) -> io::Result<Self> {
+		let mut file = open_archive(vfs_file, OpenOptions::new().read(true), false)?;
 		let mut map_offset = [0; 8];
 
 		file.read_exact(&mut map_offset)
-			.expect("failed to read map offset");
+			.with_path("failed to read the map offset of", vfs_file)?;
 
 		file.seek(SeekFrom::Start(u64::from_le_bytes(map_offset)))
-			.expect("failed to seek to map offset");
+			.with_path("failed to seek to the map of", vfs_file)?;
 
-		let map = bincode::deserialize_from(BufReader::with_capacity(BUF_LEN, &file))
-			.expect("failed to deserialize VFS");
+		let map = deserialize_map(BufReader::with_capacity(BUF_LEN, &file))
+			.with_path("failed to read the map of", vfs_file)?;
 
-		Self { path, map, file }
+		Ok(Self {
+			path,
+			source,
+			prefix,
+			map,
+			file: Arc::new(file)
+		})
 	}
 
-	pub fn create(mut path: PathBuf) {
-		let vfs_file = path.join("data.vfs");
-		path.push("Data");
+	pub fn create(path: PathBuf) -> io::Result<()> {
+		Self::create_dir(path, &data_dir())
+	}
 
-		let path_m = path
-			.metadata()
-			.expect("failed to get metadata for data path");
+	pub fn create_dir(path: PathBuf, dir: &Path) -> io::Result<()> {
+		let vfs_file = path.join(archive_name(dir));
+		let path = source_dir(&path, dir);
+		let path_m = path.metadata().with_path("failed to read", &path)?;
 
-		assert!(path_m.is_dir(), "data path not a dir: {}", path.display());
+		if !path_m.is_dir() {
+			return Err(not_dir(&path));
+		}
 
 		let vfs_m = match vfs_file.metadata() {
 			Ok(m) => Some(m),
 			Err(e) if e.kind() == ErrorKind::NotFound => None,
-			Err(e) => return Err(e).expect("failed to get metadata for VFS file")
+			Err(e) => return Err(e).with_path("failed to read", &vfs_file)
+		};
+
+		let outdated = match vfs_m {
+			Some(vfs_m) => path_m.modified()? > vfs_m.modified()?,
+			None => true
+		};
+
+		if outdated {
+			Self::write(&path, &vfs_file)?;
+		}
+
+		Ok(())
+	}
+
+	// packs an arbitrary directory, e.g. a translation pack laid out like Data
+	pub fn create_pack(source: &Path, archive: &Path) -> io::Result<()> {
+		if !source.is_dir() {
+			return Err(not_dir(source));
+		}
+
+		Self::write(source, archive)
+	}
+
+	fn write(path: &Path, vfs_file: &Path) -> io::Result<()> {
+		tracing::info!("creating VFS...");
+
+		let mut walker = Walker {
+			path: path.to_path_buf(),
+			map: BTreeMap::new(),
+			paths: BTreeMap::new(),
+			size: 0
+		};
+
+		walker.walk(path)?;
+
+		let files: Vec<_> = walker
+			.map
+			.iter()
+			.filter(|(_, entry)| matches!(entry, Entry::File { .. }))
+			.map(|(p, _)| (p.clone(), walker.paths[p].clone()))
+			.collect();
+
+		let mtimes = files
+			.iter()
+			.filter_map(|(p, path)| Some((p.clone(), mtime(path)?)))
+			.collect();
+
+		// truncated only once it's ours, a reader may still hold the old archive
+		let file = open_archive(vfs_file, OpenOptions::new().write(true).create(true), true)?;
+		file.set_len(0)?;
+
+		ABORT.store(false, Ordering::SeqCst);
+		PACKING.store(true, Ordering::SeqCst);
+		let result = write_archive(file, &mut walker.map, &files, &mtimes, walker.size);
+		PACKING.store(false, Ordering::SeqCst);
+
+		if let Err(e) = result {
+			fs::remove_file(vfs_file).ok();
+
+			if aborted(&e) {
+				tracing::warn!("packing aborted, removed {}", vfs_file.display());
+			}
+
+			return Err(e).with_path("failed to write", vfs_file);
+		}
+
+		tracing::info!("finished creating VFS");
+		Ok(())
+	}
+
+	// appends new and modified files and a new map after everything else, then points the header
+	// at it, so a crash leaves the old map intact and readers holding it stay consistent
+	pub fn update_dir(path: PathBuf, dir: &Path) -> io::Result<usize> {
+		let vfs_file = path.join(archive_name(dir));
+
+		let since = match vfs_file.metadata() {
+			Ok(m) => m.modified()?,
+			Err(e) if e.kind() == ErrorKind::NotFound => {
+				Self::create_dir(path, dir)?;
+				return Ok(1);
+			}
+			Err(e) => return Err(e).with_path("failed to read", &vfs_file)
 		};
 
-		if vfs_m.map_or(true, |vfs_m| {
-			path_m.modified().unwrap() > vfs_m.modified().unwrap()
-		}) {
-			println!("creating VFS...");
+		let path = source_dir(&path, dir);
+
+		let mut walker = Walker {
+			path: path.clone(),
+			map: BTreeMap::new(),
+			paths: BTreeMap::new(),
+			size: 0
+		};
+
+		walker.walk(&path)?;
+
+		let mut file = open_archive(&vfs_file, OpenOptions::new().read(true).write(true), false)?;
+		let (_, old, trailer) = read_archive(&mut file).with_path("failed to read", &vfs_file)?;
+		let current = GAME_VERSION.load(Ordering::Relaxed);
+		let restamp = current != 0 && trailer.version != Some(current);
+
+		let mut changed = old.keys().filter(|p| !walker.map.contains_key(*p)).count();
+		let mut mtimes = BTreeMap::new();
+
+		let mut offset = file.seek(SeekFrom::End(0))?;
+		let mut writer = BufWriter::with_capacity(BUF_LEN, &mut file);
+		let mut buf = vec![0; BUF_LEN];
 
-			let mut walker = Walker {
-				path: path.clone(),
-				map: BTreeMap::new(),
-				size: 0
+		for (p, entry) in walker.map.iter_mut() {
+			let (e_offset, len) = match entry {
+				Entry::File { offset, len } => (offset, *len),
+				Entry::Directory => {
+					if old.get(p) != Some(&Entry::Directory) {
+						changed += 1;
+					}
+
+					continue;
+				}
 			};
 
-			walker.walk(&path);
-			let entries_len = walker.map.len();
+			let path = &walker.paths[p];
+			let mtime = mtime(path);
 
-			let mut file = BufWriter::with_capacity(
-				BUF_LEN,
-				File::create(&vfs_file).expect("failed to create VFS")
-			);
+			// archives packed before mtimes were recorded only have their own to go by
+			let modified = match &trailer.mtimes {
+				Some(mtimes) => mtime.is_none() || mtimes.get(p) != mtime.as_ref(),
+				None => {
+					path.metadata()
+						.and_then(|m| m.modified())
+						.map_or(true, |m| m >= since)
+				}
+			};
+
+			if let Some(mtime) = mtime {
+				mtimes.insert(p.clone(), mtime);
+			}
+
+			match old.get(p) {
+				Some(&Entry::File { offset: o, len: l }) if l == len && !modified => {
+					*e_offset = o;
+				}
+				_ => {
+					let l = File::open(path)
+						.and_then(|mut f| copy(&mut f, &mut writer, &mut buf))
+						.with_path("failed to pack", path)?;
+
+					if l != len {
+						return Err(changed_while_packing(path));
+					}
 
-			file.seek(SeekFrom::Start(8)).unwrap();
-
-			let mut buf = vec![0; BUF_LEN];
-			let mut offset: u64 = 8;
-			let mut instant = Instant::now();
-
-			for (i, (p, entry)) in walker.map.iter_mut().enumerate() {
-				if let Entry::File {
-					offset: e_offset,
-					len
-				} = entry
-				{
-					let path = path.join(p);
-
-					let l = copy(
-						&mut File::open(&path)
-							.expect(&format!("failed to open file: {}", path.display())),
-						&mut file,
-						&mut buf
-					)
-					.expect("failed to write to VFS");
-
-					assert_eq!(*len as u64, l);
 					*e_offset = offset;
 					offset += l;
+					changed += 1;
+
+					tracing::debug!("updated {}", path.display());
 				}
+			}
+		}
 
-				let ins = Instant::now();
+		if changed == 0 && !restamp {
+			return Ok(0);
+		}
+
+		let waste = waste(&walker.map, offset);
+
+		serialize_map(&mut writer, &walker.map)?;
+		write_trailer(&mut writer, current, &mtimes, waste)?;
+		writer.flush()?;
+		drop(writer);
 
-				if ins.duration_since(instant) >= Duration::from_millis(100) {
-					print!(
-						"\rcopying files into VFS: {:6}/{:6} {}/{} {:5.1}%",
-						i,
-						entries_len,
-						format_size(offset - 8),
-						format_size(walker.size),
-						(offset - 8) as f64 / walker.size as f64 * 100.0
-					);
+		swap_map(&mut file, offset).with_path("failed to update", &vfs_file)?;
+		drop(file);
 
-					io::stdout().flush().unwrap();
-					instant = ins;
+		// appending leaves every replaced file and map behind, only a repack reclaims them
+		if waste * REPACK_WASTE > offset {
+			tracing::info!(
+				"{} bytes of {} are unreachable, repacking",
+				waste,
+				vfs_file.display()
+			);
+
+			match Self::write(&path, &vfs_file) {
+				Ok(()) => {}
+				Err(e) if e.kind() == ErrorKind::WouldBlock => {
+					tracing::info!("{} is in use, repacking later", vfs_file.display());
 				}
+				Err(e) => return Err(e)
 			}
+		}
 
-			println!("\nfinished copying files into VFS");
+		Ok(changed)
+	}
+
+	// appends one file and a new map and swaps the header like update_dir does, until the next
+	// update or repack from the loose files drops it again; unlike update_dir it needs the archive
+	// to itself, so nothing reading it can be left holding the replaced entry
+	pub fn put(path: PathBuf, dir: &Path, target: &Path, source: &Path) -> io::Result<()> {
+		let vfs_file = path.join(archive_name(dir));
+		let key = suffix(&path.join(dir), target)
+			.filter(|key| !key.as_os_str().is_empty())
+			.ok_or_else(|| {
+				io::Error::new(
+					ErrorKind::InvalidInput,
+					format!("{} is not inside {}", target.display(), dir.display())
+				)
+			})?;
 
-			file.seek(SeekFrom::Start(0)).unwrap();
-			file.write_all(&offset.to_le_bytes())
-				.expect("failed to write VFS map offset");
+		let mut file = open_archive(&vfs_file, OpenOptions::new().read(true).write(true), true)?;
+		let (_, mut map, trailer) =
+			read_archive(&mut file).with_path("failed to read", &vfs_file)?;
+		// the put data doesn't come from the loose file, so update_dir must not trust its mtime
+		let mut mtimes = trailer.mtimes.unwrap_or_default();
+		mtimes.remove(&key);
 
-			file.seek(SeekFrom::End(0)).unwrap();
-			bincode::serialize_into(&mut file, &walker.map).expect("failed to serialize VFS map");
+		for parent in key
+			.ancestors()
+			.skip(1)
+			.filter(|p| !p.as_os_str().is_empty())
+		{
+			match map.entry(parent.to_path_buf()).or_insert(Entry::Directory) {
+				Entry::Directory => {}
+				Entry::File { .. } => {
+					return Err(io::Error::new(
+						ErrorKind::InvalidInput,
+						format!("{} is a file in the archive", parent.display())
+					))
+				}
+			}
+		}
 
-			println!("finished creating VFS");
+		if map.get(&key) == Some(&Entry::Directory) {
+			return Err(io::Error::new(
+				ErrorKind::InvalidInput,
+				format!("{} is a directory in the archive", key.display())
+			));
+		}
+
+		let offset = file.seek(SeekFrom::End(0))?;
+		let mut writer = BufWriter::with_capacity(BUF_LEN, &mut file);
+
+		let len = File::open(source)
+			.and_then(|mut f| copy(&mut f, &mut writer, &mut vec![0; BUF_LEN]))
+			.with_path("failed to read", source)?;
+
+		map.insert(key, Entry::File { offset, len });
+		let waste = waste(&map, offset + len);
+
+		serialize_map(&mut writer, &map)?;
+		write_trailer(&mut writer, trailer.version.unwrap_or(0), &mtimes, waste)?;
+		writer.flush()?;
+		drop(writer);
+
+		swap_map(&mut file, offset + len).with_path("failed to update", &vfs_file)
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	pub fn source(&self) -> &Path {
+		&self.source
+	}
+
+	pub fn entries(&self) -> impl Iterator<Item = (&Path, &Entry)> {
+		self.map.iter().map(|(k, v)| (k.as_path(), v))
+	}
+
+	pub fn verify(&self) -> io::Result<Vec<(PathBuf, String)>> {
+		let mut problems = Vec::new();
+		let mut buf = (vec![0; BUF_LEN], vec![0; BUF_LEN]);
+
+		let mut walker = Walker {
+			path: self.source.clone(),
+			map: BTreeMap::new(),
+			paths: BTreeMap::new(),
+			size: 0
+		};
+
+		walker.walk(&self.source)?;
+
+		for (suffix, entry) in &self.map {
+			let path = walker
+				.paths
+				.get(suffix)
+				.cloned()
+				.unwrap_or_else(|| self.source.join(suffix));
+
+			let problem = match *entry {
+				Entry::Directory if path.is_dir() => continue,
+				Entry::Directory => "directory missing".to_owned(),
+				Entry::File { offset, len } => {
+					match self.compare(&path, offset, len, &mut buf) {
+						Ok(true) => continue,
+						Ok(false) => "contents differ".to_owned(),
+						Err(e) => e.to_string()
+					}
+				}
+			};
+
+			problems.push((path, problem));
+		}
+
+		for (suffix, path) in &walker.paths {
+			if !self.map.contains_key(suffix) {
+				problems.push((path.clone(), "not in archive".to_owned()));
+			}
+		}
+
+		Ok(problems)
+	}
+
+	fn compare(
+		&self,
+		path: &Path,
+		offset: u64,
+		len: u64,
+		(archive, loose): &mut (Vec<u8>, Vec<u8>)
+	) -> io::Result<bool> {
+		let mut file = File::open(path)?;
+
+		if file.metadata()?.len() != len {
+			return Ok(false);
+		}
+
+		let mut index = 0;
+
+		while index < len {
+			let chunk = (len - index).min(BUF_LEN as u64) as usize;
+
+			file.read_exact(&mut loose[..chunk])?;
+
+			let mut read = 0;
+
+			while read < chunk {
+				match read_at(
+					&self.file,
+					&mut archive[read..chunk],
+					offset + index + read as u64
+				)? {
+					0 => return Err(ErrorKind::UnexpectedEof.into()),
+					n => read += n
+				}
+			}
+
+			if archive[..chunk] != loose[..chunk] {
+				return Ok(false);
+			}
+
+			index += chunk as u64;
+		}
+
+		Ok(true)
+	}
+
+	pub fn suffix(&self, path: &Path) -> Option<PathBuf> {
+		suffix(&self.path, path)
+	}
+
+	pub fn key(&self, path: &Path) -> Option<PathBuf> {
+		Some(self.prefix.join(self.suffix(path)?))
+	}
+
+	// inverse of key
+	pub fn resolve(&self, key: &Path) -> Option<PathBuf> {
+		let path = self.path.join(key.strip_prefix(&self.prefix).ok()?);
+
+		if self.has_file(&path) {
+			Some(path)
+		} else {
+			None
 		}
 	}
 
@@ -155,75 +539,111 @@ impl Vfs {
 		suffix(&self.path, path).is_some()
 	}
 
+	pub fn has_file(&self, path: &Path) -> bool {
+		suffix(&self.path, path).map_or(false, |s| {
+			matches!(self.map.get(&s), Some(Entry::File { .. }))
+		})
+	}
+
+	pub fn has_dir(&self, path: &Path) -> bool {
+		suffix(&self.path, path).map_or(false, |s| {
+			matches!(self.map.get(&s), Some(Entry::Directory))
+		})
+	}
+
 	pub fn read(&self, path: &Path) -> Option<Option<Reader>> {
 		match self.map.get(&suffix(&self.path, path)?) {
 			Some(&Entry::File { offset, len }) => {
 				Some(Some(Reader {
-					file: &self.file,
+					file: self.file.clone(),
 					offset,
 					len,
-					index: 0
+					index: 0,
+					streak: 0,
+					buf: Vec::new(),
+					buf_len: BUF_LEN,
+					buf_index: 0
 				}))
 			}
 			_ => Some(None)
 		}
 	}
 
-	pub fn find(&self, path: &Path) -> Option<Vec<(&str, &Entry)>> {
+	// None if the search isn't inside this archive, Some(None) if the directory doesn't exist
+	pub fn find(&self, path: &Path) -> Option<Option<Vec<(String, Entry)>>> {
 		let path = suffix(&self.path, path)?;
-		let dir = path.parent().unwrap();
+		let dir = path.parent()?;
 
-		assert_eq!(self.map.get(dir), Some(&Entry::Directory));
+		if self.map.get(dir) != Some(&Entry::Directory) {
+			return Some(None);
+		}
 
-		let file_name = path.file_name().unwrap().to_str().unwrap();
-		assert!(!file_name.contains('\\'));
-		assert!(file_name.contains('*'));
+		// *.* matches names without an extension too
+		let file_name = match path.file_name()?.to_str()? {
+			"*.*" => "*",
+			file_name => file_name
+		};
+
+		let pattern = glob_regex(file_name);
 
-		let mut pattern = String::new();
-		pattern.insert(0, '^');
-		pattern.push_str(
-			&file_name
-				.replace('.', r"\.")
-				.replace('?', ".")
-				.replace('*', r".*")
-		);
-		pattern.push('$');
+		let mut entries: Vec<_> = self
+			.map
+			.range(PathBuf::from(dir)..)
+			.take_while(|(k, _)| k.starts_with(dir))
+			.filter_map(|(k, v)| k.strip_prefix(dir).ok().map(|s| (s.to_str().unwrap(), v)))
+			.filter(|(k, _)| !k.contains(MAIN_SEPARATOR))
+			.map(|(k, &v)| ((if k.is_empty() { "." } else { k }).to_owned(), v))
+			.filter(|(k, _)| pattern.is_match(k))
+			.collect();
 
-		let pattern = Regex::new(&pattern).unwrap();
+		// the archive root is game/Data, never a drive root, so it has a parent too
+		if pattern.is_match("..") {
+			entries.push(("..".to_owned(), Entry::Directory));
+		}
+
+		sort(&mut entries);
+		Some(Some(entries))
+	}
+
+	pub fn find_recursive(&self, path: &Path) -> Option<Vec<(&Path, &Entry)>> {
+		let path = suffix(&self.path, path)?;
+
+		let dir: PathBuf = path
+			.components()
+			.take_while(|c| !c.as_os_str().to_str().unwrap().contains(&['*', '?'][..]))
+			.collect();
+
+		let pattern = glob_regex(path.to_str().unwrap());
 
 		Some(
 			self.map
-				.range(PathBuf::from(dir)..)
-				.take_while(|(k, _)| k.starts_with(dir))
-				.filter_map(|(k, v)| k.strip_prefix(dir).ok().map(|s| (s.to_str().unwrap(), v)))
-				.filter(|(k, _)| !k.contains('\\') && pattern.is_match(k))
-				.map(|(k, v)| (if k.is_empty() { "." } else { k }, v))
+				.range(dir.clone()..)
+				.take_while(|(k, _)| k.starts_with(&dir))
+				.filter(|(k, _)| pattern.is_match(k.to_str().unwrap()))
+				.map(|(k, v)| (k.as_path(), v))
 				.collect()
 		)
 	}
 }
 
 impl Walker {
-	fn walk(&mut self, path: &Path) {
-		let m = path
-			.symlink_metadata()
-			.expect(&format!("failed to get metadata: {}", path.display()));
+	fn walk(&mut self, path: &Path) -> io::Result<()> {
+		let m = path.symlink_metadata().with_path("failed to read", path)?;
+
+		let suffix = suffix(&self.path, path).ok_or_else(|| {
+			io::Error::new(
+				ErrorKind::InvalidInput,
+				format!("{} is not inside {}", path.display(), self.path.display())
+			)
+		})?;
 
-		let suffix = suffix(&self.path, path).expect(&format!(
-			"strip prefix: {} {}",
-			path.display(),
-			self.path.display()
-		));
+		self.paths.insert(suffix.clone(), path.to_path_buf());
 
 		if m.is_dir() {
 			self.map.insert(suffix, Entry::Directory);
 
-			for entry in path
-				.read_dir()
-				.expect(&format!("failed to read dir: {}", path.display()))
-			{
-				let entry = entry.expect(&format!("failed to read dir entry: {}", path.display()));
-				self.walk(&entry.path());
+			for entry in path.read_dir().with_path("failed to read", path)? {
+				self.walk(&entry.with_path("failed to read", path)?.path())?;
 			}
 		} else if m.is_file() {
 			self.size += m.len();
@@ -232,39 +652,112 @@ impl Walker {
 				suffix,
 				Entry::File {
 					offset: 0,
-					len: m.len().try_into().unwrap()
+					len: m.len()
 				}
 			);
 		} else {
-			panic!();
+			return Err(io::Error::new(
+				ErrorKind::InvalidInput,
+				format!("{} is neither a file nor a directory", path.display())
+			));
 		}
+
+		Ok(())
 	}
 }
 
-impl Reader<'_> {
-	pub fn len(&self) -> usize {
+impl Reader {
+	pub fn len(&self) -> u64 {
 		self.len
 	}
+
+	pub fn position(&self) -> u64 {
+		self.index
+	}
+
+	// the caller promised to read front to back, so don't wait to see it
+	pub fn prefetch(&mut self) {
+		self.buf_len = PREFETCH_BUF_LEN;
+		self.streak = self.streak.max(READAHEAD_AFTER);
+
+		if self.buffered().is_none() {
+			let _ = self.fill();
+		}
+	}
+
+	fn sequential(&self) -> bool {
+		self.streak >= READAHEAD_AFTER
+	}
+
+	fn buffered(&self) -> Option<&[u8]> {
+		if self.index >= self.buf_index && self.index < self.buf_index + self.buf.len() as u64 {
+			Some(&self.buf[(self.index - self.buf_index) as usize..])
+		} else {
+			None
+		}
+	}
+
+	fn fill(&mut self) -> io::Result<()> {
+		let start = ((self.offset + self.index) & !(READ_ALIGN - 1)).max(self.offset);
+		let end = (start + self.buf_len as u64).min(self.offset + self.len);
+
+		self.buf.resize((end - start) as usize, 0);
+		self.buf_index = start - self.offset;
+
+		let mut read = 0;
+
+		while read < self.buf.len() {
+			match read_at(&self.file, &mut self.buf[read..], start + read as u64) {
+				Ok(0) => {
+					self.buf.truncate(read);
+					break;
+				}
+				Ok(len) => read += len,
+				Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+				Err(e) => {
+					self.buf.clear();
+					return Err(e);
+				}
+			}
+		}
+
+		Ok(())
+	}
 }
 
-impl Read for Reader<'_> {
+impl Read for Reader {
 	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-		let len = buf.len().min(self.len - self.index);
+		let len = (buf.len() as u64).min(self.len.saturating_sub(self.index)) as usize;
 
 		if len == 0 {
 			return Ok(0);
 		}
 
-		let read = self
-			.file
-			.seek_read(&mut buf[..len], self.offset + self.index as u64)?;
+		let sequential = self.sequential();
+		self.streak = self.streak.saturating_add(1);
+
+		// a read as large as the buffer gains nothing from going through it
+		if len < self.buf_len {
+			if self.buffered().is_none() && sequential {
+				self.fill()?;
+			}
+
+			if let Some(buffered) = self.buffered() {
+				let len = len.min(buffered.len());
+				buf[..len].copy_from_slice(&buffered[..len]);
+				self.index += len as u64;
+				return Ok(len);
+			}
+		}
+
+		let read = read_at(&self.file, &mut buf[..len], self.offset + self.index)?;
 
-		self.index += read;
+		self.index += read as u64;
 		Ok(read)
 	}
 }
 
-impl Seek for Reader<'_> {
+impl Seek for Reader {
 	fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
 		let index = match from {
 			SeekFrom::Start(o) => o as i64,
@@ -274,30 +767,534 @@ impl Seek for Reader<'_> {
 
 		if index < 0 {
 			Err(ErrorKind::InvalidInput.into())
-		} else if index > self.len() as i64 {
-			panic!()
 		} else {
-			self.index = index as usize;
-			Ok(self.index as u64)
+			if index as u64 != self.index {
+				self.streak = 0;
+			}
+
+			self.index = index as u64;
+
+			// kept while seeks stay inside it
+			if self.buffered().is_none() {
+				self.buf = Vec::new();
+			}
+
+			Ok(self.index)
+		}
+	}
+}
+
+impl fmt::Display for Aborted {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("packing aborted")
+	}
+}
+
+impl std::error::Error for Aborted {}
+
+impl<T> PathContext<T> for io::Result<T> {
+	fn with_path(self, what: &str, path: &Path) -> io::Result<T> {
+		self.map_err(|e| {
+			if aborted(&e) {
+				e
+			} else {
+				io::Error::new(e.kind(), format!("{} {}: {}", what, path.display(), e))
+			}
+		})
+	}
+}
+
+pub fn data_dir() -> PathBuf {
+	config::get()
+		.data_dir
+		.unwrap_or_else(|| PathBuf::from(DATA_DIR))
+}
+
+// UNDERRAIL_DATA points at the real data directory, e.g. when Data is a junction to another
+// drive, while the game keeps asking for it under its own directory
+pub fn source_dir(path: &Path, dir: &Path) -> PathBuf {
+	match config::get().data {
+		Some(data) if dir == data_dir() => data,
+		_ => path.join(dir)
+	}
+}
+
+// translation and other content packs, in priority order
+pub fn packs(path: &Path) -> Vec<PathBuf> {
+	let mut packs: Vec<_> = path
+		.join(PACKS_DIR)
+		.read_dir()
+		.into_iter()
+		.flatten()
+		.flatten()
+		.map(|entry| entry.path())
+		.filter(|p| {
+			p.extension()
+				.map_or(false, |e| e.eq_ignore_ascii_case("vfs"))
+		})
+		.collect();
+
+	packs.sort();
+	packs
+}
+
+pub fn pack_path(path: &Path, name: &str) -> PathBuf {
+	path.join(PACKS_DIR).join(format!("{}.vfs", name))
+}
+
+// profiles get their own overlay so switching mod setups doesn't need a repack
+pub fn overlay_dir() -> PathBuf {
+	let config = config::get();
+
+	match (config.overlay, config.profile) {
+		(Some(overlay), _) => overlay,
+		(None, Some(profile)) => Path::new("Profiles").join(profile),
+		(None, None) => PathBuf::from(OVERLAY_DIR)
+	}
+}
+
+pub fn dirs() -> Vec<PathBuf> {
+	let mut dirs = vec![data_dir()];
+
+	if let Some(extra) = config::get().extra_dirs {
+		dirs.extend(extra.into_iter().map(PathBuf::from));
+	}
+
+	dirs
+}
+
+// top level directories that mirror part of Data's layout (expansion or DLC content) are packed
+// and intercepted like Data, unless disabled with UNDERRAIL_FIX_DETECT_DIRS=0
+pub fn detect_dirs(path: &Path) {
+	if config::get().detect_dirs == Some(false) {
+		return;
+	}
+
+	let subdirs = |dir: &Path| -> Vec<String> {
+		dir.read_dir()
+			.into_iter()
+			.flatten()
+			.flatten()
+			.filter(|entry| entry.path().is_dir())
+			.map(|entry| entry.file_name().to_string_lossy().to_lowercase())
+			.collect()
+	};
+
+	let layout = subdirs(&source_dir(path, &data_dir()));
+
+	if layout.is_empty() {
+		return;
+	}
+
+	let mut dirs = dirs();
+	let mut found = Vec::new();
+
+	for name in subdirs(path) {
+		let dir = PathBuf::from(&name);
+
+		if name.starts_with('.')
+			|| name == "profiles"
+			|| name == PACKS_DIR.to_lowercase()
+			|| overlay_dir().components().next().map_or(false, |c| {
+				c.as_os_str().to_string_lossy().eq_ignore_ascii_case(&name)
+			}) || dirs
+			.iter()
+			.any(|d| d.to_string_lossy().eq_ignore_ascii_case(&name))
+		{
+			continue;
 		}
+
+		if subdirs(&path.join(&dir)).iter().any(|s| layout.contains(s)) {
+			tracing::info!("detected content root {}", dir.display());
+			found.push(name);
+			dirs.push(dir);
+		}
+	}
+
+	if !found.is_empty() {
+		let mut extra = config::get().extra_dirs.unwrap_or_default();
+		extra.extend(found);
+		env::set_var("UNDERRAIL_FIX_EXTRA_DIRS", extra.join(","));
+	}
+}
+
+pub fn archive_name(dir: &Path) -> String {
+	let config = config::get();
+
+	let name = match config.archive {
+		Some(archive) if dir == data_dir() => archive,
+		_ => {
+			format!(
+				"{}.vfs",
+				dir.to_str()
+					.unwrap()
+					.trim_matches(&['\\', '/'][..])
+					.replace(&['\\', '/'][..], "_")
+					.to_lowercase()
+			)
+		}
+	};
+
+	// an absolute archive dir overrides the game dir it gets joined onto
+	match config.archive_dir {
+		Some(archive_dir) => archive_dir.join(name).to_string_lossy().into_owned(),
+		None => name
 	}
 }
 
 fn suffix(prefix: &Path, path: &Path) -> Option<PathBuf> {
-	Some(
-		path.strip_prefix(prefix)
-			.ok()?
-			.components()
-			.fold(PathBuf::new(), |mut path, c| {
-				match c {
-					Component::Normal(s) => path.push(s.to_str().unwrap().to_lowercase()),
-					Component::ParentDir => assert!(path.pop(), "{}", path.display()),
-					_ => panic!("{}", path.display())
-				}
+	let mut suffix = PathBuf::new();
 
-				path
-			})
-	)
+	for c in path.strip_prefix(prefix).ok()?.components() {
+		match c {
+			Component::Normal(s) => suffix.push(s.to_str()?.to_lowercase()),
+			Component::CurDir => {}
+			Component::ParentDir if suffix.pop() => {}
+			_ => return None
+		}
+	}
+
+	Some(suffix)
+}
+
+// packs share game/Data with the base archives, so a directory can be listed by several of them;
+// listings come in priority order and the first to list a name wins
+pub fn merge(listings: Vec<Vec<(String, Entry)>>) -> Vec<(String, Entry)> {
+	let mut entries: Vec<_> = listings.into_iter().flatten().collect();
+	sort(&mut entries);
+	entries.dedup_by(|(a, _), (b, _)| ntfs_key(a) == ntfs_key(b));
+	entries
+}
+
+// stable, which merge relies on
+fn sort(entries: &mut [(String, Entry)]) {
+	entries.sort_by_cached_key(|(name, _)| (name != "." && name != "..", ntfs_key(name)));
+}
+
+fn ntfs_key(name: &str) -> Vec<u16> {
+	let mut key = Vec::with_capacity(name.len());
+	let mut buf = [0; 2];
+
+	for c in name.chars() {
+		let mut upper = c.to_uppercase();
+
+		let c = match (upper.next(), upper.next()) {
+			(Some(u), None) => u,
+			_ => c
+		};
+
+		key.extend_from_slice(c.encode_utf16(&mut buf));
+	}
+
+	key
+}
+
+fn glob_regex(glob: &str) -> Regex {
+	let separator = regex::escape(&MAIN_SEPARATOR.to_string());
+	let mut pattern = String::from("^");
+	let mut components = glob.split(is_separator).peekable();
+
+	while let Some(component) = components.next() {
+		if component == "**" {
+			pattern.push_str(&format!("(?:[^{0}]*{0})*", separator));
+
+			if components.peek().is_none() {
+				pattern.push_str(".*");
+			}
+
+			continue;
+		}
+
+		for c in component.chars() {
+			match c {
+				'*' => pattern.push_str(&format!("[^{}]*", separator)),
+				'?' => pattern.push_str(&format!("[^{}]", separator)),
+				c => pattern.push_str(&regex::escape(c.encode_utf8(&mut [0; 4])))
+			}
+		}
+
+		if components.peek().is_some() {
+			pattern.push_str(&separator);
+		}
+	}
+
+	pattern.push('$');
+	Regex::new(&pattern).unwrap()
+}
+
+// archives always store Windows separators, so one packed on Linux works for the game
+fn serialize_map<W: Write, V: Serialize>(writer: W, map: &BTreeMap<PathBuf, V>) -> io::Result<()> {
+	if cfg!(windows) {
+		return bincode::serialize_into(writer, map).map_err(bincode_error);
+	}
+
+	let map: BTreeMap<_, _> = map
+		.iter()
+		.map(|(k, v)| (k.to_str().unwrap().replace(MAIN_SEPARATOR, "\\"), v))
+		.collect();
+
+	bincode::serialize_into(writer, &map).map_err(bincode_error)
+}
+
+fn deserialize_map<R: Read, V: DeserializeOwned>(reader: R) -> io::Result<BTreeMap<PathBuf, V>> {
+	if cfg!(windows) {
+		return bincode::deserialize_from(reader).map_err(bincode_error);
+	}
+
+	let map: BTreeMap<String, V> = bincode::deserialize_from(reader).map_err(bincode_error)?;
+
+	Ok(map
+		.into_iter()
+		.map(|(k, v)| (k.replace('\\', &MAIN_SEPARATOR.to_string()).into(), v))
+		.collect())
+}
+
+// a map that doesn't deserialize means a corrupt archive rather than a bug
+fn bincode_error(e: bincode::Error) -> io::Error {
+	match *e {
+		bincode::ErrorKind::Io(e) => e,
+		e => io::Error::new(ErrorKind::InvalidData, e)
+	}
+}
+
+// the build of the game new and updated archives are stamped with, so the launcher can tell when
+// a patch has outdated them
+pub fn set_game_version(version: [u16; 4]) {
+	let [a, b, c, d] = version;
+	let version = (a as u64) << 48 | (b as u64) << 32 | (c as u64) << 16 | d as u64;
+	GAME_VERSION.store(version, Ordering::Relaxed);
+}
+
+// None for archives packed before the stamp existed or without a known game version
+pub fn game_version(vfs_file: &Path) -> Option<[u16; 4]> {
+	let mut file = open_archive(vfs_file, OpenOptions::new().read(true), false).ok()?;
+	let (_, _, trailer) = read_archive(&mut file).ok()?;
+	let version = trailer.version?;
+
+	Some([
+		(version >> 48) as u16,
+		(version >> 32) as u16,
+		(version >> 16) as u16,
+		version as u16
+	])
+}
+
+fn stamp<W: Write>(mut writer: W, version: u64) -> io::Result<()> {
+	match version {
+		0 => Ok(()),
+		version => {
+			writer.write_all(GAME_VERSION_TAG)?;
+			writer.write_all(&version.to_le_bytes())
+		}
+	}
+}
+
+// None for archives packed before the count existed
+pub fn wasted(vfs_file: &Path) -> Option<u64> {
+	let mut file = open_archive(vfs_file, OpenOptions::new().read(true), false).ok()?;
+	let (_, _, trailer) = read_archive(&mut file).ok()?;
+	trailer.waste
+}
+
+// everything between the header and the map that isn't one of its files
+fn waste(map: &BTreeMap<PathBuf, Entry>, map_offset: u64) -> u64 {
+	let live: u64 = map
+		.values()
+		.map(|entry| {
+			match *entry {
+				Entry::File { len, .. } => len,
+				Entry::Directory => 0
+			}
+		})
+		.sum();
+
+	map_offset.saturating_sub(8 + live)
+}
+
+fn write_trailer<W: Write>(
+	mut writer: W,
+	version: u64,
+	mtimes: &BTreeMap<PathBuf, u64>,
+	waste: u64
+) -> io::Result<()> {
+	stamp(&mut writer, version)?;
+	writer.write_all(MTIMES_TAG)?;
+	serialize_map(&mut writer, mtimes)?;
+	writer.write_all(WASTE_TAG)?;
+	writer.write_all(&waste.to_le_bytes())
+}
+
+// returns the map's offset along with it
+fn read_archive(file: &mut File) -> io::Result<(u64, BTreeMap<PathBuf, Entry>, Trailer)> {
+	let mut map_offset = [0; 8];
+
+	file.seek(SeekFrom::Start(0))?;
+	file.read_exact(&mut map_offset)?;
+
+	let offset = u64::from_le_bytes(map_offset);
+
+	file.seek(SeekFrom::Start(offset))?;
+
+	let map = deserialize_map(BufReader::new(&mut *file))?;
+	// keys are written with backslashes everywhere, which are as long as any separator here
+	let end = offset + bincode::serialized_size(&map).map_err(bincode_error)?;
+
+	Ok((offset, map, read_trailer(file, end)))
+}
+
+// read right after the map rather than at the end of the file, which may hold an update that
+// never got as far as the header
+fn read_trailer(file: &mut File, end: u64) -> Trailer {
+	let mut trailer = Trailer {
+		version: None,
+		mtimes: None,
+		waste: None
+	};
+
+	let mut reader = BufReader::new(file);
+
+	if reader.seek(SeekFrom::Start(end)).is_err() {
+		return trailer;
+	}
+
+	let mut tag = [0; 4];
+
+	if reader.read_exact(&mut tag).is_err() {
+		return trailer;
+	}
+
+	if &tag == GAME_VERSION_TAG {
+		let mut version = [0; 8];
+
+		if reader.read_exact(&mut version).is_err() {
+			return trailer;
+		}
+
+		trailer.version = Some(u64::from_le_bytes(version));
+
+		if reader.read_exact(&mut tag).is_err() {
+			return trailer;
+		}
+	}
+
+	if &tag != MTIMES_TAG {
+		return trailer;
+	}
+
+	trailer.mtimes = deserialize_map(&mut reader).ok();
+
+	if trailer.mtimes.is_none() || reader.read_exact(&mut tag).is_err() || &tag != WASTE_TAG {
+		return trailer;
+	}
+
+	let mut waste = [0; 8];
+
+	if reader.read_exact(&mut waste).is_ok() {
+		trailer.waste = Some(u64::from_le_bytes(waste));
+	}
+
+	trailer
+}
+
+// everything the new map points at must be on disk before the header does
+fn swap_map(file: &mut File, offset: u64) -> io::Result<()> {
+	file.sync_all()?;
+	file.seek(SeekFrom::Start(0))?;
+	file.write_all(&offset.to_le_bytes())?;
+	file.sync_all()
+}
+
+fn mtime(path: &Path) -> Option<u64> {
+	let modified = path.metadata().and_then(|m| m.modified()).ok()?;
+	Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64)
+}
+
+// readers share an archive with update_dir's appends, but recreating it would pull the data out
+// from under them, so that and put need the archive to itself; share modes enforce this on Windows,
+// an flock elsewhere, which only holds against others that take it here too
+fn open_archive(path: &Path, options: &mut OpenOptions, exclusive: bool) -> io::Result<File> {
+	#[cfg(windows)]
+	{
+		use std::os::windows::fs::OpenOptionsExt;
+		use winapi::um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE};
+
+		options.share_mode(if exclusive {
+			0
+		} else {
+			FILE_SHARE_READ | FILE_SHARE_WRITE
+		});
+	}
+
+	let file = options.open(path);
+
+	#[cfg(unix)]
+	let file = file.and_then(|file| lock(&file, exclusive).map(|()| file));
+
+	// WouldBlock on every platform, so callers can tell it apart and try again later
+	file.map_err(|e| {
+		if in_use(&e) {
+			io::Error::new(
+				ErrorKind::WouldBlock,
+				format!(
+					"another instance is using the archive {}, close the game or the other launcher first",
+					path.display()
+				)
+			)
+		} else {
+			io::Error::new(
+				e.kind(),
+				format!("failed to open {}: {}", path.display(), e)
+			)
+		}
+	})
+}
+
+#[cfg(windows)]
+fn in_use(e: &io::Error) -> bool {
+	e.raw_os_error() == Some(winapi::shared::winerror::ERROR_SHARING_VIOLATION as i32)
+}
+
+#[cfg(unix)]
+fn in_use(e: &io::Error) -> bool {
+	e.raw_os_error() == Some(libc::EWOULDBLOCK)
+}
+
+// held until the file is closed
+#[cfg(unix)]
+fn lock(file: &File, exclusive: bool) -> io::Result<()> {
+	use std::os::unix::io::AsRawFd;
+
+	let operation = if exclusive {
+		libc::LOCK_EX
+	} else {
+		libc::LOCK_SH
+	};
+
+	match unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } {
+		0 => Ok(()),
+		_ => Err(io::Error::last_os_error())
+	}
+}
+
+// refuses while anything else has the archive open, as packing does; Windows already won't delete
+// an open one
+pub fn remove_archive(path: &Path) -> io::Result<()> {
+	#[cfg(unix)]
+	let _lock = open_archive(path, OpenOptions::new().read(true), true)?;
+
+	fs::remove_file(path)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+	use std::os::windows::fs::FileExt;
+	file.seek_read(buf, offset)
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+	use std::os::unix::fs::FileExt;
+	file.read_at(buf, offset)
 }
 
 fn copy<R, W>(reader: &mut R, writer: &mut W, buf: &mut [u8]) -> io::Result<u64>
@@ -320,11 +1317,178 @@ where
 	}
 }
 
-fn format_size(size: u64) -> String {
-	match size {
-		0..=999 => format!("{:6}B  ", size),
-		1000..=1022976 => format!("{:6.1}KiB", size as f64 / 1024.0),
-		1022977..=1047527424 => format!("{:6.2}MiB", size as f64 / 1024.0 / 1024.0),
-		_ => format!("{:6.2}GiB", size as f64 / 1024.0 / 1024.0 / 1024.0)
+// copies the files after the header, then writes the map and points the header at it
+fn write_archive(
+	file: File,
+	map: &mut BTreeMap<PathBuf, Entry>,
+	files: &[(PathBuf, PathBuf)],
+	mtimes: &BTreeMap<PathBuf, u64>,
+	size: u64
+) -> io::Result<()> {
+	let (threads, buf_len) = pack_options();
+	let mut file = BufWriter::with_capacity(buf_len, file);
+
+	file.seek(SeekFrom::Start(8))?;
+
+	let mut offset: u64 = 8;
+	let bar = progress_bar(size);
+
+	let record =
+		|map: &mut BTreeMap<PathBuf, Entry>, p: &Path, path: &Path, l: u64, offset: &mut u64| {
+			if let Some(Entry::File {
+				offset: e_offset,
+				len
+			}) = map.get_mut(p)
+			{
+				if *len != l {
+					return Err(changed_while_packing(path));
+				}
+
+				*e_offset = *offset;
+			}
+
+			*offset += l;
+			bar.set_position(*offset - 8);
+			bar.set_message(p.display().to_string());
+			Ok(())
+		};
+
+	if threads <= 1 {
+		let mut buf = vec![0; buf_len];
+
+		for (p, path) in files {
+			let l = File::open(path)
+				.and_then(|mut f| copy(&mut f, &mut file, &mut buf))
+				.with_path("failed to pack", path)?;
+
+			record(map, p, path, l, &mut offset)?;
+
+			if ABORT.load(Ordering::SeqCst) {
+				break;
+			}
+		}
+	} else {
+		// readers load whole files in parallel; the archive is written in arrival order
+		let queue = Arc::new((files.to_vec(), AtomicUsize::new(0)));
+		let (tx, rx) = mpsc::sync_channel(threads);
+
+		let readers: Vec<_> = (0..threads)
+			.map(|_| {
+				let (queue, tx) = (queue.clone(), tx.clone());
+
+				thread::spawn(move || {
+					let (files, next) = &*queue;
+
+					while let Some((p, path)) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+						let data = fs::read(path).with_path("failed to pack", path);
+
+						if tx.send((p.clone(), path.clone(), data)).is_err() {
+							break;
+						}
+					}
+				})
+			})
+			.collect();
+
+		drop(tx);
+
+		let mut receive = || -> io::Result<()> {
+			for (p, path, data) in &rx {
+				let data = data?;
+				file.write_all(&data)?;
+				record(map, &p, &path, data.len() as u64, &mut offset)?;
+
+				if ABORT.load(Ordering::SeqCst) {
+					break;
+				}
+			}
+
+			Ok(())
+		};
+
+		let result = receive();
+		drop(rx);
+
+		for reader in readers {
+			reader
+				.join()
+				.map_err(|_| io::Error::new(ErrorKind::Other, "VFS reader thread panicked"))?;
+		}
+
+		result?;
+	}
+
+	if ABORT.load(Ordering::SeqCst) {
+		bar.abandon_with_message("aborted");
+		return Err(io::Error::new(ErrorKind::Interrupted, Aborted));
+	}
+
+	bar.finish_and_clear();
+	tracing::info!("finished copying files into VFS");
+
+	file.seek(SeekFrom::Start(0))?;
+	file.write_all(&offset.to_le_bytes())?;
+
+	file.seek(SeekFrom::End(0))?;
+	serialize_map(&mut file, map)?;
+	write_trailer(&mut file, GAME_VERSION.load(Ordering::Relaxed), mtimes, 0)?;
+	file.flush()
+}
+
+fn changed_while_packing(path: &Path) -> io::Error {
+	io::Error::new(
+		ErrorKind::Other,
+		format!("{} changed while it was being packed", path.display())
+	)
+}
+
+fn not_dir(path: &Path) -> io::Error {
+	io::Error::new(
+		ErrorKind::InvalidInput,
+		format!("{} is not a directory", path.display())
+	)
+}
+
+fn pack_options() -> (usize, usize) {
+	let config = config::get();
+
+	(
+		config.pack_threads.unwrap_or(1),
+		config
+			.pack_buffer_size
+			.filter(|&len| len > 0)
+			.unwrap_or(BUF_LEN)
+	)
+}
+
+fn progress_bar(size: u64) -> ProgressBar {
+	if cfg!(feature = "no-console") {
+		return ProgressBar::hidden();
+	}
+
+	let bar = ProgressBar::new(size);
+
+	bar.set_style(
+		ProgressStyle::default_bar()
+			.template("{bar:30} {bytes}/{total_bytes} {bytes_per_sec} ETA {eta} {wide_msg}")
+			.progress_chars("=> ")
+	);
+
+	bar
+}
+
+// true for the error a pack returns after abort
+pub fn aborted(e: &io::Error) -> bool {
+	e.get_ref().map_or(false, |e| e.is::<Aborted>())
+}
+
+// lets the launcher's Ctrl+C handler stop a pack without leaving a partial archive behind, the
+// pack then returns an error aborted recognizes
+pub fn abort() -> bool {
+	if PACKING.load(Ordering::SeqCst) {
+		ABORT.store(true, Ordering::SeqCst);
+		true
+	} else {
+		false
 	}
 }
@@ -0,0 +1,118 @@
+use crate::vfs::{self, Vfs};
+use anyhow::Context;
+use std::{
+	io::Error, iter, os::windows::ffi::OsStrExt, path::Path, ptr, sync::mpsc, thread,
+	time::Duration
+};
+use winapi::{
+	shared::minwindef::{FALSE, TRUE},
+	um::{
+		fileapi::{CreateFileW, OPEN_EXISTING},
+		handleapi::INVALID_HANDLE_VALUE,
+		winbase::{ReadDirectoryChangesW, FILE_FLAG_BACKUP_SEMANTICS},
+		winnt::{
+			FILE_LIST_DIRECTORY, FILE_NOTIFY_CHANGE_DIR_NAME, FILE_NOTIFY_CHANGE_FILE_NAME,
+			FILE_NOTIFY_CHANGE_LAST_WRITE, FILE_NOTIFY_CHANGE_SIZE, FILE_SHARE_DELETE,
+			FILE_SHARE_READ, FILE_SHARE_WRITE, HANDLE
+		}
+	}
+};
+
+const NOTIFY_BUF_LEN: usize = 1 << 16;
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+pub fn watch(path: &Path) -> anyhow::Result<()> {
+	let (tx, rx) = mpsc::channel();
+
+	for dir in vfs::dirs() {
+		let full = vfs::source_dir(path, &dir);
+
+		if !full.is_dir() {
+			tracing::warn!("{} not found, not watching", full.display());
+			continue;
+		}
+
+		let handle = open(&full) as usize;
+		let tx = tx.clone();
+
+		thread::spawn(move || {
+			let mut buf = vec![0u32; NOTIFY_BUF_LEN / 4];
+
+			loop {
+				let mut returned = 0;
+
+				let b = unsafe {
+					ReadDirectoryChangesW(
+						handle as HANDLE,
+						buf.as_mut_ptr() as *mut _,
+						NOTIFY_BUF_LEN as _,
+						TRUE,
+						FILE_NOTIFY_CHANGE_FILE_NAME
+							| FILE_NOTIFY_CHANGE_DIR_NAME
+							| FILE_NOTIFY_CHANGE_LAST_WRITE
+							| FILE_NOTIFY_CHANGE_SIZE,
+						&mut returned,
+						ptr::null_mut(),
+						None
+					)
+				};
+
+				if b == FALSE || tx.send(dir.clone()).is_err() {
+					break;
+				}
+			}
+		});
+
+		tracing::info!("watching {}", full.display());
+	}
+
+	drop(tx);
+
+	while let Ok(dir) = rx.recv() {
+		let mut dirs = vec![dir];
+
+		// editors tend to write in bursts, so wait for things to settle
+		while let Ok(dir) = rx.recv_timeout(DEBOUNCE) {
+			if !dirs.contains(&dir) {
+				dirs.push(dir);
+			}
+		}
+
+		for dir in dirs {
+			let changed = Vfs::update_dir(path.into(), &dir)
+				.with_context(|| format!("failed to update {}", vfs::archive_name(&dir)))?;
+
+			if changed > 0 {
+				tracing::info!("updated {} entries in {}", changed, vfs::archive_name(&dir));
+			}
+		}
+	}
+
+	Ok(())
+}
+
+fn open(dir: &Path) -> HANDLE {
+	let wide: Vec<_> = dir.as_os_str().encode_wide().chain(iter::once(0)).collect();
+
+	let handle = unsafe {
+		CreateFileW(
+			wide.as_ptr(),
+			FILE_LIST_DIRECTORY,
+			FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+			ptr::null_mut(),
+			OPEN_EXISTING,
+			FILE_FLAG_BACKUP_SEMANTICS,
+			ptr::null_mut()
+		)
+	};
+
+	assert_ne!(
+		handle,
+		INVALID_HANDLE_VALUE,
+		"CreateFileW {}: {}",
+		dir.display(),
+		Error::last_os_error()
+	);
+
+	handle
+}
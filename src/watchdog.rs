@@ -0,0 +1,118 @@
+use crate::{cli, config, report, stats::Stats};
+use std::{
+	path::Path,
+	ptr, thread,
+	time::{Duration, Instant}
+};
+use underrail_fix::crash::{
+	self, MINI_DUMP_WITH_FULL_MEMORY_INFO, MINI_DUMP_WITH_HANDLE_DATA, MINI_DUMP_WITH_THREAD_INFO
+};
+use winapi::{
+	shared::winerror::WAIT_TIMEOUT,
+	um::{
+		processthreadsapi::TerminateProcess, synchapi::WaitForSingleObject, winnt::HANDLE,
+		winuser::WaitForInputIdle
+	}
+};
+
+const POLL: Duration = Duration::from_secs(1);
+
+// UNDERRAIL_FIX_WATCHDOG is the number of seconds the game may go without touching a file before
+// it has a window to take input, 0 or unset to wait forever
+pub fn timeout() -> Option<Duration> {
+	config::get()
+		.watchdog
+		.map(|secs| secs as u64)
+		.filter(|&secs| secs > 0)
+		.map(Duration::from_secs)
+}
+
+// the process handle is the launcher's, which stays open until it exits
+pub fn watch(dir: &Path, pid: u32, process: usize, timeout: Duration) {
+	let process = process as HANDLE;
+	let mut stats = None;
+	let mut last = None;
+	let mut since = Instant::now();
+
+	loop {
+		thread::sleep(POLL);
+
+		unsafe {
+			if WaitForSingleObject(process, 0) != WAIT_TIMEOUT {
+				return;
+			}
+
+			// the main menu is up and pumping messages
+			if WaitForInputIdle(process, 0) == 0 {
+				tracing::debug!("game reached its message loop, stopping the watchdog");
+				return;
+			}
+		}
+
+		// not armed until the DLL has mapped its counters, there is no progress to see before then
+		let counters = match stats {
+			Some(stats) => stats,
+			None => {
+				stats = Stats::open(pid);
+				since = Instant::now();
+				continue;
+			}
+		};
+
+		let progress = [
+			&counters.opens,
+			&counters.misses,
+			&counters.finds,
+			&counters.bytes_read
+		]
+		.iter()
+		.map(|&counter| Stats::get(counter))
+		.sum::<u64>();
+
+		if Some(progress) != last {
+			last = Some(progress);
+			since = Instant::now();
+		} else if since.elapsed() >= timeout {
+			break;
+		}
+	}
+
+	tracing::error!(
+		"the game made no progress in {}s while starting, it may be deadlocked",
+		timeout.as_secs()
+	);
+
+	if let Some(stats) = stats {
+		tracing::error!("{}", stats.summary());
+	}
+
+	let dump = dir.join(format!("underrail_fix.{}.dmp", pid));
+
+	match unsafe {
+		crash::write_dump(
+			&dump,
+			process,
+			pid,
+			MINI_DUMP_WITH_HANDLE_DATA
+				| MINI_DUMP_WITH_FULL_MEMORY_INFO
+				| MINI_DUMP_WITH_THREAD_INFO,
+			ptr::null_mut()
+		)
+	} {
+		Ok(()) => tracing::info!("wrote {}", dump.display()),
+		Err(e) => tracing::warn!("failed to write {}: {}", dump.display(), e)
+	}
+
+	let message = format!(
+		"The game has made no progress in {}s while starting and may be stuck. Kill it?",
+		timeout.as_secs()
+	);
+
+	if cli::confirm(&message) {
+		unsafe {
+			TerminateProcess(process, report::HUNG);
+		}
+	} else {
+		tracing::info!("leaving the game running");
+	}
+}
@@ -0,0 +1,221 @@
+use crate::{config, WriteFileArgs, WriteFileFn};
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use std::{
+	collections::HashMap,
+	ffi::OsString,
+	fs, mem,
+	os::windows::ffi::OsStringExt,
+	path::{Path, PathBuf},
+	ptr, slice,
+	sync::atomic::{AtomicBool, Ordering},
+	thread::{self, JoinHandle},
+	time::Duration
+};
+use winapi::{
+	shared::minwindef::{BOOL, FALSE, TRUE},
+	um::{fileapi::GetFinalPathNameByHandleW, winnt::HANDLE}
+};
+
+const WRITE_BUF_LEN: usize = 1 << 16;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(crate) struct Writes {
+	paths: Vec<PathBuf>,
+	buffers: Mutex<HashMap<usize, Vec<u8>>>,
+	write_file: OnceCell<WriteFileFn>,
+	stop: AtomicBool,
+	thread: Mutex<Option<JoinHandle<()>>>
+}
+
+impl Writes {
+	pub(crate) fn new(path: &Path) -> Self {
+		// matched against the final path of each handle, which is canonical too
+		let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+
+		let paths = config::get()
+			.buffer_writes
+			.unwrap_or_default()
+			.iter()
+			.map(|s| lowercase(&path.join(s)))
+			.collect();
+
+		Self {
+			paths,
+			buffers: Mutex::new(HashMap::new()),
+			write_file: OnceCell::new(),
+			stop: AtomicBool::new(false),
+			thread: Mutex::new(None)
+		}
+	}
+
+	pub(crate) fn start(&'static self, write_file: WriteFileFn) {
+		if self.paths.is_empty() {
+			return;
+		}
+
+		assert!(
+			self.write_file.set(write_file).is_ok(),
+			"write buffering already started"
+		);
+
+		let thread = thread::spawn(move || {
+			while !self.stop.load(Ordering::Relaxed) {
+				thread::sleep(FLUSH_INTERVAL);
+				self.flush_all();
+			}
+		});
+
+		*self.thread.lock() = Some(thread);
+	}
+
+	pub(crate) fn stop(&self) -> Option<JoinHandle<()>> {
+		self.stop.store(true, Ordering::Relaxed);
+		self.thread.lock().take()
+	}
+
+	// the game may open a file relative to its working directory or through another spelling, so
+	// only the file name is compared before asking Windows where the handle really points
+	pub(crate) fn track(&self, path: &Path, handle: HANDLE) {
+		if self.write_file.get().is_none() {
+			return;
+		}
+
+		let name = match path.file_name() {
+			Some(name) => name.to_string_lossy().to_lowercase(),
+			None => return
+		};
+
+		if !self
+			.paths
+			.iter()
+			.any(|p| p.file_name().map_or(false, |n| n.to_string_lossy() == name))
+		{
+			return;
+		}
+
+		if final_path(handle).map_or(false, |path| self.paths.contains(&lowercase(&path))) {
+			self.buffers.lock().insert(handle as usize, Vec::new());
+		}
+	}
+
+	pub(crate) fn write(&self, args: WriteFileArgs) -> Option<BOOL> {
+		// bad buffers are left to WriteFile to fail the way the game expects
+		if self.paths.is_empty()
+			|| !args.lp_overlapped.is_null()
+			|| (args.lp_buffer.is_null() && args.n_number_of_bytes_to_write != 0)
+		{
+			return None;
+		}
+
+		let mut buffers = self.buffers.lock();
+		let buf = buffers.get_mut(&(args.h_file as usize))?;
+
+		if args.n_number_of_bytes_to_write == 0 {
+			if !args.lp_number_of_bytes_written.is_null() {
+				unsafe {
+					*args.lp_number_of_bytes_written = 0;
+				}
+			}
+
+			return Some(TRUE);
+		}
+
+		buf.extend_from_slice(unsafe {
+			slice::from_raw_parts(
+				args.lp_buffer as *const u8,
+				args.n_number_of_bytes_to_write as usize
+			)
+		});
+
+		if !args.lp_number_of_bytes_written.is_null() {
+			unsafe {
+				*args.lp_number_of_bytes_written = args.n_number_of_bytes_to_write;
+			}
+		}
+
+		Some(if buf.len() >= WRITE_BUF_LEN {
+			self.write_out(args.h_file, buf)
+		} else {
+			TRUE
+		})
+	}
+
+	pub(crate) fn flush(&self, handle: HANDLE) -> BOOL {
+		if self.paths.is_empty() {
+			return TRUE;
+		}
+
+		match self.buffers.lock().get_mut(&(handle as usize)) {
+			Some(buf) => self.write_out(handle, buf),
+			None => TRUE
+		}
+	}
+
+	pub(crate) fn close(&self, handle: HANDLE) {
+		if self.paths.is_empty() {
+			return;
+		}
+
+		if let Some(mut buf) = self.buffers.lock().remove(&(handle as usize)) {
+			self.write_out(handle, &mut buf);
+		}
+	}
+
+	pub(crate) fn flush_all(&self) {
+		for (&handle, buf) in self.buffers.lock().iter_mut() {
+			self.write_out(handle as HANDLE, buf);
+		}
+	}
+
+	fn write_out(&self, handle: HANDLE, buf: &mut Vec<u8>) -> BOOL {
+		let write_file = *self.write_file.get().unwrap();
+		let buf = mem::take(buf);
+		let mut offset = 0;
+
+		while offset < buf.len() {
+			let mut written = 0;
+
+			let b = unsafe {
+				write_file(
+					handle,
+					buf[offset..].as_ptr() as *const _,
+					(buf.len() - offset) as _,
+					&mut written,
+					ptr::null_mut()
+				)
+			};
+
+			if b == FALSE || written == 0 {
+				return FALSE;
+			}
+
+			offset += written as usize;
+		}
+
+		TRUE
+	}
+}
+
+fn final_path(handle: HANDLE) -> Option<PathBuf> {
+	let mut buf = vec![0; 260];
+
+	loop {
+		let len = unsafe { GetFinalPathNameByHandleW(handle, buf.as_mut_ptr(), buf.len() as _, 0) }
+			as usize;
+
+		if len == 0 {
+			return None;
+		}
+
+		if len < buf.len() {
+			return Some(OsString::from_wide(&buf[..len]).into());
+		}
+
+		buf.resize(len, 0);
+	}
+}
+
+fn lowercase(path: &Path) -> PathBuf {
+	path.to_string_lossy().to_lowercase().into()
+}